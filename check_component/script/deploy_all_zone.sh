@@ -11,6 +11,7 @@ do
   rsync -avz ../src/archive/base-endpoint.json "mbr-verify-$ZN:~/base-endpoint.json"
   rsync -avz ../config_check_component.json "mbr-verify-$ZN:~/config_check_component.json"
   rsync -avz ../../scripts/benchmark/massbit.lua "mbr-verify-$ZN:~/massbit.lua"
+  rsync -avz ../../scripts/benchmark/dapi.lua "mbr-verify-$ZN:~/dapi.lua"
   rsync -avz ../../scripts/benchmark/wrk "mbr-verify-$ZN:~/wrk"
   rsync -avz ../.env "mbr-verify-$ZN:~/.env"
 
@@ -4,6 +4,7 @@ sudo mv ~/base-endpoint.json /opt/verification/
 sudo mv ~/config_check_component.json /opt/verification/
 sudo mv ~/run.sh /opt/verification/
 sudo mv ~/massbit.lua /opt/verification/
+sudo mv ~/dapi.lua /opt/verification/
 sudo mv ~/wrk /opt/verification/
 sudo mv ~/.env /opt/verification/
 
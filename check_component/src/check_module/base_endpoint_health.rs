@@ -0,0 +1,217 @@
+use crate::check_module::check_module::{BlockChainType, CheckComponent, EndpointInfo};
+use crate::check_module::util::lock;
+use crate::CONFIG;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// An endpoint is flagged as broken when it failed or disagreed in more than half of
+// at least this number of queries
+const MIN_QUERIES_TO_FLAG: u64 = 5;
+// Weight of the latest answer in the average latency
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// Base endpoints of a chain network could not give an answer, so components
+/// of the chain cannot be judged. Added as context of the error of the base node call.
+#[derive(Debug)]
+pub struct ReferenceUnavailable {
+    pub blockchain: String,
+    pub network: String,
+}
+
+impl fmt::Display for ReferenceUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.network.is_empty() {
+            true => write!(f, "reference endpoints of {} unavailable", self.blockchain),
+            false => write!(
+                f,
+                "reference endpoints of {} {} unavailable",
+                self.blockchain, self.network
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReferenceUnavailable {}
+
+/// Answers of base endpoints, used to try healthy endpoints first, to disable failing ones
+/// for a while and to flag broken entries of the base-endpoint file.
+#[derive(Debug, Default)]
+pub struct BaseEndpointTracker {
+    stats: HashMap<String, EndpointStats>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct EndpointStats {
+    pub queried: u64,
+    pub failed: u64,
+    pub disagreed: u64,
+    // Average latency of answers, recent answers weigh more
+    pub latency_ms: Option<f64>,
+    pub consecutive_failures: u64,
+    pub last_error: Option<String>,
+    #[serde(skip)]
+    disabled_until: Option<Instant>,
+}
+
+impl EndpointStats {
+    pub fn is_broken(&self) -> bool {
+        self.queried >= MIN_QUERIES_TO_FLAG && (self.failed + self.disagreed) * 2 > self.queried
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_until
+            .map(|disabled_until| disabled_until > Instant::now())
+            .unwrap_or_default()
+    }
+
+    pub fn success_percent(&self) -> Option<f64> {
+        match self.queried {
+            0 => None,
+            queried => Some((queried - self.failed) as f64 * 100.0 / queried as f64),
+        }
+    }
+}
+
+/// State of a base endpoint as exposed by the check component server.
+#[derive(Clone, Debug, Serialize)]
+pub struct EndpointState {
+    pub url: String,
+    #[serde(flatten)]
+    pub stats: EndpointStats,
+    pub success_percent: Option<f64>,
+    pub broken: bool,
+    pub disabled: bool,
+    // Remaining time the endpoint is skipped
+    pub disabled_for_ms: u64,
+}
+
+impl BaseEndpointTracker {
+    /// Record a call, returns true if the endpoint just got disabled.
+    pub fn record_call(&mut self, url: &str, answer: Result<Duration, String>) -> bool {
+        let stats = self.stats.entry(url.to_string()).or_default();
+        stats.queried += 1;
+        match answer {
+            Ok(latency) => {
+                let latency_ms = latency.as_secs_f64() * 1000.0;
+                stats.latency_ms = Some(match stats.latency_ms {
+                    Some(average) => average + (latency_ms - average) * LATENCY_WEIGHT,
+                    None => latency_ms,
+                });
+                stats.consecutive_failures = 0;
+                stats.disabled_until = None;
+                false
+            }
+            Err(err) => {
+                stats.failed += 1;
+                stats.consecutive_failures += 1;
+                stats.last_error = Some(err);
+                // A re-enabled endpoint is disabled again by its next failure
+                let max_failures = CONFIG.base_endpoint_max_failures.max(1);
+                if stats.consecutive_failures >= max_failures && !stats.is_disabled() {
+                    stats.disabled_until = Some(
+                        Instant::now() + Duration::from_millis(CONFIG.base_endpoint_disable_ms),
+                    );
+                    return true;
+                }
+                false
+            }
+        }
+    }
+
+    /// Record an answer which disagreed with other base endpoints,
+    /// returns true if the endpoint just became broken.
+    pub fn record_disagreement(&mut self, url: &str) -> bool {
+        let stats = self.stats.entry(url.to_string()).or_default();
+        let was_broken = stats.is_broken();
+        stats.disagreed += 1;
+        !was_broken && stats.is_broken()
+    }
+
+    /// Endpoints in the order they should be tried: endpoints without recent failures by
+    /// average latency, then failing ones. Disabled endpoints are skipped unless all are
+    /// disabled. Endpoints with the same health keep the order of the base-endpoint file.
+    pub fn order<'a>(&self, endpoints: &'a [EndpointInfo]) -> Vec<&'a EndpointInfo> {
+        let stats = |endpoint: &EndpointInfo| self.stats.get(&endpoint.url);
+        let mut ordered = endpoints
+            .iter()
+            .filter(|endpoint| !stats(endpoint).map(|s| s.is_disabled()).unwrap_or_default())
+            .collect::<Vec<_>>();
+        if ordered.is_empty() {
+            ordered = endpoints.iter().collect();
+        }
+        ordered.sort_by(|first, second| {
+            let key = |endpoint: &EndpointInfo| match stats(endpoint) {
+                Some(stats) => (
+                    stats.is_disabled(),
+                    stats.consecutive_failures > 0,
+                    stats.latency_ms.unwrap_or_default(),
+                ),
+                // Not queried yet, try it to learn its latency
+                None => (false, false, 0.0),
+            };
+            key(first)
+                .partial_cmp(&key(second))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        ordered
+    }
+
+    pub fn broken_endpoints(&self) -> Vec<(String, EndpointStats)> {
+        let mut endpoints = self
+            .stats
+            .iter()
+            .filter(|(_, stats)| stats.is_broken())
+            .map(|(url, stats)| (url.clone(), stats.clone()))
+            .collect::<Vec<_>>();
+        endpoints.sort_by(|(first, _), (second, _)| first.cmp(second));
+        endpoints
+    }
+
+    /// State of the endpoints of each base-endpoint key.
+    pub fn state(
+        &self,
+        base_nodes: &HashMap<BlockChainType, Vec<EndpointInfo>>,
+    ) -> BTreeMap<BlockChainType, Vec<EndpointState>> {
+        let now = Instant::now();
+        base_nodes
+            .iter()
+            .map(|(chain, endpoints)| {
+                let states = endpoints
+                    .iter()
+                    .map(|endpoint| {
+                        let stats = self.stats.get(&endpoint.url).cloned().unwrap_or_default();
+                        let disabled_for_ms = stats
+                            .disabled_until
+                            .map(|disabled_until| {
+                                disabled_until.saturating_duration_since(now).as_millis() as u64
+                            })
+                            .unwrap_or_default();
+                        EndpointState {
+                            url: endpoint.url.clone(),
+                            success_percent: stats.success_percent(),
+                            broken: stats.is_broken(),
+                            disabled: disabled_for_ms > 0,
+                            disabled_for_ms,
+                            stats,
+                        }
+                    })
+                    .collect();
+                (chain.clone(), states)
+            })
+            .collect()
+    }
+}
+
+impl CheckComponent {
+    /// State of the base endpoints of the latest reloaded base-endpoint file.
+    pub fn base_endpoint_state(&self) -> BTreeMap<BlockChainType, Vec<EndpointState>> {
+        let base_nodes = match &self.flow_config {
+            Some(receiver) => receiver.borrow().base_nodes.clone(),
+            None => self.base_nodes.clone(),
+        };
+        let tracker = lock(&self.base_endpoint_tracker);
+        tracker.state(&base_nodes)
+    }
+}
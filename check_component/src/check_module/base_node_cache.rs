@@ -0,0 +1,131 @@
+use crate::check_module::check_module::{
+    ActionCall, ActionResponse, ComponentInfo, RenderedRequest,
+};
+use crate::check_module::return_field::ReturnField;
+use crate::check_module::util::lock;
+use crate::check_module::ws_action::ActionWebSocket;
+use crate::CONFIG;
+use log::debug;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+
+/// Base node call of a step: chain, network and rendered request.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BaseNodeCacheKey {
+    blockchain: String,
+    network: String,
+    return_name: String,
+    request: RenderedRequest,
+    // Method, return fields and consensus, steps may extract different fields of the same request
+    action: String,
+}
+
+impl BaseNodeCacheKey {
+    /// None if the answer must not be shared, e.g. the step measures connections on purpose.
+    pub fn new(
+        component: &ComponentInfo,
+        action: &ActionCall,
+        return_name: &String,
+        request: &RenderedRequest,
+    ) -> Option<Self> {
+        if action.cold_connection || action.measure_connection {
+            return None;
+        }
+        Some(BaseNodeCacheKey {
+            blockchain: component.blockchain.clone(),
+            network: component.network.clone(),
+            return_name: return_name.clone(),
+            request: request.clone(),
+            action: format!(
+                "{:?} {:?} {:?}",
+                action.request_type,
+                sorted_return_fields(&action.return_fields),
+                action.consensus
+            ),
+        })
+    }
+
+    /// Key of a websocket call or subscription, rendered like a http request without query.
+    pub fn new_ws(
+        component: &ComponentInfo,
+        action: &ActionWebSocket,
+        is_subscribe: bool,
+        return_name: &str,
+        request: &RenderedRequest,
+    ) -> Option<Self> {
+        Some(BaseNodeCacheKey {
+            blockchain: component.blockchain.clone(),
+            network: component.network.clone(),
+            return_name: return_name.to_string(),
+            request: request.clone(),
+            action: format!(
+                "ws {} {} {:?}",
+                is_subscribe,
+                action.notification_number,
+                sorted_return_fields(&action.return_fields)
+            ),
+        })
+    }
+}
+
+fn sorted_return_fields(return_fields: &HashMap<String, ReturnField>) -> Vec<String> {
+    let mut return_fields = return_fields
+        .iter()
+        .map(|(name, field)| format!("{}={:?}", name, field))
+        .collect::<Vec<String>>();
+    return_fields.sort();
+    return_fields
+}
+
+/// Answers of base nodes shared by checks of components on the same chain for
+/// `base_node_cache_ttl_ms`, so that N components of a check cycle cost one base node call
+/// and compare against the same reference block. Concurrent checks wait for the first call.
+/// Failed calls are not cached. Clones share the cache.
+#[derive(Clone, Debug, Default)]
+pub struct BaseNodeCache {
+    entries: Arc<Mutex<HashMap<BaseNodeCacheKey, CacheEntry>>>,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    created: Instant,
+    answer: Arc<OnceCell<ActionResponse>>,
+}
+
+impl BaseNodeCache {
+    /// Cached answer of the key, otherwise the answer of `call`.
+    pub async fn get_or_call<F, Fut>(
+        &self,
+        key: Option<BaseNodeCacheKey>,
+        call: F,
+    ) -> Result<ActionResponse, anyhow::Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<ActionResponse, anyhow::Error>>,
+    {
+        let ttl = Duration::from_millis(CONFIG.base_node_cache_ttl_ms);
+        let key = match key {
+            Some(key) if !ttl.is_zero() => key,
+            _ => return call().await,
+        };
+        let answer = {
+            let mut entries = lock(&self.entries);
+            entries.retain(|_, entry| entry.created.elapsed() < ttl);
+            entries
+                .entry(key)
+                .or_insert_with(|| CacheEntry {
+                    created: Instant::now(),
+                    answer: Default::default(),
+                })
+                .answer
+                .clone()
+        };
+        if let Some(answer) = answer.get() {
+            debug!("use cached base node answer: {:?}", answer);
+        }
+        answer.get_or_try_init(call).await.map(Clone::clone)
+    }
+}
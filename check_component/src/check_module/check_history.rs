@@ -0,0 +1,714 @@
+use crate::check_module::check_module::{CheckMkReport, ComponentInfo, StepOutcome};
+use crate::check_module::report_outbox::process_name;
+use crate::check_module::util::{lock, now_ms};
+use crate::CONFIG;
+use anyhow::Error;
+use log::{info, warn};
+use rusqlite::{params, Connection, Row};
+use serde::Serialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use wrap_wrk::WrkReport;
+
+// Writers of other processes on the host are waited for at most this time
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS check_runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    checked_at_ms INTEGER NOT NULL,
+    checker TEXT NOT NULL,
+    component_id TEXT NOT NULL,
+    component_type TEXT NOT NULL,
+    blockchain TEXT NOT NULL,
+    network TEXT NOT NULL,
+    zone TEXT NOT NULL,
+    ip TEXT NOT NULL,
+    flow TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    ok INTEGER NOT NULL,
+    reference_failure INTEGER NOT NULL,
+    status_detail TEXT NOT NULL,
+    metrics TEXT NOT NULL,
+    benchmark_requests INTEGER,
+    benchmark_requests_per_second REAL,
+    benchmark_success_percent INTEGER,
+    benchmark_latency_p90_ms REAL,
+    benchmark_latency_p95_ms REAL,
+    benchmark_latency_p99_ms REAL
+);
+CREATE INDEX IF NOT EXISTS check_runs_component ON check_runs (component_id, checked_at_ms);
+CREATE INDEX IF NOT EXISTS check_runs_checked_at ON check_runs (checked_at_ms);
+CREATE TABLE IF NOT EXISTS check_steps (
+    run_id INTEGER NOT NULL REFERENCES check_runs (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    success INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    attempts INTEGER NOT NULL,
+    response_time_ms INTEGER,
+    message TEXT NOT NULL,
+    PRIMARY KEY (run_id, position)
+);
+";
+
+const RUN_COLUMNS: &str = "id, checked_at_ms, checker, component_id, component_type, blockchain, \
+    network, zone, ip, flow, status, ok, reference_failure, status_detail, metrics, \
+    benchmark_requests, benchmark_requests_per_second, benchmark_success_percent, \
+    benchmark_latency_p90_ms, benchmark_latency_p95_ms, benchmark_latency_p99_ms";
+
+// Judged runs failing, the start of a failing streak is the first of them after the last ok run
+const FAILING_COMPONENTS: &str = "
+WITH last_ok AS (
+    SELECT component_id, MAX(checked_at_ms) AS checked_at_ms FROM check_runs
+    WHERE reference_failure = 0 AND ok = 1 GROUP BY component_id
+)
+SELECT run.component_id, run.component_type, run.blockchain, run.network,
+    MIN(run.checked_at_ms), MAX(run.checked_at_ms), COUNT(*)
+FROM check_runs run LEFT JOIN last_ok ON last_ok.component_id = run.component_id
+WHERE run.reference_failure = 0 AND run.ok = 0
+    AND run.checked_at_ms > COALESCE(last_ok.checked_at_ms, -1)
+    AND (?1 IS NULL OR run.component_id = ?1)
+GROUP BY run.component_id
+ORDER BY MIN(run.checked_at_ms)
+";
+
+/// Benchmark of a check run, if the logic checks passed and the benchmark ran.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct BenchmarkSummary {
+    pub requests: u64,
+    pub requests_per_second: f64,
+    pub success_percent: Option<u32>,
+    pub latency_p90_ms: f64,
+    pub latency_p95_ms: f64,
+    pub latency_p99_ms: f64,
+}
+
+impl From<&WrkReport> for BenchmarkSummary {
+    fn from(report: &WrkReport) -> Self {
+        BenchmarkSummary {
+            requests: report.total_req as u64,
+            requests_per_second: report.req_per_sec as f64,
+            success_percent: report.get_success_percent(),
+            latency_p90_ms: report.histogram_90 as f64,
+            latency_p95_ms: report.histogram_95 as f64,
+            latency_p99_ms: report.histogram_99 as f64,
+        }
+    }
+}
+
+/// Check of a component, as kept in the history.
+#[derive(Clone, Debug, Serialize)]
+pub struct CheckRun {
+    pub id: i64,
+    pub checked_at_ms: u64,
+    // Process which checked the component, e.g. `mbr-fisherman`
+    pub checker: String,
+    pub component_id: String,
+    pub component_type: String,
+    pub blockchain: String,
+    pub network: String,
+    pub zone: String,
+    pub ip: String,
+    // Tasks of the check flow, e.g. `checking_chain_type,checking_chain_sync`
+    pub flow: String,
+    pub status: u8,
+    // Component is ok as counted by the fisherman
+    pub ok: bool,
+    pub reference_failure: bool,
+    pub status_detail: String,
+    pub metrics: Value,
+    pub benchmark: Option<BenchmarkSummary>,
+    pub steps: Vec<StepOutcome>,
+}
+
+impl CheckRun {
+    pub fn new(
+        component: &ComponentInfo,
+        flow: &str,
+        report: &CheckMkReport,
+        benchmark: Option<&WrkReport>,
+    ) -> Self {
+        CheckRun {
+            id: 0,
+            checked_at_ms: now_ms(),
+            checker: process_name(),
+            component_id: component.id.clone(),
+            component_type: component.component_type.to_string(),
+            blockchain: component.blockchain.clone(),
+            network: component.network.clone(),
+            zone: format!("{:?}", component.zone),
+            ip: component.ip.clone(),
+            flow: flow.to_string(),
+            status: report.status,
+            ok: report.is_component_status_ok(),
+            reference_failure: report.reference_failure,
+            status_detail: report.status_detail.clone(),
+            metrics: serde_json::to_value(&report.metric.metric).unwrap_or_default(),
+            benchmark: benchmark.map(BenchmarkSummary::from),
+            steps: report.steps.clone(),
+        }
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let metrics: String = row.get(14)?;
+        let benchmark_requests: Option<i64> = row.get(15)?;
+        let benchmark = match benchmark_requests {
+            Some(requests) => Some(BenchmarkSummary {
+                requests: requests as u64,
+                requests_per_second: row.get(16)?,
+                success_percent: row.get(17)?,
+                latency_p90_ms: row.get(18)?,
+                latency_p95_ms: row.get(19)?,
+                latency_p99_ms: row.get(20)?,
+            }),
+            None => None,
+        };
+        Ok(CheckRun {
+            id: row.get(0)?,
+            checked_at_ms: row.get::<_, i64>(1)? as u64,
+            checker: row.get(2)?,
+            component_id: row.get(3)?,
+            component_type: row.get(4)?,
+            blockchain: row.get(5)?,
+            network: row.get(6)?,
+            zone: row.get(7)?,
+            ip: row.get(8)?,
+            flow: row.get(9)?,
+            status: row.get(10)?,
+            ok: row.get(11)?,
+            reference_failure: row.get(12)?,
+            status_detail: row.get(13)?,
+            metrics: serde_json::from_str(&metrics).unwrap_or_default(),
+            benchmark,
+            steps: vec![],
+        })
+    }
+}
+
+/// Runs of `CheckHistory::runs`, newest first.
+#[derive(Clone, Debug, Default)]
+pub struct HistoryQuery {
+    pub component_id: Option<String>,
+    pub since_ms: Option<u64>,
+    pub until_ms: Option<u64>,
+    // Judged runs of components which were not ok
+    pub failed_only: bool,
+    // 0 returns all runs
+    pub limit: usize,
+}
+
+/// Component failing since its last ok run.
+#[derive(Clone, Debug, Serialize)]
+pub struct FailingComponent {
+    pub component_id: String,
+    pub component_type: String,
+    pub blockchain: String,
+    pub network: String,
+    // First failed run after the last ok run
+    pub failing_since_ms: u64,
+    pub last_failed_ms: u64,
+    pub failed_runs: u64,
+}
+
+#[derive(Debug, Default)]
+struct HistoryState {
+    connection: Option<Connection>,
+    last_prune: Option<Instant>,
+}
+
+/// Check runs of the fisherman and the check component kept in the SQLite database of
+/// `check_history_path`, shared by the processes on the host. Runs older than
+/// `check_history_max_age_ms` and runs of a component beyond
+/// `check_history_max_runs_per_component` are pruned. Clones share the database connection.
+#[derive(Clone, Debug)]
+pub struct CheckHistory {
+    // No history if none
+    path: Option<PathBuf>,
+    state: Arc<Mutex<HistoryState>>,
+}
+
+impl Default for CheckHistory {
+    fn default() -> Self {
+        match CONFIG.check_history_path.is_empty() {
+            true => CheckHistory::disabled(),
+            false => CheckHistory::new(PathBuf::from(&CONFIG.check_history_path)),
+        }
+    }
+}
+
+impl CheckHistory {
+    /// History in the database at `path`, it is created at the first use.
+    pub fn new(path: PathBuf) -> Self {
+        CheckHistory {
+            path: Some(path),
+            state: Default::default(),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        CheckHistory {
+            path: None,
+            state: Default::default(),
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Keep the check run, a failure is logged and does not fail the check.
+    pub async fn record(&self, run: CheckRun) {
+        if self.path.is_none() {
+            return;
+        }
+        let history = self.clone();
+        let component_id = run.component_id.clone();
+        let recorded = tokio::task::spawn_blocking(move || history.insert(&run))
+            .await
+            .map_err(Error::from)
+            .and_then(|result| result);
+        if let Err(err) = recorded {
+            warn!(
+                "Cannot record check of {} in history: {}",
+                component_id, err
+            );
+        }
+    }
+
+    /// Insert the run and its steps, blocking. Prunes the history every
+    /// `check_history_prune_interval_ms`.
+    pub fn insert(&self, run: &CheckRun) -> Result<i64, Error> {
+        let prune_interval = Duration::from_millis(CONFIG.check_history_prune_interval_ms);
+        self.with_connection(|connection, last_prune| {
+            let transaction = connection.transaction()?;
+            let benchmark = run.benchmark.as_ref();
+            transaction.execute(
+                "INSERT INTO check_runs (checked_at_ms, checker, component_id, component_type, \
+                 blockchain, network, zone, ip, flow, status, ok, reference_failure, status_detail, \
+                 metrics, benchmark_requests, benchmark_requests_per_second, \
+                 benchmark_success_percent, benchmark_latency_p90_ms, benchmark_latency_p95_ms, \
+                 benchmark_latency_p99_ms) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, \
+                 ?17, ?18, ?19, ?20)",
+                params![
+                    run.checked_at_ms as i64,
+                    run.checker,
+                    run.component_id,
+                    run.component_type,
+                    run.blockchain,
+                    run.network,
+                    run.zone,
+                    run.ip,
+                    run.flow,
+                    run.status,
+                    run.ok,
+                    run.reference_failure,
+                    run.status_detail,
+                    run.metrics.to_string(),
+                    benchmark.map(|benchmark| benchmark.requests as i64),
+                    benchmark.map(|benchmark| benchmark.requests_per_second),
+                    benchmark.and_then(|benchmark| benchmark.success_percent),
+                    benchmark.map(|benchmark| benchmark.latency_p90_ms),
+                    benchmark.map(|benchmark| benchmark.latency_p95_ms),
+                    benchmark.map(|benchmark| benchmark.latency_p99_ms),
+                ],
+            )?;
+            let run_id = transaction.last_insert_rowid();
+            for (position, step) in run.steps.iter().enumerate() {
+                transaction.execute(
+                    "INSERT INTO check_steps (run_id, position, name, success, status, attempts, \
+                     response_time_ms, message) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![
+                        run_id,
+                        position as i64,
+                        step.name,
+                        step.success,
+                        step.status,
+                        step.attempts as i64,
+                        step.response_time_ms,
+                        step.message,
+                    ],
+                )?;
+            }
+            transaction.commit()?;
+            if last_prune.map_or(true, |last_prune| last_prune.elapsed() >= prune_interval) {
+                *last_prune = Some(Instant::now());
+                let pruned = Self::prune_connection(
+                    connection,
+                    now_ms(),
+                    CONFIG.check_history_max_age_ms,
+                    CONFIG.check_history_max_runs_per_component,
+                )?;
+                if pruned > 0 {
+                    info!("Pruned {} check runs from history", pruned);
+                }
+            }
+            Ok(run_id)
+        })
+    }
+
+    /// Runs matching the query with their steps, newest first. Blocking.
+    pub fn runs(&self, query: &HistoryQuery) -> Result<Vec<CheckRun>, Error> {
+        self.with_connection(|connection, _| {
+            let mut statement = connection.prepare(&format!(
+                "SELECT {} FROM check_runs \
+                 WHERE (?1 IS NULL OR component_id = ?1) \
+                 AND (?2 IS NULL OR checked_at_ms >= ?2) \
+                 AND (?3 IS NULL OR checked_at_ms < ?3) \
+                 AND (?4 = 0 OR (ok = 0 AND reference_failure = 0)) \
+                 ORDER BY checked_at_ms DESC, id DESC LIMIT ?5",
+                RUN_COLUMNS
+            ))?;
+            let limit = if query.limit == 0 {
+                -1
+            } else {
+                query.limit as i64
+            };
+            let mut runs = statement
+                .query_map(
+                    params![
+                        query.component_id,
+                        query.since_ms.map(|since_ms| since_ms as i64),
+                        query.until_ms.map(|until_ms| until_ms as i64),
+                        query.failed_only,
+                        limit,
+                    ],
+                    CheckRun::from_row,
+                )?
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut steps = connection.prepare(
+                "SELECT name, success, status, attempts, response_time_ms, message \
+                 FROM check_steps WHERE run_id = ?1 ORDER BY position",
+            )?;
+            for run in runs.iter_mut() {
+                run.steps = steps
+                    .query_map(params![run.id], |row| {
+                        Ok(StepOutcome {
+                            name: row.get(0)?,
+                            success: row.get(1)?,
+                            status: row.get(2)?,
+                            attempts: row.get::<_, i64>(3)? as usize,
+                            response_time_ms: row.get(4)?,
+                            message: row.get(5)?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+            }
+            Ok(runs)
+        })
+    }
+
+    /// Components whose latest judged run failed, with the start of the failure, oldest
+    /// failure first. Runs without answers of the base endpoints are ignored. Blocking.
+    pub fn failing_components(
+        &self,
+        component_id: Option<&str>,
+    ) -> Result<Vec<FailingComponent>, Error> {
+        self.with_connection(|connection, _| {
+            let mut statement = connection.prepare(FAILING_COMPONENTS)?;
+            let components = statement
+                .query_map(params![component_id], |row| {
+                    Ok(FailingComponent {
+                        component_id: row.get(0)?,
+                        component_type: row.get(1)?,
+                        blockchain: row.get(2)?,
+                        network: row.get(3)?,
+                        failing_since_ms: row.get::<_, i64>(4)? as u64,
+                        last_failed_ms: row.get::<_, i64>(5)? as u64,
+                        failed_runs: row.get::<_, i64>(6)? as u64,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(components)
+        })
+    }
+
+    /// Time of the first failed run after the last ok run of the component, none if its
+    /// latest judged run is ok. Blocking.
+    pub fn failing_since(&self, component_id: &str) -> Result<Option<u64>, Error> {
+        Ok(self
+            .failing_components(Some(component_id))?
+            .first()
+            .map(|component| component.failing_since_ms))
+    }
+
+    /// Remove runs older than `max_age_ms` and the oldest runs of components with more than
+    /// `max_runs_per_component` runs, 0 keeps them. Returns the number of removed runs. Blocking.
+    pub fn prune(&self, max_age_ms: u64, max_runs_per_component: usize) -> Result<usize, Error> {
+        self.with_connection(|connection, _| {
+            Self::prune_connection(connection, now_ms(), max_age_ms, max_runs_per_component)
+        })
+    }
+
+    fn prune_connection(
+        connection: &Connection,
+        now_ms: u64,
+        max_age_ms: u64,
+        max_runs_per_component: usize,
+    ) -> Result<usize, Error> {
+        let mut pruned = 0;
+        if max_age_ms > 0 {
+            pruned += connection.execute(
+                "DELETE FROM check_runs WHERE checked_at_ms < ?1",
+                params![now_ms.saturating_sub(max_age_ms) as i64],
+            )?;
+        }
+        if max_runs_per_component > 0 {
+            pruned += connection.execute(
+                "DELETE FROM check_runs WHERE id IN (SELECT id FROM (SELECT id, ROW_NUMBER() \
+                 OVER (PARTITION BY component_id ORDER BY checked_at_ms DESC, id DESC) AS number \
+                 FROM check_runs) WHERE number > ?1)",
+                params![max_runs_per_component as i64],
+            )?;
+        }
+        Ok(pruned)
+    }
+
+    // Open the database at the first use
+    fn with_connection<T>(
+        &self,
+        run: impl FnOnce(&mut Connection, &mut Option<Instant>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| Error::msg("check history is disabled"))?;
+        let mut state = lock(&self.state);
+        let state = &mut *state;
+        if state.connection.is_none() {
+            state.connection = Some(Self::open(path)?);
+        }
+        match state.connection.as_mut() {
+            Some(connection) => run(connection, &mut state.last_prune),
+            None => Err(Error::msg("check history is not open")),
+        }
+    }
+
+    fn open(path: &Path) -> Result<Connection, Error> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let connection = Connection::open(path)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        // Readers do not block the checks, steps are removed with their run
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn history() -> CheckHistory {
+        CheckHistory::new(PathBuf::from(":memory:"))
+    }
+
+    fn run(component_id: &str, checked_at_ms: u64, ok: bool, reference_failure: bool) -> CheckRun {
+        CheckRun {
+            id: 0,
+            checked_at_ms,
+            checker: "mbr-fisherman".to_string(),
+            component_id: component_id.to_string(),
+            component_type: "node".to_string(),
+            blockchain: "eth".to_string(),
+            network: "mainnet".to_string(),
+            zone: "AS".to_string(),
+            ip: "127.0.0.1".to_string(),
+            flow: "checking_chain_type".to_string(),
+            status: if ok { 0 } else { 2 },
+            ok,
+            reference_failure,
+            status_detail: String::new(),
+            metrics: json!({"checkCall_response_time_ms": 12}),
+            benchmark: None,
+            steps: ["checkCall", "compareCall"]
+                .iter()
+                .map(|name| StepOutcome {
+                    name: name.to_string(),
+                    success: ok,
+                    status: 0,
+                    attempts: 1,
+                    response_time_ms: Some(12),
+                    message: String::new(),
+                })
+                .collect(),
+        }
+    }
+
+    fn count(history: &CheckHistory, table: &str) -> i64 {
+        history
+            .with_connection(|connection, _| {
+                Ok(
+                    connection.query_row(
+                        &format!("SELECT COUNT(*) FROM {}", table),
+                        [],
+                        |row| row.get(0),
+                    )?,
+                )
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn inserted_runs_are_queried_newest_first() {
+        let history = history();
+        let start = now_ms() - 10000;
+        let mut benchmarked = run("a", start + 2000, true, false);
+        benchmarked.benchmark = Some(BenchmarkSummary {
+            requests: 100,
+            requests_per_second: 50.0,
+            success_percent: Some(99),
+            latency_p90_ms: 10.0,
+            latency_p95_ms: 20.0,
+            latency_p99_ms: 30.0,
+        });
+        history.insert(&run("a", start, true, false)).unwrap();
+        history
+            .insert(&run("a", start + 1000, false, false))
+            .unwrap();
+        history.insert(&benchmarked).unwrap();
+        history.insert(&run("b", start, false, true)).unwrap();
+
+        let runs = history
+            .runs(&HistoryQuery {
+                component_id: Some("a".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        let times: Vec<_> = runs.iter().map(|run| run.checked_at_ms).collect();
+        assert_eq!(times, vec![start + 2000, start + 1000, start]);
+        assert_eq!(
+            runs[0].benchmark.as_ref().unwrap().success_percent,
+            Some(99)
+        );
+        assert!(runs[1].benchmark.is_none());
+        assert_eq!(runs[0].metrics, json!({"checkCall_response_time_ms": 12}));
+        let steps: Vec<_> = runs[0]
+            .steps
+            .iter()
+            .map(|step| step.name.as_str())
+            .collect();
+        assert_eq!(steps, vec!["checkCall", "compareCall"]);
+
+        let query = |query: HistoryQuery| history.runs(&query).unwrap().len();
+        assert_eq!(query(Default::default()), 4);
+        assert_eq!(
+            query(HistoryQuery {
+                limit: 2,
+                ..Default::default()
+            }),
+            2
+        );
+        // Reference failures are not judged
+        assert_eq!(
+            query(HistoryQuery {
+                failed_only: true,
+                ..Default::default()
+            }),
+            1
+        );
+        assert_eq!(
+            query(HistoryQuery {
+                since_ms: Some(start + 1000),
+                until_ms: Some(start + 2000),
+                ..Default::default()
+            }),
+            1
+        );
+    }
+
+    #[test]
+    fn failing_components_start_after_last_ok_run() {
+        let history = history();
+        let start = now_ms() - 10000;
+        for (component_id, offset, ok, reference_failure) in [
+            ("a", 1, false, false),
+            ("a", 2, true, false),
+            ("a", 3, false, false),
+            ("a", 4, true, true),
+            ("a", 5, false, false),
+            ("b", 1, false, false),
+            ("b", 2, true, false),
+            ("c", 1, false, false),
+            ("c", 2, false, false),
+        ] {
+            history
+                .insert(&run(component_id, start + offset, ok, reference_failure))
+                .unwrap();
+        }
+        let failing = history.failing_components(None).unwrap();
+        let failing: Vec<_> = failing
+            .iter()
+            .map(|component| {
+                (
+                    component.component_id.as_str(),
+                    component.failing_since_ms - start,
+                    component.last_failed_ms - start,
+                    component.failed_runs,
+                )
+            })
+            .collect();
+        assert_eq!(failing, vec![("c", 1, 2, 2), ("a", 3, 5, 2)]);
+        assert_eq!(history.failing_since("a").unwrap(), Some(start + 3));
+        assert_eq!(history.failing_since("b").unwrap(), None);
+    }
+
+    #[test]
+    fn prune_removes_old_and_extra_runs_with_steps() {
+        let history = history();
+        let start = now_ms() - 10000;
+        for offset in [1, 2, 3, 4] {
+            history
+                .insert(&run("a", start + offset, true, false))
+                .unwrap();
+        }
+        // Same time, the latest insert is kept
+        history.insert(&run("b", start + 4, true, false)).unwrap();
+        history.insert(&run("b", start + 4, true, false)).unwrap();
+        history
+            .insert(&run("c", start - 5000, true, false))
+            .unwrap();
+        assert_eq!(count(&history, "check_steps"), 14);
+
+        let pruned = history
+            .with_connection(|connection, _| {
+                CheckHistory::prune_connection(connection, start + 10, 1000, 2)
+            })
+            .unwrap();
+        // c is too old, a keeps 2 runs, b keeps both
+        assert_eq!(pruned, 3);
+        let runs = history.runs(&Default::default()).unwrap();
+        let runs: Vec<_> = runs
+            .iter()
+            .map(|run| (run.component_id.as_str(), run.checked_at_ms - start))
+            .collect();
+        assert_eq!(runs, vec![("b", 4), ("b", 4), ("a", 4), ("a", 3)]);
+        // Steps are removed with their runs
+        assert_eq!(count(&history, "check_steps"), 8);
+
+        let pruned = history
+            .with_connection(|connection, _| {
+                CheckHistory::prune_connection(connection, start + 10, 0, 1)
+            })
+            .unwrap();
+        assert_eq!(pruned, 2);
+        let ids: Vec<_> = history
+            .runs(&HistoryQuery {
+                component_id: Some("b".to_string()),
+                ..Default::default()
+            })
+            .unwrap()
+            .iter()
+            .map(|run| run.id)
+            .collect();
+        assert_eq!(ids, vec![6]);
+        assert_eq!(count(&history, "check_steps"), 4);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::check_module::check_module::BlockChainType;
+use crate::check_module::util::lock;
+use crate::CONFIG;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Limits of concurrent checks, shared by clones of the check component.
+/// A component takes the permit of its chain before a global slot, so that components waiting
+/// for a busy chain do not hold slots of other chains.
+#[derive(Clone, Debug)]
+pub struct CheckLimits {
+    checks: Arc<Semaphore>,
+    chains: Arc<Mutex<HashMap<BlockChainType, Arc<Semaphore>>>>,
+    per_chain: usize,
+    benchmark: Arc<Semaphore>,
+}
+
+impl Default for CheckLimits {
+    fn default() -> Self {
+        CheckLimits::new(
+            CONFIG.check_concurrency,
+            CONFIG.check_concurrency_per_chain,
+            CONFIG.benchmark_concurrency,
+        )
+    }
+}
+
+impl CheckLimits {
+    pub fn new(checks: usize, per_chain: usize, benchmarks: usize) -> Self {
+        CheckLimits {
+            checks: Arc::new(Semaphore::new(checks.max(1))),
+            chains: Default::default(),
+            per_chain: per_chain.max(1),
+            benchmark: Arc::new(Semaphore::new(benchmarks.max(1))),
+        }
+    }
+
+    /// Wait until a component of the chain can be checked.
+    pub async fn acquire_chain(&self, blockchain: &BlockChainType) -> OwnedSemaphorePermit {
+        let semaphore = {
+            let mut chains = lock(&self.chains);
+            chains
+                .entry(blockchain.clone())
+                .or_insert_with(|| Arc::new(Semaphore::new(self.per_chain)))
+                .clone()
+        };
+        // The semaphore is never closed
+        semaphore.acquire_owned().await.unwrap()
+    }
+
+    /// Wait for a global slot, taken after the permit of the chain.
+    pub async fn acquire_check(&self) -> OwnedSemaphorePermit {
+        self.checks.clone().acquire_owned().await.unwrap()
+    }
+
+    /// Wait until a benchmark can run, benchmarks load the checker much more than logic checks.
+    pub async fn acquire_benchmark(&self) -> OwnedSemaphorePermit {
+        self.benchmark.clone().acquire_owned().await.unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn busy_chain_does_not_hold_global_slots() {
+        let limits = CheckLimits::new(2, 1, 1);
+        let eth = "eth".to_string();
+        let _eth_check = (
+            limits.acquire_chain(&eth).await,
+            limits.acquire_check().await,
+        );
+        // Another eth component waits for its chain without a global slot
+        let waiting = tokio::spawn({
+            let limits = limits.clone();
+            let eth = eth.clone();
+            async move {
+                let _chain = limits.acquire_chain(&eth).await;
+                limits.acquire_check().await
+            }
+        });
+        tokio::task::yield_now().await;
+        let dot = "dot".to_string();
+        let dot_check = timeout(Duration::from_secs(1), async {
+            let chain = limits.acquire_chain(&dot).await;
+            (chain, limits.acquire_check().await)
+        })
+        .await;
+        assert!(dot_check.is_ok());
+        // Both global slots are taken
+        assert!(timeout(Duration::from_millis(50), limits.acquire_check())
+            .await
+            .is_err());
+        waiting.abort();
+    }
+}
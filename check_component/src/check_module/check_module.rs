@@ -1,11 +1,10 @@
-use futures::pin_mut;
+use futures::{pin_mut, stream, StreamExt};
 use futures_util::future::join_all;
-use minifier::json::minify;
 
 use serde::{Deserialize, Serialize};
 
-use handlebars::Handlebars;
 use log::{debug, info, warn};
+use regex::Regex;
 use serde_json::Value;
 
 use std::collections::HashMap;
@@ -15,23 +14,49 @@ use std::io::{BufRead, BufReader};
 
 use anyhow::Error;
 use reqwest::RequestBuilder;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use std::{thread, usize};
+use tokio::sync::watch;
 
+use crate::check_module::base_endpoint_health::{BaseEndpointTracker, ReferenceUnavailable};
+use crate::check_module::base_node_cache::{BaseNodeCache, BaseNodeCacheKey};
+use crate::check_module::check_history::{CheckHistory, CheckRun};
+use crate::check_module::check_limits::CheckLimits;
 use crate::check_module::check_module::CheckMkStatus::{Unknown, Warning};
 use crate::check_module::check_module::ComponentType::Gateway;
+use crate::check_module::consensus::Consensus;
+use crate::check_module::flow_reloader::{
+    load_source, parse_base_endpoint_document, parse_check_flow_document, FlowConfig,
+};
+use crate::check_module::flow_source::FlowSource;
+use crate::check_module::flow_validator::{validate_base_endpoints, FlowError};
+use crate::check_module::http_client::{ConnectionTiming, HttpClient, HANDSHAKE_TIME_KEY};
+use crate::check_module::prometheus_metrics::METRICS;
+use crate::check_module::report_signature::ReportSigner;
+use crate::check_module::report_sink::{ReportEvent, ReportSinks};
+use crate::check_module::retry::{HttpStatusError, RetryPolicy};
+use crate::check_module::return_field::ReturnField;
+use crate::check_module::step_graph::step_stages;
+use crate::check_module::step_value::StepValue;
 use crate::check_module::store_report::ReportType::ReportProvider;
 use crate::check_module::store_report::{ReportType, ReporterRole, SendPurpose, StoreReport};
-use crate::{BASE_ENDPOINT_JSON, BENCHMARK_WRK_PATH, CONFIG, LOCAL_IP, PORTAL_AUTHORIZATION};
+use crate::check_module::template_helper::HANDLEBARS;
+use crate::check_module::util::lock;
+use crate::check_module::ws_action::{ActionWebSocket, CONNECT_TIME_KEY};
+use crate::{BENCHMARK_WRK_PATH, CONFIG, LOCAL_IP, PORTAL_AUTHORIZATION};
+use std::cmp::Ordering;
 use std::str::FromStr;
 use strum_macros::EnumString;
 use warp::{Rejection, Reply};
 pub use wrap_wrk::{WrkBenchmark, WrkReport};
 
-type BlockChainType = String;
+pub(crate) type BlockChainType = String;
 type UrlType = String;
-type TaskType = String;
-type StepResult = HashMap<String, String>;
+pub(crate) type TaskType = String;
+// Http status code of call action is added to step result with this key
+pub const STATUS_CODE_KEY: &str = "status_code";
+pub type StepResult = HashMap<String, StepValue>;
 type ComponentId = String;
 
 #[derive(Debug, PartialEq, Clone, Deserialize, Serialize, Hash, Eq)]
@@ -75,27 +100,208 @@ impl Default for Zone {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "action_type", rename_all = "snake_case")]
+pub enum CheckAction {
+    Call(ActionCall),
+    Compare(ActionCompare),
+    WsCall(ActionWebSocket),
+    WsSubscribe(ActionWebSocket),
+    Set(ActionSet),
+}
+
+impl CheckAction {
+    pub fn is_base_node(&self) -> bool {
+        match self {
+            CheckAction::Call(action) => action.is_base_node,
+            CheckAction::WsCall(action) | CheckAction::WsSubscribe(action) => action.is_base_node,
+            CheckAction::Compare(_) | CheckAction::Set(_) => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RequestType {
+    Get,
+    Post,
+    Put,
+}
+
+impl Default for RequestType {
+    fn default() -> Self {
+        RequestType::Post
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct ActionCall {
-    action_type: String,
-    is_base_node: bool,
-    request_type: String,
-    header: HashMap<String, String>,
-    body: String,
+    pub(crate) is_base_node: bool,
+    #[serde(default)]
+    pub(crate) request_type: RequestType,
+    // Path appended to component or base endpoint url, e.g. `/ping`
+    #[serde(default)]
+    pub(crate) path: String,
+    // Header and query values are rendered like body
+    #[serde(default)]
+    pub(crate) header: HashMap<String, String>,
+    #[serde(default)]
+    pub(crate) query: HashMap<String, String>,
+    #[serde(default)]
+    pub(crate) body: String,
     time_out: usize,
-    return_fields: HashMap<String, String>,
+    #[serde(default)]
+    pub(crate) return_fields: HashMap<String, ReturnField>,
+    // Base node only: combine answers of several base endpoints instead of using the first one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) consensus: Option<Consensus>,
+    // Add `connect_time_ms` and `handshake_time_ms` measured on a separate connection
+    #[serde(default)]
+    pub(crate) measure_connection: bool,
+    // Send the request on a new connection, so that response time includes connect and handshake
+    #[serde(default)]
+    pub(crate) cold_connection: bool,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+impl ActionCall {
+    /// All templates of the request, they may reference results of previous steps.
+    pub fn templates(&self) -> Vec<&String> {
+        let mut templates = vec![&self.path, &self.body];
+        templates.extend(self.header.values());
+        templates.extend(self.query.values());
+        templates
+    }
+}
+
+/// Path, query, header and body of a call action rendered with results of previous steps.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RenderedRequest {
+    pub(crate) path: String,
+    // Sorted by key
+    pub(crate) query: Vec<(String, String)>,
+    pub(crate) header: Vec<(String, String)>,
+    pub(crate) body: String,
+}
+
+impl RenderedRequest {
+    pub fn render(action: &ActionCall, step_result: &StepResult) -> Result<Self, anyhow::Error> {
+        let render_all = |templates: &HashMap<String, String>| {
+            let mut rendered = Vec::new();
+            for (key, value) in templates.iter() {
+                rendered.push((
+                    key.clone(),
+                    CheckComponent::replace_string(value.clone(), step_result)?,
+                ));
+            }
+            rendered.sort();
+            Ok::<_, anyhow::Error>(rendered)
+        };
+        Ok(RenderedRequest {
+            path: CheckComponent::replace_string(action.path.clone(), step_result)?,
+            query: render_all(&action.query)?,
+            header: render_all(&action.header)?,
+            // Replace body for transport result of previous step
+            body: CheckComponent::replace_string(action.body.clone(), step_result)?,
+        })
+    }
+}
+
+/// Values rendered once per flow run and stored as `<return_name>_<name>`, so that random
+/// helpers give the same value to every later step, e.g. base and check call of one block.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActionSet {
+    pub(crate) vars: HashMap<String, String>,
+}
+
+impl ActionSet {
+    pub fn templates(&self) -> Vec<&String> {
+        self.vars.values().collect()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct ActionCompare {
-    action_type: String,
-    operator_items: OperatorCompare,
+    pub(crate) operator_items: OperatorCompare,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, Default)]
-pub struct OperatorCompare {
-    operator_type: String,
-    params: Value,
+// Items are step result keys or literals prefixed by `#`
+type CompareItems = Vec<String>;
+type CompareItemPair = [String; 2];
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "operator_type", content = "params", rename_all = "lowercase")]
+pub enum OperatorCompare {
+    And(Vec<OperatorCompare>),
+    Or(Vec<OperatorCompare>),
+    Not(NotOperand),
+    Eq(CompareItems),
+    Neq(CompareItems),
+    Gt(CompareItemPair),
+    Gte(CompareItemPair),
+    Lt(CompareItemPair),
+    Lte(CompareItemPair),
+    Regex(CompareItemPair),
+    Contains(CompareItemPair),
+    // [first, second, tolerance]
+    Within([String; 3]),
+}
+
+// `not` accepts both a single operator and a list with one operator
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum NotOperand {
+    Single(Box<OperatorCompare>),
+    List([Box<OperatorCompare>; 1]),
+}
+
+impl NotOperand {
+    pub fn operator(&self) -> &OperatorCompare {
+        match self {
+            NotOperand::Single(operator) => operator,
+            NotOperand::List([operator]) => operator,
+        }
+    }
+}
+
+impl OperatorCompare {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OperatorCompare::And(_) => "and",
+            OperatorCompare::Or(_) => "or",
+            OperatorCompare::Not(_) => "not",
+            OperatorCompare::Eq(_) => "eq",
+            OperatorCompare::Neq(_) => "neq",
+            OperatorCompare::Gt(_) => "gt",
+            OperatorCompare::Gte(_) => "gte",
+            OperatorCompare::Lt(_) => "lt",
+            OperatorCompare::Lte(_) => "lte",
+            OperatorCompare::Regex(_) => "regex",
+            OperatorCompare::Contains(_) => "contains",
+            OperatorCompare::Within(_) => "within",
+        }
+    }
+
+    /// All items (keys and `#` literals) used by this operator and its sub operators.
+    pub fn items(&self) -> Vec<&String> {
+        match self {
+            OperatorCompare::And(operators) | OperatorCompare::Or(operators) => operators
+                .iter()
+                .flat_map(|operator| operator.items())
+                .collect(),
+            OperatorCompare::Not(operand) => operand.operator().items(),
+            OperatorCompare::Eq(items) | OperatorCompare::Neq(items) => items.iter().collect(),
+            OperatorCompare::Gt(items)
+            | OperatorCompare::Gte(items)
+            | OperatorCompare::Lt(items)
+            | OperatorCompare::Lte(items)
+            | OperatorCompare::Regex(items)
+            | OperatorCompare::Contains(items) => items.iter().collect(),
+            OperatorCompare::Within(items) => items.iter().collect(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Default, Hash, PartialEq, Eq)]
@@ -129,8 +335,14 @@ pub struct UserInfo {
 }
 
 impl ComponentInfo {
-    fn get_url(&self) -> UrlType {
-        format!("https://{}", self.ip)
+    /// Url of the component. DApi is reached by its host name with the api key in the path.
+    pub(crate) fn get_url(&self, domain: &String) -> UrlType {
+        match self.component_type {
+            ComponentType::DApi => {
+                format!("https://{}/{}", self.get_host_header(domain), self.token)
+            }
+            _ => format!("https://{}", self.ip),
+        }
     }
 
     pub fn get_host_header(&self, domain: &String) -> String {
@@ -141,7 +353,22 @@ impl ComponentInfo {
             ComponentType::Gateway => {
                 format!("{}.gw.mbr.{}", self.id, domain)
             }
-            ComponentType::DApi => String::default(),
+            // e.g. `5f74cc88-678a-4055-be16-7ec0d4abb835.eth-mainnet.massbitroute.dev`
+            ComponentType::DApi => {
+                format!(
+                    "{}.{}-{}.{}",
+                    self.id, self.blockchain, self.network, domain
+                )
+            }
+        }
+    }
+
+    /// Authentication and host headers of requests to the component.
+    pub fn get_request_headers(&self, domain: &String) -> Vec<(String, String)> {
+        let host = ("host".to_string(), self.get_host_header(domain));
+        match self.component_type {
+            ComponentType::DApi => vec![host],
+            _ => vec![("x-api-key".to_string(), self.token.clone()), host],
         }
     }
 }
@@ -168,35 +395,100 @@ pub struct CheckComponent {
     pub check_flows: CheckFlows,
     pub is_loop_check: bool,
     pub is_write_to_file: bool,
+    // Store reports of `check_components` to portal
+    #[serde(default = "default_is_store_report")]
+    pub is_store_report: bool,
+    #[serde(skip)]
+    pub base_endpoint_tracker: Arc<Mutex<BaseEndpointTracker>>,
+    // Reloaded check flows and base endpoints, see `FlowReloader`
+    #[serde(skip)]
+    pub flow_config: Option<watch::Receiver<Arc<FlowConfig>>>,
+    #[serde(skip)]
+    pub flow_config_version: u64,
+    #[serde(skip)]
+    pub check_flow_source: Arc<FlowSource>,
+    #[serde(skip)]
+    pub base_endpoint_source: Arc<FlowSource>,
+    #[serde(skip)]
+    pub check_limits: CheckLimits,
+    #[serde(skip)]
+    pub http_client: HttpClient,
+    #[serde(skip)]
+    pub base_node_cache: BaseNodeCache,
+    #[serde(skip)]
+    pub report_sinks: ReportSinks,
+    #[serde(skip)]
+    pub check_history: CheckHistory,
+}
+
+pub type CheckFlows = HashMap<TaskType, Vec<CheckFlow>>;
+
+fn default_is_store_report() -> bool {
+    true
 }
 
-type CheckFlows = HashMap<TaskType, Vec<CheckFlow>>;
+// Network of a check flow matching all networks of the chain
+pub const ANY_NETWORK: &str = "*";
+
+/// Base endpoints of the chain network, key `<chain>.<network>` (e.g. `eth.goerli`),
+/// falling back to the endpoints of the chain key.
+pub(crate) fn network_base_endpoints<'a>(
+    base_nodes: &'a HashMap<BlockChainType, Vec<EndpointInfo>>,
+    blockchain: &str,
+    network: &str,
+) -> Option<&'a Vec<EndpointInfo>> {
+    base_nodes
+        .get(&format!("{}.{}", blockchain, network))
+        .or_else(|| base_nodes.get(blockchain))
+}
 
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
 pub struct EndpointInfo {
-    url: UrlType,
+    pub(crate) url: UrlType,
+    // Websocket url if it differs from `url` with ws(s) scheme
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ws_url: Option<UrlType>,
     #[serde(default, rename = "X-Api-Key")]
-    x_api_key: String,
+    pub(crate) x_api_key: String,
+}
+
+impl EndpointInfo {
+    pub(crate) fn get_ws_url(&self) -> UrlType {
+        self.ws_url.clone().unwrap_or(self.url.clone())
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
 pub struct CheckFlow {
     #[serde(default)]
-    blockchain: BlockChainType,
+    pub(crate) blockchain: BlockChainType,
+    // Flow for all networks of the chain if empty or `*`
     #[serde(default)]
-    component: String,
+    pub(crate) network: String,
     #[serde(default)]
-    check_steps: Vec<CheckStep>,
+    pub(crate) component: String,
+    #[serde(default)]
+    pub(crate) check_steps: Vec<CheckStep>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+impl CheckFlow {
+    pub(crate) fn is_any_network(&self) -> bool {
+        self.network.is_empty() || self.network == ANY_NETWORK
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CheckStep {
+    pub(crate) action: CheckAction,
     #[serde(default)]
-    action: Value,
-    #[serde(default)]
-    return_name: String,
+    pub(crate) return_name: String,
     #[serde(default)]
     failed_case: FailedCase,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) retry: Option<RetryPolicy>,
+    // Return names of steps to wait for, all previous steps if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) depends_on: Option<Vec<String>>,
 }
 
 // struct GatewayInfo;
@@ -253,6 +545,24 @@ pub struct CheckMkReport {
     pub metric: CheckMkMetric,
     pub status_detail: String,
     pub success: bool,
+    // Base endpoints did not answer, the component is not judged
+    #[serde(default)]
+    pub reference_failure: bool,
+    // Steps run by `run_check_steps`, in the order of the flow
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<StepOutcome>,
+}
+
+/// Result of a check step of a check run.
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+pub struct StepOutcome {
+    pub name: String,
+    pub success: bool,
+    // CheckMk status concluded by the step, ok if it succeeded
+    pub status: u8,
+    pub attempts: usize,
+    pub response_time_ms: Option<i64>,
+    pub message: String,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
@@ -261,26 +571,29 @@ pub struct CheckMkMetric {
 }
 
 impl ToString for CheckMkMetric {
+    // CheckMk metrics are numbers, sorted so that the output is stable
     fn to_string(&self) -> String {
-        if self.metric.is_empty() {
-            format!("-")
-        } else {
-            self.metric
-                .iter()
-                .map(|(key, val)| format!("{}={}", key, val))
-                .collect::<Vec<String>>()
-                .join("|")
+        let mut metrics = self
+            .metric
+            .iter()
+            .filter(|(_, val)| val.is_number())
+            .map(|(key, val)| format!("{}={}", key, val))
+            .collect::<Vec<String>>();
+        if metrics.is_empty() {
+            return format!("-");
         }
+        metrics.sort();
+        metrics.join("|")
     }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
 pub struct ActionResponse {
-    success: bool,
-    conclude: CheckMkStatus,
-    return_name: String,
-    result: HashMap<String, String>,
-    message: String,
+    pub(crate) success: bool,
+    pub(crate) conclude: CheckMkStatus,
+    pub(crate) return_name: String,
+    pub(crate) result: StepResult,
+    pub(crate) message: String,
 }
 
 #[derive(PartialEq, Clone, Debug, Deserialize, Serialize)]
@@ -304,7 +617,8 @@ impl ToString for CheckMkReport {
             status = &self.status,
             service_name = &self.service_name,
             metric = self.metric.to_string(),
-            status_detail = self.status_detail
+            // A local check is one line
+            status_detail = self.status_detail.replace('\n', " ")
         )
     }
 }
@@ -360,6 +674,8 @@ impl CheckMkReport {
             metric: Default::default(),
             status_detail: message,
             success: true,
+            reference_failure: false,
+            steps: vec![],
         }
     }
 }
@@ -408,6 +724,8 @@ impl CheckMkReport {
             metric,
             status_detail,
             success,
+            reference_failure: logic_check.reference_failure,
+            steps: logic_check.steps.clone(),
         }
     }
 }
@@ -427,44 +745,34 @@ impl CheckComponent {
         filter_zone: &Zone,
     ) -> Result<(), anyhow::Error> {
         // Get nodes
-        let url = &self.list_node_id_file;
-        debug!("list_node_id url:{}", url);
-        let res_data = reqwest::Client::builder()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .unwrap()
-            .get(url)
-            .send()
-            .await?
-            .text()
-            .await?;
-        debug!("res_data Node: {:?}", res_data);
-        let mut components: Vec<ComponentInfo> = serde_json::from_str(res_data.as_str())?;
-        debug!("components Node: {:?}", components);
-        for component in components.iter_mut() {
-            component.component_type = ComponentType::Node;
-        }
-        self.list_nodes = components;
+        self.list_nodes = Self::get_list_component(
+            self.http_client.pooled(),
+            &self.list_node_id_file,
+            ComponentType::Node,
+        )
+        .await?;
 
         //Get gateway
-        let url = &self.list_gateway_id_file;
-        debug!("list_gateway_id url:{}", url);
-        let res_data = reqwest::Client::builder()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .unwrap()
-            .get(url)
-            .send()
-            .await?
-            .text()
-            .await?;
-        debug!("res_data Gateway: {:?}", res_data);
-        let mut components: Vec<ComponentInfo> = serde_json::from_str(res_data.as_str()).unwrap();
-        debug!("components Gateway: {:?}", components);
-        for component in components.iter_mut() {
-            component.component_type = ComponentType::Gateway;
+        self.list_gateways = Self::get_list_component(
+            self.http_client.pooled(),
+            &self.list_gateway_id_file,
+            ComponentType::Gateway,
+        )
+        .await?;
+
+        //Get dapi, nodes and gateways are still checked if the dapi list is unavailable
+        if !self.list_dapi_id_file.is_empty() {
+            match Self::get_list_component(
+                self.http_client.pooled(),
+                &self.list_dapi_id_file,
+                ComponentType::DApi,
+            )
+            .await
+            {
+                Ok(components) => self.list_dapis = components,
+                Err(err) => warn!("Cannot load list dapi, keep the previous list: {}", err),
+            }
         }
-        self.list_gateways = components;
 
         //Filter components
         if let Some(status) = filter_status {
@@ -472,6 +780,8 @@ impl CheckComponent {
                 .retain(|component| &component.status == status);
             self.list_gateways
                 .retain(|component| &component.status == status);
+            self.list_dapis
+                .retain(|component| &component.status == status);
         }
 
         //Filter zone
@@ -481,13 +791,34 @@ impl CheckComponent {
                 .retain(|component| component.zone == *filter_zone);
             self.list_gateways
                 .retain(|component| component.zone == *filter_zone);
+            self.list_dapis
+                .retain(|component| component.zone == *filter_zone);
         }
         Ok(())
     }
 
+    async fn get_list_component(
+        client: &reqwest::Client,
+        url: &String,
+        component_type: ComponentType,
+    ) -> Result<Vec<ComponentInfo>, anyhow::Error> {
+        debug!("list {} id url:{}", component_type.to_string(), url);
+        let res_data = client.get(url).send().await?.text().await?;
+        debug!("res_data {:?}: {:?}", component_type, res_data);
+        let mut components: Vec<ComponentInfo> = serde_json::from_str(res_data.as_str())?;
+        debug!("components {:?}: {:?}", component_type, components);
+        for component in components.iter_mut() {
+            component.component_type = component_type.clone();
+        }
+        Ok(components)
+    }
+
+    /// Steps of the flows for the network of the chain, flows for any network are used
+    /// if the task has no flow for the network.
     pub fn get_check_steps(
         &self,
         blockchain: &String,
+        network: &String,
         component_type: &String,
         tasks: &Vec<TaskType>,
     ) -> Result<Vec<CheckStep>, anyhow::Error> {
@@ -498,10 +829,22 @@ impl CheckComponent {
         for task in tasks {
             match self.check_flows.get(task.as_str()) {
                 Some(check_flows) => {
+                    let check_flows = check_flows
+                        .iter()
+                        .filter(|check_flow| {
+                            &check_flow.blockchain == blockchain
+                                && &check_flow.component == component_type
+                        })
+                        .collect::<Vec<_>>();
+                    let has_network_flow = check_flows
+                        .iter()
+                        .any(|check_flow| &check_flow.network == network);
                     for check_flow in check_flows {
-                        if &check_flow.blockchain == blockchain
-                            && &check_flow.component == component_type
-                        {
+                        let is_matched = match has_network_flow {
+                            true => &check_flow.network == network,
+                            false => check_flow.is_any_network(),
+                        };
+                        if is_matched {
                             check_steps.extend(check_flow.check_steps.clone());
                         }
                     }
@@ -514,48 +857,135 @@ impl CheckComponent {
         Ok(check_steps)
     }
 
+    fn get_item_values(
+        items: &[String],
+        step_result: &StepResult,
+    ) -> Result<Vec<StepValue>, anyhow::Error> {
+        let mut item_values = Vec::new();
+        for item in items {
+            match item.strip_prefix("#") {
+                Some(literal) => item_values.push(StepValue::from_literal(literal)),
+                None => {
+                    let item_value = step_result
+                        .get(item.as_str())
+                        .ok_or(anyhow::Error::msg(format!("Cannot find key {}", item)))?;
+                    item_values.push(item_value.clone());
+                }
+            }
+        }
+        debug!("item_values: {:?}", item_values);
+        Ok(item_values)
+    }
+
+    fn get_item_pair(
+        items: &CompareItemPair,
+        step_result: &StepResult,
+    ) -> Result<(StepValue, StepValue), anyhow::Error> {
+        let mut item_values = Self::get_item_values(items, step_result)?;
+        let second = item_values.pop().unwrap_or_default();
+        let first = item_values.pop().unwrap_or_default();
+        Ok((first, second))
+    }
+
+    fn get_number(
+        operator: &OperatorCompare,
+        value: &StepValue,
+    ) -> Result<StepValue, anyhow::Error> {
+        match value.is_number() {
+            true => Ok(value.clone()),
+            false => Err(anyhow::Error::msg(format!(
+                "Operator {}: value {:?} is not a number",
+                operator.name(),
+                value
+            ))),
+        }
+    }
+
+    fn compare_numbers(
+        operator: &OperatorCompare,
+        items: &CompareItemPair,
+        step_result: &StepResult,
+    ) -> Result<Ordering, anyhow::Error> {
+        let (first, second) = Self::get_item_pair(items, step_result)?;
+        Self::get_number(operator, &first)?
+            .compare_number(&Self::get_number(operator, &second)?)
+            .ok_or(anyhow::Error::msg(format!(
+                "Operator {}: cannot compare {} and {}",
+                operator.name(),
+                first,
+                second
+            )))
+    }
+
     fn do_compare(
         operator: &OperatorCompare,
         step_result: &StepResult,
     ) -> Result<bool, anyhow::Error> {
-        match operator.operator_type.as_str() {
-            "and" => {
-                let sub_actions: Vec<OperatorCompare> =
-                    serde_json::from_value(operator.params.clone())?;
+        match operator {
+            OperatorCompare::And(sub_operators) => {
                 let mut result = true;
-                for sub_operator in sub_actions {
-                    result = result && CheckComponent::do_compare(&sub_operator, step_result)?;
+                for sub_operator in sub_operators {
+                    result = result && CheckComponent::do_compare(sub_operator, step_result)?;
                 }
-                return Ok(result);
+                Ok(result)
             }
-            "eq" => {
-                let items: Vec<String> = serde_json::from_value(operator.params.clone())?;
-                let mut item_values = Vec::new();
-                for mut item in items {
-                    if item.starts_with("#") {
-                        item.remove(0);
-                        item_values.push(item.clone());
-                    } else {
-                        let item_value = step_result
-                            .get(item.as_str())
-                            .ok_or(anyhow::Error::msg("Cannot find key"))?;
-                        item_values.push(item_value.clone());
-                    }
+            OperatorCompare::Or(sub_operators) => {
+                let mut result = false;
+                for sub_operator in sub_operators {
+                    result = result || CheckComponent::do_compare(sub_operator, step_result)?;
                 }
-                debug!("item_values: {:?}", item_values);
+                Ok(result)
+            }
+            OperatorCompare::Not(operand) => Ok(!CheckComponent::do_compare(
+                operand.operator(),
+                step_result,
+            )?),
+            OperatorCompare::Eq(items) | OperatorCompare::Neq(items) => {
+                let item_values = Self::get_item_values(items, step_result)?;
                 if item_values.len() > 1 {
                     let first = &item_values[0];
-                    for item_value in item_values.iter() {
-                        if item_value != first {
-                            return Ok(false);
-                        }
-                    }
-                    return Ok(true);
+                    let is_equal = item_values
+                        .iter()
+                        .all(|item_value| item_value.loosely_eq(first));
+                    Ok(match operator {
+                        OperatorCompare::Eq(_) => is_equal,
+                        _ => !is_equal,
+                    })
                 } else {
-                    return Ok(false);
+                    Ok(false)
                 }
             }
-            _ => return Err(anyhow::Error::msg("Cannot find key")),
+            OperatorCompare::Gt(items) => {
+                Ok(Self::compare_numbers(operator, items, step_result)? == Ordering::Greater)
+            }
+            OperatorCompare::Gte(items) => {
+                Ok(Self::compare_numbers(operator, items, step_result)? != Ordering::Less)
+            }
+            OperatorCompare::Lt(items) => {
+                Ok(Self::compare_numbers(operator, items, step_result)? == Ordering::Less)
+            }
+            OperatorCompare::Lte(items) => {
+                Ok(Self::compare_numbers(operator, items, step_result)? != Ordering::Greater)
+            }
+            OperatorCompare::Regex(items) => {
+                let (value, pattern) = Self::get_item_pair(items, step_result)?;
+                let regex = Regex::new(pattern.to_string().as_str())?;
+                Ok(regex.is_match(value.to_string().as_str()))
+            }
+            OperatorCompare::Contains(items) => {
+                let (value, item) = Self::get_item_pair(items, step_result)?;
+                Ok(value.contains(&item))
+            }
+            OperatorCompare::Within(items) => {
+                let numbers = Self::get_item_values(items, step_result)?
+                    .iter()
+                    .map(|value| {
+                        Self::get_number(operator, value)
+                            .map(|value| value.as_f64().unwrap_or_default())
+                    })
+                    .collect::<Result<Vec<f64>, anyhow::Error>>()?;
+                Ok((numbers[0] - numbers[1]).abs() <= numbers[2])
+            }
         }
     }
 
@@ -575,8 +1005,8 @@ impl CheckComponent {
 
         debug!("Run Compare Action success: {}", success);
 
-        let mut result: HashMap<String, String> = HashMap::new();
-        result.insert(return_name.clone(), success.to_string());
+        let mut result: StepResult = HashMap::new();
+        result.insert(return_name.clone(), StepValue::from(success));
         return Ok(ActionResponse {
             success: true,
             conclude: match success {
@@ -589,55 +1019,122 @@ impl CheckComponent {
         });
     }
 
-    fn replace_string(org: String, step_result: &StepResult) -> Result<String, anyhow::Error> {
-        let handlebars = Handlebars::new();
-        handlebars
+    fn set_action(
+        action: &ActionSet,
+        return_name: &str,
+        step_result: &StepResult,
+    ) -> Result<ActionResponse, anyhow::Error> {
+        let mut result: StepResult = HashMap::new();
+        for (name, template) in &action.vars {
+            let value = CheckComponent::replace_string(template.clone(), step_result)?;
+            result.insert(name.clone(), StepValue::from_json(Value::String(value)));
+        }
+        Ok(ActionResponse {
+            success: true,
+            conclude: CheckMkStatus::Ok,
+            return_name: return_name.to_string(),
+            message: format!("set vars: {:?}", result),
+            result,
+        })
+    }
+
+    pub(crate) fn replace_string(
+        org: String,
+        step_result: &StepResult,
+    ) -> Result<String, anyhow::Error> {
+        HANDLEBARS
             .render_template(org.as_str(), step_result)
             .map_err(|err| {
-                println!("err:{:?}", err);
+                debug!("Cannot render template {:?}: {:?}", org, err);
                 anyhow::Error::msg(format!("{}", err))
             })
     }
 
+    pub(crate) fn get_base_endpoints(
+        &self,
+        component: &ComponentInfo,
+    ) -> Result<&Vec<EndpointInfo>, anyhow::Error> {
+        network_base_endpoints(&self.base_nodes, &component.blockchain, &component.network).ok_or(
+            anyhow::Error::msg(format!(
+                "Cannot found base node for chain {:?} network {:?}",
+                &component.blockchain, &component.network
+            )),
+        )
+    }
+
     pub async fn call_action(
         &self,
+        action: &ActionCall,
         component: &ComponentInfo,
         step_result: &StepResult,
         step: &CheckStep,
     ) -> Result<ActionResponse, anyhow::Error> {
-        let action: ActionCall = serde_json::from_value(step.action.clone()).unwrap();
-        debug!("call action: {:?}", &action);
+        debug!("call action: {:?}", action);
 
         if action.is_base_node {
             // Get base_endpoints
-            let mut report = Err(anyhow::Error::msg("Cannot found working base node"));
-            let base_endpoints =
-                self.base_nodes
-                    .get(&component.blockchain)
-                    .ok_or(anyhow::Error::msg(format!(
-                        "Cannot found base node for chain {:?}",
-                        &component.blockchain
-                    )))?;
-            // Calling to Base node retry if failed
-            for endpoint in base_endpoints {
-                debug!("try endpoint:{:?}", endpoint);
-                let res = self
-                    .call_action_base_node(&action, &step.return_name, &step_result, endpoint)
-                    .await;
-                if res.is_ok() {
-                    report = res;
-                    debug!("endpoint {:?} success return: {:?}", endpoint, report);
-                    break;
-                }
-            }
-            report
+            let base_endpoints = self.order_base_endpoints(self.get_base_endpoints(component)?);
+            // Rendered once, so that all base endpoints get the same request
+            let request = RenderedRequest::render(action, step_result)?;
+            // Components of the same chain compare against the same cached answer
+            let cache_key = BaseNodeCacheKey::new(component, action, &step.return_name, &request);
+            self.base_node_cache
+                .get_or_call(cache_key, || {
+                    self.call_base_endpoints(action, &base_endpoints, &step.return_name, &request)
+                })
+                .await
+                .map_err(|err| {
+                    // The component cannot be judged without the reference answer
+                    err.context(ReferenceUnavailable {
+                        blockchain: component.blockchain.clone(),
+                        network: component.network.clone(),
+                    })
+                })
         } else {
             // Calling check node only runs once
-            self.call_action_check_node(&action, component, &step.return_name, &step_result)
+            self.call_action_check_node(action, component, &step.return_name, &step_result)
                 .await
         }
     }
 
+    // Healthy base endpoints first, see `BaseEndpointTracker::order`
+    pub(crate) fn order_base_endpoints<'a>(
+        &self,
+        endpoints: &'a [EndpointInfo],
+    ) -> Vec<&'a EndpointInfo> {
+        let tracker = lock(&self.base_endpoint_tracker);
+        tracker.order(endpoints)
+    }
+
+    async fn call_base_endpoints(
+        &self,
+        action: &ActionCall,
+        base_endpoints: &[&EndpointInfo],
+        return_name: &String,
+        request: &RenderedRequest,
+    ) -> Result<ActionResponse, anyhow::Error> {
+        if let Some(consensus) = &action.consensus {
+            return self
+                .call_action_consensus(action, consensus, base_endpoints, return_name, request)
+                .await;
+        }
+        // Calling to Base node retry if failed
+        let mut report = Err(anyhow::Error::msg("Cannot found working base node"));
+        for endpoint in base_endpoints {
+            debug!("try endpoint:{:?}", endpoint);
+            let res = self
+                .call_action_base_node(action, return_name, request, endpoint)
+                .await;
+            // Keep the last error, it decides whether the step is retried
+            report = res;
+            if report.is_ok() {
+                debug!("endpoint {:?} success return: {:?}", endpoint, report);
+                break;
+            }
+        }
+        report
+    }
+
     async fn call_action_check_node(
         &self,
         action: &ActionCall,
@@ -646,23 +1143,72 @@ impl CheckComponent {
         step_result: &StepResult,
     ) -> Result<ActionResponse, anyhow::Error> {
         // prepare rpc call
-        let node_url = node.get_url();
-        let url = &node_url;
-
-        let client_builder = reqwest::ClientBuilder::new();
-        let client = client_builder.danger_accept_invalid_certs(true).build()?;
-        // Replace body for transport result of previous step
-        let body = Self::replace_string(action.body.clone(), step_result)?;
-
-        debug!("body: {:?}", body);
-        let request_builder = client
-            .post(url)
-            .header("content-type", "application/json")
-            .header("x-api-key", node.token.as_str())
-            .header("host", node.get_host_header(&self.domain))
-            .body(body);
+        let node_url = node.get_url(&self.domain);
+
+        let client = self.http_client.get(action.cold_connection);
+        let request = RenderedRequest::render(action, step_result)?;
+        let mut request_builder = Self::build_request(client, &node_url, action, &request)?;
+        for (name, value) in node.get_request_headers(&self.domain) {
+            request_builder = request_builder.header(name, value);
+        }
 
+        Self::send_request(request_builder, &node_url, action, return_name).await
+    }
+
+    // Create request with method of the action and its rendered path, query, header and body.
+    fn build_request(
+        client: &reqwest::Client,
+        url: &str,
+        action: &ActionCall,
+        request: &RenderedRequest,
+    ) -> Result<RequestBuilder, anyhow::Error> {
+        let path = &request.path;
+        let url = match path.is_empty() {
+            true => url.to_string(),
+            false => format!(
+                "{}/{}",
+                url.trim_end_matches('/'),
+                path.trim_start_matches('/')
+            ),
+        };
+        let mut request_builder = match action.request_type {
+            RequestType::Get => client.get(url),
+            RequestType::Post => client.post(url),
+            RequestType::Put => client.put(url),
+        };
+        if !request.query.is_empty() {
+            request_builder = request_builder.query(&request.query);
+        }
+        let has_content_type = action
+            .header
+            .keys()
+            .any(|key| key.eq_ignore_ascii_case("content-type"));
+        if !has_content_type && action.request_type != RequestType::Get {
+            request_builder = request_builder.header("content-type", "application/json");
+        }
+        for (key, value) in request.header.iter() {
+            request_builder = request_builder.header(key, value);
+        }
+        debug!("body: {:?}", request.body);
+        if !request.body.is_empty() {
+            request_builder = request_builder.body(request.body.clone());
+        }
+        Ok(request_builder)
+    }
+
+    async fn send_request(
+        request_builder: RequestBuilder,
+        url: &str,
+        action: &ActionCall,
+        return_name: &String,
+    ) -> Result<ActionResponse, anyhow::Error> {
         debug!("request_builder: {:?}", request_builder);
+        let time_out = std::time::Duration::from_secs(action.time_out as u64);
+        // Probe before the request, so that it does not compete with the request connection
+        let connection_timing = match action.measure_connection {
+            true => Some(ConnectionTiming::probe(url, time_out).await?),
+            false => None,
+        };
 
         let sender = request_builder.send();
         pin_mut!(sender);
@@ -670,17 +1216,40 @@ impl CheckComponent {
         // Call rpc
         // Start clock to meansure call time
         let now = Instant::now();
-        let res = tokio::time::timeout(
-            std::time::Duration::from_secs(action.time_out as u64),
-            &mut sender,
-        )
-        .await;
+        let res = tokio::time::timeout(time_out, &mut sender).await;
         //End clock
         let response_time_ms = now.elapsed().as_millis();
 
-        let str_resp = res??.text().await?;
-        debug!("response call: {:?}", str_resp);
-        Self::prepare_result(&str_resp, response_time_ms, action, return_name)
+        let response = res??;
+        let status_code = response.status().as_u16();
+        let str_resp = response.text().await?;
+        debug!("response call: {} {:?}", status_code, str_resp);
+        // Prepare return result
+        let mut action_resp =
+            match Self::prepare_result(&str_resp, response_time_ms, action, return_name) {
+                Ok(action_resp) => action_resp,
+                // Keep the status so that the step can be retried on server errors
+                Err(err) if status_code >= 500 => {
+                    return Err(anyhow::Error::new(HttpStatusError(status_code))
+                        .context(format!("http status {}, {}", status_code, err)))
+                }
+                Err(err) => return Err(err),
+            };
+        action_resp.result.insert(
+            STATUS_CODE_KEY.to_string(),
+            StepValue::from(status_code as u64),
+        );
+        if let Some(timing) = connection_timing {
+            action_resp.result.insert(
+                CONNECT_TIME_KEY.to_string(),
+                StepValue::from(timing.connect_time_ms),
+            );
+            action_resp.result.insert(
+                HANDSHAKE_TIME_KEY.to_string(),
+                StepValue::from(timing.handshake_time_ms),
+            );
+        }
+        Ok(action_resp)
     }
 
     fn prepare_result(
@@ -692,50 +1261,30 @@ impl CheckComponent {
         let mut str_resp_short = str_resp.clone();
         str_resp_short.truncate(CONFIG.max_length_report_detail);
 
-        let resp: Value = serde_json::from_str(&str_resp).map_err(|e| {
-            anyhow::Error::msg(format!(
-                "Err {} when parsing response: {} ",
-                e, str_resp_short,
-            ))
-        })?;
+        // Non-json response (e.g. `pong`) is kept as string
+        let resp: Value = match serde_json::from_str(&str_resp) {
+            Ok(resp) => resp,
+            Err(e) if str_resp.trim_start().starts_with(&['{', '['][..]) => {
+                return Err(anyhow::Error::msg(format!(
+                    "Err {} when parsing response: {} ",
+                    e, str_resp_short,
+                )));
+            }
+            Err(_) => Value::String(str_resp.clone()),
+        };
 
         // get result
-        let mut result: HashMap<String, String> = HashMap::new();
+        let mut result: StepResult = HashMap::new();
 
         // Add response_time
         result.insert(
             CONFIG.response_time_key.to_string(),
-            response_time_ms.to_string(),
+            StepValue::from(response_time_ms as u64),
         );
 
-        for (name, path) in action.return_fields.clone() {
-            let mut value = resp.clone();
-            let path: Vec<String> = path.split("/").map(|s| s.to_string()).collect();
-            //debug!("path: {:?}", path);
-            for key in path.into_iter() {
-                //debug!("key: {:?}", key);
-                value = value
-                    .get(key.clone())
-                    .ok_or(anyhow::Error::msg(format!(
-                        "cannot find key {} in result: {:?} ",
-                        &key, resp
-                    )))?
-                    .clone();
-                //debug!("value: {:?}", value);
-            }
-            // Fixme: check other type
-            let str_value: String = match value.as_bool() {
-                Some(value) => value.to_string(),
-                None => value
-                    .as_str()
-                    .ok_or(anyhow::Error::msg(format!(
-                        "value {:?} cannot parse to string",
-                        value
-                    )))?
-                    .to_string(),
-            };
-
-            result.insert(name, str_value);
+        for (name, field) in action.return_fields.iter() {
+            let value = field.extract(&resp)?;
+            result.insert(name.clone(), StepValue::from_json(value));
         }
 
         let action_resp = ActionResponse {
@@ -751,54 +1300,100 @@ impl CheckComponent {
         Ok(action_resp)
     }
 
-    async fn call_action_base_node(
+    pub(crate) async fn call_action_base_node(
         &self,
         action: &ActionCall,
         return_name: &String,
-        step_result: &StepResult,
+        request: &RenderedRequest,
         base_endpoint: &EndpointInfo,
     ) -> Result<ActionResponse, anyhow::Error> {
-        let url = &base_endpoint.url;
-
-        let client_builder = reqwest::ClientBuilder::new();
-        let client = client_builder.danger_accept_invalid_certs(true).build()?;
-        // Replace body for transport result of previous step
-        let body = Self::replace_string(action.body.clone(), step_result)?;
-
-        debug!("body: {:?}", body);
-        let request_builder = if base_endpoint.x_api_key.is_empty() {
-            client
-                .post(url)
-                .header("content-type", "application/json")
-                .body(body)
-        } else {
-            client
-                .post(url)
-                .header("content-type", "application/json")
-                .header("x-api-key", base_endpoint.x_api_key.as_str())
-                .body(body)
-        };
+        let client = self.http_client.get(action.cold_connection);
+        let mut request_builder = Self::build_request(client, &base_endpoint.url, action, request)?;
+        if !base_endpoint.x_api_key.is_empty() {
+            request_builder = request_builder.header("x-api-key", base_endpoint.x_api_key.as_str());
+        }
 
-        debug!("request_builder: {:?}", request_builder);
+        let now = Instant::now();
+        let response =
+            Self::send_request(request_builder, &base_endpoint.url, action, return_name).await;
+        self.record_base_endpoint_call(base_endpoint, now, &response);
+        response
+    }
 
-        let sender = request_builder.send();
-        pin_mut!(sender);
+    // Answer of a http or websocket call to a base endpoint started at `started`
+    pub(crate) fn record_base_endpoint_call(
+        &self,
+        base_endpoint: &EndpointInfo,
+        started: Instant,
+        response: &Result<ActionResponse, anyhow::Error>,
+    ) {
+        let answer = match response {
+            Ok(_) => Ok(started.elapsed()),
+            Err(err) => Err(err.to_string()),
+        };
+        let mut tracker = lock(&self.base_endpoint_tracker);
+        if tracker.record_call(&base_endpoint.url, answer) {
+            warn!(
+                "Base endpoint {} in {} is disabled for {}ms after {} failures, last error: {:?}",
+                base_endpoint.url,
+                self.base_endpoint_file,
+                CONFIG.base_endpoint_disable_ms,
+                CONFIG.base_endpoint_max_failures,
+                response.as_ref().err().map(|err| err.to_string())
+            );
+        }
+    }
 
-        // Call rpc
-        // Start clock to meansure call time
-        let now = Instant::now();
-        let res = tokio::time::timeout(
-            std::time::Duration::from_secs(action.time_out as u64),
-            &mut sender,
-        )
-        .await;
-        //End clock
-        let response_time_ms = now.elapsed().as_millis();
+    async fn run_step_action(
+        &self,
+        step: &CheckStep,
+        component: &ComponentInfo,
+        step_result: &StepResult,
+    ) -> Result<ActionResponse, anyhow::Error> {
+        match &step.action {
+            CheckAction::Call(action) => {
+                self.call_action(action, component, step_result, step).await
+            }
+            CheckAction::Compare(action) => {
+                debug!("compare action: {:?}", action);
+                self.compare_action(action, component, &step.return_name, step_result)
+            }
+            CheckAction::WsCall(action) => {
+                self.ws_action(action, false, component, step_result, &step.return_name)
+                    .await
+            }
+            CheckAction::WsSubscribe(action) => {
+                self.ws_action(action, true, component, step_result, &step.return_name)
+                    .await
+            }
+            CheckAction::Set(action) => Self::set_action(action, &step.return_name, step_result),
+        }
+    }
 
-        let str_resp = res??.text().await?;
-        debug!("response call: {:?}", str_resp);
-        // Prepare return result
-        Self::prepare_result(&str_resp, response_time_ms, action, return_name)
+    // Run the step action until it succeeds or its retry policy gives up.
+    // Returns the last result, number of attempts and message of failed attempts.
+    async fn run_step_with_retry(
+        &self,
+        step: &CheckStep,
+        component: &ComponentInfo,
+        step_result: &StepResult,
+    ) -> (Result<ActionResponse, anyhow::Error>, usize, String) {
+        let mut message = String::new();
+        let mut attempt = 1;
+        loop {
+            let report = self.run_step_action(step, component, step_result).await;
+            match (&report, &step.retry) {
+                (Err(err), Some(retry)) if retry.should_retry(attempt, err) => {
+                    message.push_str(&format!(
+                        "Step {} attempt {}/{} failed, err: {}. ",
+                        &step.return_name, attempt, retry.attempts, err
+                    ));
+                    tokio::time::sleep(retry.delay(attempt)).await;
+                    attempt += 1;
+                }
+                _ => return (report, attempt, message),
+            }
+        }
     }
 
     pub async fn run_check_steps(
@@ -808,83 +1403,128 @@ impl CheckComponent {
     ) -> Result<CheckMkReport, anyhow::Error> {
         let mut step_result: StepResult = HashMap::new();
         let mut status = CheckMkStatus::Ok;
+        let mut reference_failure = false;
         let mut message = String::new();
         let step_number = steps.len();
         let mut metric: HashMap<String, Value> = HashMap::new();
-
-        for step in steps {
-            debug!("step: {:?}", step);
-            let report = match step
-                .action
-                .get("action_type")
-                .unwrap()
-                .as_str()
-                .unwrap_or_default()
-            {
-                "call" => self.call_action(component, &step_result, &step).await,
-                "compare" => {
-                    let action: ActionCompare =
-                        serde_json::from_value(step.action.clone()).unwrap();
-                    debug!("compare action: {:?}", &action);
-                    self.compare_action(&action, component, &step.return_name, &step_result)
+        let mut outcomes: Vec<StepOutcome> = Vec::new();
+
+        // Steps of a stage do not depend on each other and run concurrently,
+        // their results are handled in the order of the flow
+        'stages: for stage in step_stages(&steps) {
+            debug!("stage: {:?}", stage);
+            let stage_reports =
+                join_all(stage.iter().map(|index| {
+                    self.run_step_with_retry(&steps[*index], component, &step_result)
+                }))
+                .await;
+            for (index, (report, attempts, retry_message)) in stage.into_iter().zip(stage_reports) {
+                let step = &steps[index];
+                debug!("step: {:?}", step);
+                message.push_str(&retry_message);
+                if step.retry.is_some() {
+                    metric.insert(
+                        format!("{}_attempts", step.return_name),
+                        Value::from(attempts),
+                    );
                 }
-                _ => Err(anyhow::Error::msg("not support action")),
-            };
-
-            // Handle report
-            match report {
-                Ok(report) => {
-                    let resp_time = if let Some(response_time_ms) =
-                        report.result.get(&*CONFIG.response_time_key)
-                    {
-                        Some(response_time_ms.parse::<i64>().unwrap_or_default())
-                    } else {
-                        None
-                    };
-                    if let Some(resp_time) = resp_time {
-                        let metric_name =
-                            format!("{}_{}", report.return_name, CONFIG.response_time_key);
-                        metric.insert(metric_name, resp_time.into());
-                    }
-
-                    match report.success {
-                        true => {
-                            debug!(
-                                "Success step: {:?}, report: {:?}",
-                                &step.return_name, report
-                            );
-                            for (key, value) in &report.result {
-                                step_result.insert(
-                                    format!("{}_{}", &report.return_name, key),
-                                    value.clone(),
-                                );
+                let mut outcome = StepOutcome {
+                    name: step.return_name.clone(),
+                    success: false,
+                    status: step.failed_case.conclude.clone() as u8,
+                    attempts,
+                    response_time_ms: None,
+                    message: String::new(),
+                };
+
+                // Handle report
+                match report {
+                    Ok(report) => {
+                        // Connect and handshake times are separate from the response time
+                        let time_keys = [
+                            CONFIG.response_time_key.as_str(),
+                            CONNECT_TIME_KEY,
+                            HANDSHAKE_TIME_KEY,
+                        ];
+                        for key in time_keys {
+                            if let Some(time_ms) = report.result.get(key) {
+                                let metric_name = format!("{}_{}", report.return_name, key);
+                                let time_ms = time_ms.as_i128().unwrap_or_default() as i64;
+                                metric.insert(metric_name, time_ms.into());
                             }
                         }
-                        false => {
-                            status = step.failed_case.conclude;
-                            if step.failed_case.critical {
-                                message.push_str(&format!(
-                                    "Failed at step {} due to critical error, message: {}",
-                                    &step.return_name, report.message
-                                ));
-                                break;
-                            } else {
-                                message.push_str(&format!(
-                                    "Failed at step {}, message: {}",
-                                    &step.return_name, report.message
-                                ));
+                        outcome.success = report.success;
+                        if report.success {
+                            outcome.status = CheckMkStatus::Ok as u8;
+                        }
+                        outcome.response_time_ms = report
+                            .result
+                            .get(CONFIG.response_time_key.as_str())
+                            .and_then(|time_ms| time_ms.as_i128())
+                            .map(|time_ms| time_ms as i64);
+                        outcome.message = report.message.clone();
+                        outcomes.push(outcome);
+
+                        match report.success {
+                            true => {
+                                debug!(
+                                    "Success step: {:?}, report: {:?}",
+                                    &step.return_name, report
+                                );
+                                for (key, value) in &report.result {
+                                    step_result.insert(
+                                        format!("{}_{}", &report.return_name, key),
+                                        value.clone(),
+                                    );
+                                }
+                            }
+                            false => {
+                                status = step.failed_case.conclude.clone();
+                                if step.failed_case.critical {
+                                    message.push_str(&format!(
+                                        "Failed at step {} due to critical error, message: {}",
+                                        &step.return_name, report.message
+                                    ));
+                                    break 'stages;
+                                } else {
+                                    message.push_str(&format!(
+                                        "Failed at step {}, message: {}",
+                                        &step.return_name, report.message
+                                    ));
+                                }
                             }
                         }
                     }
-                }
-                Err(e) => {
-                    message.push_str(&format!(
-                        "Failed at step {}, err: {}.",
-                        &step.return_name, e
-                    ));
-                    status = step.failed_case.conclude;
-                    if step.failed_case.critical {
-                        break;
+                    Err(e) if e.downcast_ref::<ReferenceUnavailable>().is_some() => {
+                        // Reference problem and the error of the last base endpoint
+                        let err = e
+                            .chain()
+                            .take(2)
+                            .map(|err| err.to_string())
+                            .collect::<Vec<_>>()
+                            .join(": ");
+                        message.push_str(&format!(
+                            "Cannot judge at step {}, err: {}.",
+                            &step.return_name, err
+                        ));
+                        outcome.status = CheckMkStatus::Unknown as u8;
+                        outcome.message = err;
+                        outcomes.push(outcome);
+                        status = CheckMkStatus::Unknown;
+                        reference_failure = true;
+                        break 'stages;
+                    }
+                    Err(e) => {
+                        message.push_str(&format!(
+                            "Failed at step {}, err: {}.",
+                            &step.return_name, e
+                        ));
+                        outcome.message = e.to_string();
+                        outcomes.push(outcome);
+                        status = step.failed_case.conclude.clone();
+                        if step.failed_case.critical {
+                            break 'stages;
+                        }
                     }
                 }
             }
@@ -914,6 +1554,8 @@ impl CheckComponent {
             metric: CheckMkMetric { metric },
             status_detail: message,
             success: true,
+            reference_failure,
+            steps: outcomes,
         })
     }
 
@@ -924,10 +1566,12 @@ impl CheckComponent {
         // Get logic report
         let mut check_mk_report = CheckMkReport::default();
         let mut wrk_report = WrkReport::default();
+        let mut is_benchmarked = false;
         debug!("component_info:{:?}", component_info);
         let check_steps = self
             .get_check_steps(
                 &component_info.blockchain,
+                &component_info.network,
                 &component_info.component_type.to_string(),
                 &CONFIG.check_task_list_all,
             )
@@ -939,10 +1583,10 @@ impl CheckComponent {
         let res_check_data = self.run_check_steps(check_steps, &component_info).await;
         info!("res:{:?}", res_check_data);
 
-        let response_time_threshold = if component_info.component_type == ComponentType::Gateway {
-            CONFIG.node_response_time_threshold_ms
-        } else {
-            CONFIG.gateway_response_time_threshold_ms
+        let response_time_threshold = match component_info.component_type {
+            ComponentType::Gateway => CONFIG.node_response_time_threshold_ms,
+            ComponentType::Node => CONFIG.gateway_response_time_threshold_ms,
+            ComponentType::DApi => CONFIG.dapi_response_time_threshold_ms,
         };
 
         match res_check_data {
@@ -957,11 +1601,15 @@ impl CheckComponent {
                             metric: Default::default(),
                             status_detail: "Skip benchmark".to_string(),
                             success: true,
+                            reference_failure: false,
+                            steps: vec![],
                         },
                         false => {
                             wrk_report = self
                                 .run_benchmark(response_time_threshold, &component_info)
                                 .await?;
+                            is_benchmarked = true;
+                            METRICS.record_benchmark(component_info, &wrk_report);
 
                             let res_benchmark = CheckMkReport::from_wrk_report(
                                 wrk_report.clone(),
@@ -980,6 +1628,15 @@ impl CheckComponent {
                 }
             }
         }
+        METRICS.record_check(component_info, &check_mk_report);
+        self.check_history
+            .record(CheckRun::new(
+                component_info,
+                &CONFIG.check_task_list_all.join(","),
+                &check_mk_report,
+                Some(&wrk_report).filter(|_| is_benchmarked),
+            ))
+            .await;
         Ok((check_mk_report, wrk_report))
     }
 
@@ -988,15 +1645,14 @@ impl CheckComponent {
         response_time_threshold: f32,
         component: &ComponentInfo,
     ) -> Result<WrkReport, anyhow::Error> {
-        let dapi_url = format!("https://{}", component.ip);
-        let host = match component.component_type {
-            ComponentType::Node => {
-                format!("{}.node.mbr.{}", component.id, self.domain)
-            }
-            ComponentType::Gateway => {
-                format!("{}.gw.mbr.{}", component.id, self.domain)
-            }
-            ComponentType::DApi => String::default(),
+        let host = component.get_host_header(&self.domain);
+        // Benchmark script of DApi adds the api key to the path
+        let (dapi_url, script) = match component.component_type {
+            ComponentType::DApi => (format!("https://{}", host), &CONFIG.benchmark_dapi_script),
+            _ => (
+                format!("https://{}", component.ip),
+                &CONFIG.benchmark_script,
+            ),
         };
 
         let mut benchmark = WrkBenchmark::build(
@@ -1007,12 +1663,14 @@ impl CheckComponent {
             dapi_url,
             component.token.clone(),
             host,
-            CONFIG.benchmark_script.to_string(),
+            script.to_string(),
             CONFIG.benchmark_wrk_path.to_string(),
             BENCHMARK_WRK_PATH.clone().to_string(),
             response_time_threshold,
         );
-        benchmark.run()
+        let _benchmark_permit = self.check_limits.acquire_benchmark().await;
+        // Wrk blocks until the benchmark is done
+        tokio::task::spawn_blocking(move || benchmark.run()).await?
     }
 
     //Using in fisherman service
@@ -1027,47 +1685,99 @@ impl CheckComponent {
         let mut components = Vec::new();
         components.extend(self.list_nodes.clone());
         components.extend(self.list_gateways.clone());
-        let mut reports = Vec::new();
-        for component in components {
-            match self.get_report_component(&component).await {
-                Ok((check_mk_report, wrk_report)) => {
-                    // Store report to portal
-                    let mut store_report = StoreReport::build(
-                        &*LOCAL_IP,
-                        ReporterRole::Fisherman,
-                        &*PORTAL_AUTHORIZATION,
-                        &self.domain,
-                    );
-                    store_report.set_report_data(
-                        &wrk_report,
-                        &check_mk_report,
-                        &component,
-                        ReportType::Benchmark,
-                    );
-                    let res = store_report.send_data(SendPurpose::Store).await;
-                    info!("Store report: {:?}", res.unwrap().text().await);
-                    // Store reports
-                    reports.push((component, check_mk_report));
-                }
-                Err(e) => {
-                    info!(
-                        "Cannot get report for component {:?}, error: {:?}",
-                        component, e
-                    );
-                }
+        components.extend(self.list_dapis.clone());
+        METRICS.add_queue_depth("check", components.len() as i64);
+        // Components are checked concurrently within `check_limits`, in the order they finish
+        let component_count = components.len();
+        let mut reports = stream::iter(components.into_iter().enumerate())
+            .map(|(index, component)| async move { (index, self.check_component(component).await) })
+            .buffer_unordered(component_count.max(1))
+            .filter_map(|(index, report)| async move { report.map(|report| (index, report)) })
+            .collect::<Vec<_>>()
+            .await;
+        // Reports keep the order of components
+        reports.sort_by_key(|(index, _)| *index);
+        let reports = reports
+            .into_iter()
+            .map(|(_, report)| report)
+            .collect::<Vec<_>>();
+        // One reference problem per chain network instead of a failure per component
+        let mut reference_failures: HashMap<(&String, &String), usize> = HashMap::new();
+        for (component, report) in reports.iter() {
+            if report.reference_failure {
+                *reference_failures
+                    .entry((&component.blockchain, &component.network))
+                    .or_default() += 1;
             }
         }
+        for ((blockchain, network), count) in reference_failures {
+            warn!(
+                "Reference endpoints of {} {} unavailable, {} components are not judged",
+                blockchain, network, count
+            );
+        }
         Ok(reports)
     }
+
+    async fn check_component(
+        &self,
+        component: ComponentInfo,
+    ) -> Option<(ComponentInfo, CheckMkReport)> {
+        let _chain_permit = self.check_limits.acquire_chain(&component.blockchain).await;
+        let _check_permit = self.check_limits.acquire_check().await;
+        METRICS.add_queue_depth("check", -1);
+        match self.get_report_component(&component).await {
+            Ok((check_mk_report, _))
+                if check_mk_report.reference_failure || !self.is_store_report =>
+            {
+                // Not a result of the component, do not send it to report sinks
+                Some((component, check_mk_report))
+            }
+            Ok((check_mk_report, wrk_report)) => {
+                // Send report to report sinks
+                let mut store_report = StoreReport::build(
+                    &*LOCAL_IP,
+                    ReporterRole::Fisherman,
+                    &*PORTAL_AUTHORIZATION,
+                    &self.domain,
+                );
+                store_report.set_report_data(
+                    &wrk_report,
+                    &check_mk_report,
+                    &component,
+                    ReportType::Benchmark,
+                );
+                let event = ReportEvent {
+                    purpose: SendPurpose::Store,
+                    component: &component,
+                    check_mk_report: Some(&check_mk_report),
+                    report: &store_report,
+                };
+                self.report_sinks
+                    .send(self.http_client.pooled(), &event)
+                    .await;
+                Some((component, check_mk_report))
+            }
+            Err(e) => {
+                info!(
+                    "Cannot get report for component {:?}, error: {:?}",
+                    component, e
+                );
+                None
+            }
+        }
+    }
 }
 
 pub struct GeneratorBuilder {
     inner: CheckComponent,
+    check_flow_json: String,
 }
 
 impl Default for GeneratorBuilder {
     fn default() -> Self {
         Self {
+            check_flow_json: String::new(),
             inner: CheckComponent {
                 list_node_id_file: "".to_string(),
                 list_gateway_id_file: "".to_string(),
@@ -1085,6 +1795,17 @@ impl Default for GeneratorBuilder {
                 check_flows: Default::default(),
                 is_loop_check: false,
                 is_write_to_file: false,
+                is_store_report: true,
+                base_endpoint_tracker: Default::default(),
+                flow_config: None,
+                flow_config_version: 0,
+                check_flow_source: Default::default(),
+                base_endpoint_source: Default::default(),
+                check_limits: Default::default(),
+                http_client: Default::default(),
+                base_node_cache: Default::default(),
+                report_sinks: Default::default(),
+                check_history: Default::default(),
             },
         }
     }
@@ -1163,41 +1884,282 @@ impl GeneratorBuilder {
         return Ok(users);
     }
 
-    pub fn with_check_flow_file(mut self, path: String) -> Self {
-        let json = std::fs::read_to_string(&path)
-            .unwrap_or_else(|err| panic!("Unable to read `{}`: {}", path, err));
-        debug!("json: {:#?}", json);
-        let test_flow: CheckFlows = serde_json::from_str(&minify(&json)).unwrap();
+    /// Check-flow is a local path or an url, see `FlowSource`.
+    pub async fn with_check_flow_file(mut self, path: String) -> Self {
+        let source = Arc::new(FlowSource::new(&path));
+        let (test_flow, json) = load_source(&source, parse_check_flow_document)
+            .await
+            .unwrap_or_else(|err| panic!("{}", err));
         debug!("test_flow: {:#?}", test_flow);
         self.inner.check_flow_file = path;
+        self.inner.check_flow_source = source;
         self.inner.check_flows = test_flow;
+        self.check_flow_json = json;
         self
     }
+
+    pub fn format_flow_errors(path: &String, errors: &Vec<FlowError>) -> String {
+        errors
+            .iter()
+            .map(|err| format!("{}:{}", path, err))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
     pub fn with_domain(mut self, path: String) -> Self {
         self.inner.domain = path;
         self
     }
-    pub fn with_base_endpoint_file(mut self, path: String) -> Self {
-        let json = if !path.is_empty() {
-            std::fs::read_to_string(&path)
-                .unwrap_or_else(|err| panic!("Unable to read `{}`: {}", path, err))
-        } else {
-            println!("Load base endpoint from env: \n{:?}", *BASE_ENDPOINT_JSON);
-            BASE_ENDPOINT_JSON.clone()
+    /// Base-endpoint is a local path, an url or empty for env var `BASE_ENDPOINT_JSON`.
+    pub async fn with_base_endpoint_file(mut self, path: String) -> Self {
+        if path.is_empty() {
+            // Not the content, it has api keys of base endpoints
+            info!("Load base endpoint from env var BASE_ENDPOINT_JSON");
+        }
+        let source = Arc::new(FlowSource::new(&path));
+        let base_nodes = match load_source(&source, parse_base_endpoint_document).await {
+            Ok((base_nodes, _)) => base_nodes,
+            Err(err) => {
+                warn!("{}", err);
+                HashMap::new()
+            }
         };
-
-        let base_nodes: HashMap<BlockChainType, Vec<EndpointInfo>> =
-            serde_json::from_str(&minify(&json)).unwrap_or_default();
         self.inner.base_endpoint_file = path;
+        self.inner.base_endpoint_source = source;
         self.inner.base_nodes = base_nodes;
         self
     }
+
+    pub fn with_store_report(mut self, is_store_report: bool) -> Self {
+        self.inner.is_store_report = is_store_report;
+        self
+    }
+
+    /// Sign reports for the portal, e.g. with the sr25519 key of the fisherman.
+    pub fn with_report_signer(mut self, signer: Arc<dyn ReportSigner>) -> Self {
+        self.inner.report_sinks = ReportSinks::new(&CONFIG.report_sinks, Some(signer));
+        self
+    }
+
+    pub fn with_write_to_file(mut self, is_write_to_file: bool) -> Self {
+        self.inner.is_write_to_file = is_write_to_file;
+        self
+    }
+
     pub fn with_output_file(mut self, output_file: String) -> Self {
         self.inner.output_file = output_file;
         self
     }
 
     pub fn build(self) -> CheckComponent {
+        let errors = validate_base_endpoints(
+            &self.check_flow_json,
+            &self.inner.check_flows,
+            &self.inner.base_nodes,
+        );
+        if !errors.is_empty() {
+            warn!(
+                "{}",
+                Self::format_flow_errors(&self.inner.check_flow_file, &errors)
+            );
+        }
         self.inner
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn step_result() -> StepResult {
+        vec![
+            ("baseCall_blockNumber", json!("0x10")),
+            ("checkCall_blockNumber", json!(18)),
+            ("checkCall_version", json!("Geth/v1.10.17-stable")),
+            ("checkCall_peers", json!(["peer-a", "peer-b"])),
+            ("checkCall_network", json!("mainnet")),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), StepValue::from_json(value)))
+        .collect()
+    }
+
+    fn compare(operator: Value) -> Result<bool, anyhow::Error> {
+        let operator: OperatorCompare = serde_json::from_value(operator).unwrap();
+        CheckComponent::do_compare(&operator, &step_result())
+    }
+
+    fn compare_ok(operator: Value) -> bool {
+        compare(operator.clone()).unwrap_or_else(|err| panic!("{} failed: {}", operator, err))
+    }
+
+    fn compare_err(operator: Value) -> String {
+        match compare(operator.clone()) {
+            Ok(result) => panic!("{} returned {} instead of an error", operator, result),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn eq_and_neq_compare_numbers_by_value() {
+        assert!(compare_ok(
+            json!({"operator_type": "eq", "params": ["baseCall_blockNumber", "#16"]})
+        ));
+        assert!(compare_ok(
+            json!({"operator_type": "eq", "params": ["#18", "checkCall_blockNumber", "#0x12"]})
+        ));
+        assert!(!compare_ok(
+            json!({"operator_type": "eq", "params": ["baseCall_blockNumber", "checkCall_blockNumber"]})
+        ));
+        assert!(compare_ok(
+            json!({"operator_type": "neq", "params": ["baseCall_blockNumber", "checkCall_blockNumber"]})
+        ));
+        assert!(!compare_ok(
+            json!({"operator_type": "neq", "params": ["checkCall_network", "#mainnet"]})
+        ));
+    }
+
+    #[test]
+    fn ordering_operators_at_boundaries() {
+        let cases = [
+            ("gt", "#17", true),
+            ("gt", "#18", false),
+            ("gte", "#18", true),
+            ("gte", "#19", false),
+            ("lt", "#19", true),
+            ("lt", "#18", false),
+            ("lte", "#18", true),
+            ("lte", "#17", false),
+        ];
+        for (operator, literal, expected) in cases {
+            assert_eq!(
+                compare_ok(
+                    json!({"operator_type": operator, "params": ["checkCall_blockNumber", literal]})
+                ),
+                expected,
+                "18 {} {}",
+                operator,
+                literal
+            );
+        }
+        // Hex quantities are numbers
+        assert!(compare_ok(
+            json!({"operator_type": "lt", "params": ["baseCall_blockNumber", "checkCall_blockNumber"]})
+        ));
+    }
+
+    #[test]
+    fn regex_contains_and_within() {
+        assert!(compare_ok(
+            json!({"operator_type": "regex", "params": ["checkCall_version", "#Geth\\/v1\\.1[0-9]"]})
+        ));
+        assert!(!compare_ok(
+            json!({"operator_type": "regex", "params": ["checkCall_version", "#^Nethermind"]})
+        ));
+        assert!(compare_ok(
+            json!({"operator_type": "contains", "params": ["checkCall_peers", "#peer-b"]})
+        ));
+        assert!(!compare_ok(
+            json!({"operator_type": "contains", "params": ["checkCall_peers", "#peer-c"]})
+        ));
+        assert!(compare_ok(
+            json!({"operator_type": "contains", "params": ["checkCall_network", "#main"]})
+        ));
+        assert!(compare_ok(
+            json!({"operator_type": "within", "params": ["baseCall_blockNumber", "checkCall_blockNumber", "#2"]})
+        ));
+        assert!(!compare_ok(
+            json!({"operator_type": "within", "params": ["baseCall_blockNumber", "checkCall_blockNumber", "#1"]})
+        ));
+    }
+
+    #[test]
+    fn logical_operators() {
+        let is_true = json!({"operator_type": "eq", "params": ["checkCall_network", "#mainnet"]});
+        let is_false = json!({"operator_type": "eq", "params": ["checkCall_network", "#goerli"]});
+        assert!(compare_ok(
+            json!({"operator_type": "and", "params": [is_true, is_true]})
+        ));
+        assert!(!compare_ok(
+            json!({"operator_type": "and", "params": [is_true, is_false]})
+        ));
+        assert!(compare_ok(
+            json!({"operator_type": "or", "params": [is_false, is_true]})
+        ));
+        assert!(!compare_ok(
+            json!({"operator_type": "or", "params": [is_false, is_false]})
+        ));
+        assert!(compare_ok(
+            json!({"operator_type": "not", "params": is_false})
+        ));
+        assert!(!compare_ok(
+            json!({"operator_type": "not", "params": [is_true]})
+        ));
+    }
+
+    #[test]
+    fn missing_key_is_an_error() {
+        let err =
+            compare_err(json!({"operator_type": "eq", "params": ["checkCall_missing", "#1"]}));
+        assert!(err.contains("Cannot find key checkCall_missing"), "{}", err);
+        let err =
+            compare_err(json!({"operator_type": "gt", "params": ["checkCall_missing", "#1"]}));
+        assert!(err.contains("Cannot find key checkCall_missing"), "{}", err);
+        // Operands after the result of `or` is known are not looked up
+        let is_true = json!({"operator_type": "eq", "params": ["checkCall_network", "#mainnet"]});
+        let missing = json!({"operator_type": "eq", "params": ["checkCall_missing", "#1"]});
+        assert!(compare_ok(
+            json!({"operator_type": "or", "params": [is_true, missing]})
+        ));
+        compare_err(json!({"operator_type": "and", "params": [is_true, missing]}));
+    }
+
+    #[test]
+    fn non_numeric_operand_is_an_error() {
+        for operator in ["gt", "gte", "lt", "lte"] {
+            let err = compare_err(
+                json!({"operator_type": operator, "params": ["checkCall_network", "#1"]}),
+            );
+            assert!(err.contains("is not a number"), "{}: {}", operator, err);
+        }
+        let err = compare_err(
+            json!({"operator_type": "within", "params": ["checkCall_blockNumber", "checkCall_version", "#3"]}),
+        );
+        assert!(err.contains("Operator within"), "{}", err);
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        let err = compare_err(
+            json!({"operator_type": "regex", "params": ["checkCall_version", "#Geth(["]}),
+        );
+        assert!(err.contains("regex parse error"), "{}", err);
+    }
+
+    #[test]
+    fn set_action_renders_vars_once() {
+        let action: ActionSet = serde_json::from_value(json!({"vars": {
+            "block": "{{random_block baseCall_blockNumber 8}}",
+            "next": "{{add checkCall_blockNumber 1}}",
+            "id": "{{uuid}}"
+        }}))
+        .unwrap();
+        let response = CheckComponent::set_action(&action, "vars", &step_result()).unwrap();
+        assert!(response.success);
+        assert!(response.result["next"].loosely_eq(&StepValue::from(19)));
+        let block = response.result["block"].as_i128().unwrap();
+        assert!((8..=16).contains(&block), "{}", block);
+        // Later steps render the stored value instead of drawing a new one
+        let mut step_result = step_result();
+        for (key, value) in response.result {
+            step_result.insert(format!("vars_{}", key), value);
+        }
+        let first = CheckComponent::replace_string("{{vars_id}}".to_string(), &step_result);
+        let second = CheckComponent::replace_string("{{vars_id}}".to_string(), &step_result);
+        assert_eq!(first.unwrap(), second.unwrap());
+
+        let action: ActionSet =
+            serde_json::from_value(json!({"vars": {"block": "{{random_block}}"}})).unwrap();
+        assert!(CheckComponent::set_action(&action, "vars", &step_result).is_err());
+    }
+}
@@ -0,0 +1,305 @@
+use crate::check_module::base_endpoint_health::EndpointState;
+use crate::check_module::check_module::{
+    BlockChainType, CheckComponent, CheckMkReport, CheckMkStatus, ComponentInfo, Zone,
+};
+use crate::check_module::util::write_atomic;
+use crate::CONFIG;
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+// Service of the checker itself, in the local section of the monitoring host
+const SUMMARY_SERVICE: &str = "mbr_check_component";
+const BASE_ENDPOINTS_SERVICE: &str = "mbr_base_endpoints";
+
+/// Where `check-kind --checkmk <mode>` writes local checks of all components.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CheckMkMode {
+    // Agent plugin: check once, print to stdout and exit
+    Stdout,
+    // Check every `check_interval_ms` and write the output file atomically, e.g. to the spool dir
+    Spool,
+    // Check every `check_interval_ms` and serve the output at `GET /checkmk`
+    Http,
+}
+
+impl FromStr for CheckMkMode {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "stdout" => Ok(CheckMkMode::Stdout),
+            "spool" => Ok(CheckMkMode::Spool),
+            "http" => Ok(CheckMkMode::Http),
+            _ => Err(anyhow::Error::msg(format!(
+                "Unknown checkmk mode {:?}, expected stdout, spool or http",
+                mode
+            ))),
+        }
+    }
+}
+
+/// Latest local checks, shared with the check component server.
+#[derive(Clone, Debug, Default)]
+pub struct CheckMkOutput {
+    latest: Arc<RwLock<Option<String>>>,
+}
+
+impl CheckMkOutput {
+    pub fn get(&self) -> Option<String> {
+        self.latest
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn set(&self, output: String) {
+        *self.latest.write().unwrap_or_else(PoisonError::into_inner) = Some(output);
+    }
+}
+
+/// Local check section of the checker with a summary and base endpoints per chain, followed by
+/// the reports of the components. With piggyback every component is a section of its own host,
+/// named `<component type>-<id>`, otherwise its line is in the section of the checker.
+pub fn format_local_checks(
+    reports: &[(ComponentInfo, CheckMkReport)],
+    base_endpoints: &BTreeMap<BlockChainType, Vec<EndpointState>>,
+    piggyback: bool,
+) -> String {
+    let mut output = String::from("<<<local:sep(0)>>>\n");
+    let ok = reports
+        .iter()
+        .filter(|(_, report)| report.status == CheckMkStatus::Ok as u8)
+        .count();
+    let reference_failures = reports
+        .iter()
+        .filter(|(_, report)| report.reference_failure)
+        .count();
+    let _ = writeln!(
+        output,
+        "0 {} components={}|ok={}|reference_failures={} {} of {} components are ok, {} are not judged",
+        SUMMARY_SERVICE,
+        reports.len(),
+        ok,
+        reference_failures,
+        ok,
+        reports.len(),
+        reference_failures
+    );
+    for (chain, endpoints) in base_endpoints {
+        let disabled = endpoints
+            .iter()
+            .filter(|endpoint| endpoint.disabled)
+            .count();
+        let status = match disabled {
+            0 => CheckMkStatus::Ok,
+            disabled if disabled < endpoints.len() => CheckMkStatus::Warning,
+            _ => CheckMkStatus::Critical,
+        };
+        let _ = writeln!(
+            output,
+            "{} {}_{} endpoints={}|disabled={} {} of {} base endpoints are disabled",
+            status as u8,
+            BASE_ENDPOINTS_SERVICE,
+            service_name_part(chain),
+            endpoints.len(),
+            disabled,
+            disabled,
+            endpoints.len()
+        );
+    }
+    for (component, report) in reports {
+        if piggyback {
+            let _ = writeln!(
+                output,
+                "<<<<{}-{}>>>>\n<<<local:sep(0)>>>\n{}\n<<<<>>>>",
+                component.component_type.to_string(),
+                service_name_part(&component.id),
+                report.to_string()
+            );
+        } else {
+            let _ = writeln!(output, "{}", report.to_string());
+        }
+    }
+    output
+}
+
+// Service and host names cannot contain spaces
+fn service_name_part(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_whitespace() { '_' } else { c })
+        .collect()
+}
+
+impl CheckComponent {
+    /// Check all components of the lists and format their reports as local checks.
+    pub async fn checkmk_local_checks(&mut self) -> Result<String, anyhow::Error> {
+        self.refresh_flow_config();
+        self.reload_components_list(None, &Zone::GB).await?;
+        let reports = self.check_components(&CONFIG.check_task_list_all).await?;
+        Ok(format_local_checks(
+            &reports,
+            &self.base_endpoint_state(),
+            CONFIG.checkmk_piggyback,
+        ))
+    }
+
+    /// Check components every `check_interval_ms`. Local checks are written to `output_file`
+    /// if `is_write_to_file` and kept for the server.
+    pub async fn loop_checkmk(mut self, output: CheckMkOutput) {
+        loop {
+            match self.checkmk_local_checks().await {
+                Ok(local_checks) => {
+                    if self.is_write_to_file {
+                        match write_atomic(Path::new(&self.output_file), &local_checks) {
+                            Ok(_) => info!("Wrote checkmk local checks to {}", self.output_file),
+                            Err(err) => {
+                                warn!(
+                                    "Cannot write checkmk local checks to {}: {}",
+                                    self.output_file, err
+                                )
+                            }
+                        }
+                    }
+                    output.set(local_checks);
+                }
+                Err(err) => warn!("Cannot check components for checkmk: {}", err),
+            }
+            tokio::time::sleep(Duration::from_millis(CONFIG.check_interval_ms)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check_module::base_endpoint_health::EndpointStats;
+    use crate::check_module::check_module::{CheckMkMetric, ComponentType};
+    use serde_json::json;
+
+    fn component(id: &str, component_type: ComponentType) -> ComponentInfo {
+        ComponentInfo {
+            id: id.to_string(),
+            component_type,
+            ..Default::default()
+        }
+    }
+
+    fn report(status: CheckMkStatus, service_name: &str, reference_failure: bool) -> CheckMkReport {
+        CheckMkReport {
+            status: status as u8,
+            service_name: service_name.to_string(),
+            metric: CheckMkMetric {
+                metric: vec![
+                    ("checkCall_response_time_ms".to_string(), json!(12)),
+                    ("checkCall_chain".to_string(), json!("eth")),
+                ]
+                .into_iter()
+                .collect(),
+            },
+            status_detail: "first line\nsecond line".to_string(),
+            reference_failure,
+            ..Default::default()
+        }
+    }
+
+    fn endpoint(disabled: bool) -> EndpointState {
+        EndpointState {
+            url: "https://base.example.com".to_string(),
+            stats: EndpointStats::default(),
+            success_percent: None,
+            broken: false,
+            disabled,
+            disabled_for_ms: 0,
+        }
+    }
+
+    fn inputs() -> (
+        Vec<(ComponentInfo, CheckMkReport)>,
+        BTreeMap<BlockChainType, Vec<EndpointState>>,
+    ) {
+        let reports = vec![
+            (
+                component("node 1", ComponentType::Node),
+                report(CheckMkStatus::Ok, "node-1", false),
+            ),
+            (
+                component("gw1", ComponentType::Gateway),
+                report(CheckMkStatus::Unknown, "gateway-gw1", true),
+            ),
+        ];
+        let base_endpoints = vec![
+            ("dot".to_string(), vec![endpoint(true)]),
+            ("eth".to_string(), vec![endpoint(false), endpoint(true)]),
+            ("near sandbox".to_string(), vec![endpoint(false)]),
+        ]
+        .into_iter()
+        .collect();
+        (reports, base_endpoints)
+    }
+
+    const HEADER: &str = "<<<local:sep(0)>>>
+0 mbr_check_component components=2|ok=1|reference_failures=1 1 of 2 components are ok, 1 are not judged
+2 mbr_base_endpoints_dot endpoints=1|disabled=1 1 of 1 base endpoints are disabled
+1 mbr_base_endpoints_eth endpoints=2|disabled=1 1 of 2 base endpoints are disabled
+0 mbr_base_endpoints_near_sandbox endpoints=1|disabled=0 0 of 1 base endpoints are disabled
+";
+
+    #[test]
+    fn components_in_checker_section() {
+        let (reports, base_endpoints) = inputs();
+        let output = format_local_checks(&reports, &base_endpoints, false);
+        assert_eq!(
+            output,
+            format!(
+                "{}{}",
+                HEADER,
+                "0 node-1 checkCall_response_time_ms=12 first line second line
+3 gateway-gw1 checkCall_response_time_ms=12 first line second line
+"
+            )
+        );
+    }
+
+    #[test]
+    fn components_in_piggyback_sections() {
+        let (reports, base_endpoints) = inputs();
+        let output = format_local_checks(&reports, &base_endpoints, true);
+        assert_eq!(
+            output,
+            format!(
+                "{}{}",
+                HEADER,
+                "<<<<node-node_1>>>>
+<<<local:sep(0)>>>
+0 node-1 checkCall_response_time_ms=12 first line second line
+<<<<>>>>
+<<<<gateway-gw1>>>>
+<<<local:sep(0)>>>
+3 gateway-gw1 checkCall_response_time_ms=12 first line second line
+<<<<>>>>
+"
+            )
+        );
+    }
+
+    #[test]
+    fn no_components() {
+        let output = format_local_checks(&[], &BTreeMap::new(), true);
+        assert_eq!(
+            output,
+            "<<<local:sep(0)>>>
+0 mbr_check_component components=0|ok=0|reference_failures=0 0 of 0 components are ok, 0 are not judged
+"
+        );
+        assert_eq!(
+            "stdout".parse::<CheckMkMode>().unwrap(),
+            CheckMkMode::Stdout
+        );
+        assert!("file".parse::<CheckMkMode>().is_err());
+    }
+}
@@ -0,0 +1,442 @@
+use crate::check_module::check_module::{
+    ActionCall, ActionResponse, CheckComponent, CheckMkStatus, EndpointInfo, RenderedRequest,
+    StepResult, STATUS_CODE_KEY,
+};
+use crate::check_module::http_client::HANDSHAKE_TIME_KEY;
+use crate::check_module::step_value::StepValue;
+use crate::check_module::util::lock;
+use crate::check_module::ws_action::CONNECT_TIME_KEY;
+use crate::CONFIG;
+use futures_util::future::join_all;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+// Keys added to step result of a consensus call
+pub const DISAGREEING_ENDPOINTS_KEY: &str = "disagreeing_endpoints";
+pub const FAILED_ENDPOINTS_KEY: &str = "failed_endpoints";
+pub const ANSWERED_ENDPOINTS_KEY: &str = "answered_endpoints";
+
+/// Query several base endpoints and take the majority (or median) answer per field
+/// instead of the first answer.
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Consensus {
+    // Number of base endpoints queried (first ones of the chain), all if not set
+    #[serde(default)]
+    pub(crate) endpoints: Option<usize>,
+    // Number of answers which must agree on every field, majority of queried endpoints if not set
+    #[serde(default)]
+    pub(crate) quorum: Option<usize>,
+    // Numeric fields decided by median, e.g. block number of nodes which are a few blocks apart
+    #[serde(default)]
+    pub(crate) median_fields: Vec<String>,
+    // Max distance from the median for an answer to agree
+    #[serde(default)]
+    tolerance: f64,
+}
+
+// Chosen value of a field and indexes of answers agreeing with it
+struct FieldDecision {
+    value: StepValue,
+    agreeing: Vec<usize>,
+}
+
+impl Consensus {
+    pub(crate) fn required_answers(&self, queried: usize) -> usize {
+        self.quorum.unwrap_or(queried / 2 + 1)
+    }
+
+    fn majority(values: &[&StepValue]) -> FieldDecision {
+        let mut groups: Vec<FieldDecision> = Vec::new();
+        for (index, value) in values.iter().enumerate() {
+            match groups
+                .iter_mut()
+                .find(|group| group.value.loosely_eq(value))
+            {
+                Some(group) => group.agreeing.push(index),
+                None => groups.push(FieldDecision {
+                    value: (*value).clone(),
+                    agreeing: vec![index],
+                }),
+            }
+        }
+        // The first group wins a tie, so the healthiest base endpoint decides
+        let mut winner = 0;
+        for (index, group) in groups.iter().enumerate() {
+            if group.agreeing.len() > groups[winner].agreeing.len() {
+                winner = index;
+            }
+        }
+        groups.swap_remove(winner)
+    }
+
+    fn median(&self, field: &str, values: &[&StepValue]) -> Result<FieldDecision, anyhow::Error> {
+        let mut sorted = values.to_vec();
+        if let Some(value) = sorted.iter().find(|value| !value.is_number()) {
+            return Err(anyhow::Error::msg(format!(
+                "median field {} is not a number: {}",
+                field, value
+            )));
+        }
+        sorted.sort_by(|first, second| first.compare_number(second).unwrap_or(Ordering::Equal));
+        // Lower median is an actual answer, hex values keep their format
+        let value = sorted[(sorted.len() - 1) / 2].clone();
+        let median = value.as_f64().unwrap_or_default();
+        let agreeing = values
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| {
+                (value.as_f64().unwrap_or_default() - median).abs() <= self.tolerance
+            })
+            .map(|(index, _)| index)
+            .collect();
+        Ok(FieldDecision { value, agreeing })
+    }
+
+    /// Combine answers field by field. Returns the result and indexes of disagreeing answers.
+    fn combine(
+        &self,
+        answers: &[&StepResult],
+        required: usize,
+    ) -> Result<(StepResult, BTreeSet<usize>), anyhow::Error> {
+        let mut fields = answers
+            .iter()
+            .flat_map(|answer| answer.keys())
+            .collect::<BTreeSet<_>>();
+        // Measurements of each answer are reported, not voted on
+        let measured = Self::measured_keys();
+        fields.retain(|field| !measured.contains(&field.as_str()));
+        let mut result = StepResult::new();
+        let mut disagreeing = BTreeSet::new();
+        for field in fields {
+            let values = answers
+                .iter()
+                .map(|answer| answer.get(field).unwrap_or(&StepValue::Null))
+                .collect::<Vec<_>>();
+            let decision = match self.median_fields.contains(field) {
+                true => self.median(field, &values)?,
+                false => Self::majority(&values),
+            };
+            if decision.agreeing.len() < required {
+                return Err(anyhow::Error::msg(format!(
+                    "no quorum for field {}: {} of {} required answers agree",
+                    field,
+                    decision.agreeing.len(),
+                    required
+                )));
+            }
+            disagreeing
+                .extend((0..answers.len()).filter(|index| !decision.agreeing.contains(index)));
+            result.insert(field.clone(), decision.value);
+        }
+        for key in measured {
+            let mut measurements = answers
+                .iter()
+                .filter_map(|answer| answer.get(key))
+                .filter_map(|value| value.as_i128())
+                .collect::<Vec<_>>();
+            measurements.sort_unstable();
+            if let Some(measurement) = measurements.get(measurements.len() / 2) {
+                result.insert(key.to_string(), StepValue::from(*measurement as u64));
+            }
+        }
+        Ok((result, disagreeing))
+    }
+
+    // Timings and the http status differ between endpoints, the median is reported
+    fn measured_keys() -> [&'static str; 4] {
+        [
+            CONFIG.response_time_key.as_str(),
+            CONNECT_TIME_KEY,
+            HANDSHAKE_TIME_KEY,
+            STATUS_CODE_KEY,
+        ]
+    }
+}
+
+impl CheckComponent {
+    /// Call base endpoints of the chain concurrently and combine their answers.
+    pub(crate) async fn call_action_consensus(
+        &self,
+        action: &ActionCall,
+        consensus: &Consensus,
+        base_endpoints: &[&EndpointInfo],
+        return_name: &String,
+        request: &RenderedRequest,
+    ) -> Result<ActionResponse, anyhow::Error> {
+        let endpoints = &base_endpoints[..consensus
+            .endpoints
+            .unwrap_or(base_endpoints.len())
+            .min(base_endpoints.len())];
+        let required = consensus.required_answers(endpoints.len());
+        let responses =
+            join_all(endpoints.iter().map(|endpoint| {
+                self.call_action_base_node(action, return_name, request, endpoint)
+            }))
+            .await;
+
+        let mut answered = Vec::new();
+        let mut answers = Vec::new();
+        let mut failed = Vec::new();
+        for (endpoint, response) in endpoints.iter().copied().zip(responses.iter()) {
+            match response {
+                Ok(response) => {
+                    answered.push(endpoint);
+                    answers.push(&response.result);
+                }
+                Err(err) => {
+                    debug!("base endpoint {} failed: {}", endpoint.url, err);
+                    failed.push(endpoint);
+                }
+            }
+        }
+        if answers.len() < required {
+            return Err(anyhow::Error::msg(format!(
+                "only {} of {} required base endpoints answered",
+                answers.len(),
+                required
+            )));
+        }
+        let combined = consensus.combine(&answers, required);
+        if let Ok((_, disagreeing)) = &combined {
+            self.track_disagreements(&answered, disagreeing);
+        }
+        let (mut result, disagreeing) = combined?;
+
+        let urls = |endpoints: Vec<&EndpointInfo>| {
+            StepValue::Array(
+                endpoints
+                    .iter()
+                    .map(|endpoint| Value::from(endpoint.url.clone()))
+                    .collect(),
+            )
+        };
+        let disagreeing_endpoints = disagreeing
+            .iter()
+            .map(|index| answered[*index])
+            .collect::<Vec<_>>();
+        let message = format!(
+            "consensus {}: {} of {} base endpoints answered, disagreeing: {:?}, failed: {:?}",
+            return_name,
+            answers.len(),
+            endpoints.len(),
+            disagreeing_endpoints
+                .iter()
+                .map(|endpoint| &endpoint.url)
+                .collect::<Vec<_>>(),
+            failed
+                .iter()
+                .map(|endpoint| &endpoint.url)
+                .collect::<Vec<_>>()
+        );
+        result.insert(
+            ANSWERED_ENDPOINTS_KEY.to_string(),
+            StepValue::from(answers.len() as u64),
+        );
+        result.insert(
+            DISAGREEING_ENDPOINTS_KEY.to_string(),
+            urls(disagreeing_endpoints),
+        );
+        result.insert(FAILED_ENDPOINTS_KEY.to_string(), urls(failed));
+        debug!("{}", message);
+        Ok(ActionResponse {
+            success: true,
+            conclude: CheckMkStatus::Ok,
+            return_name: return_name.clone(),
+            result,
+            message,
+        })
+    }
+
+    // Failed calls are tracked by `call_action_base_node`
+    fn track_disagreements(&self, answered: &[&EndpointInfo], disagreeing: &BTreeSet<usize>) {
+        let mut tracker = lock(&self.base_endpoint_tracker);
+        for index in disagreeing {
+            let endpoint = answered[*index];
+            if tracker.record_disagreement(&endpoint.url) {
+                warn!(
+                    "Base endpoint {} in {} looks broken, it failed or disagreed with other base endpoints in most queries",
+                    endpoint.url, self.base_endpoint_file
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn answer(value: Value) -> StepResult {
+        value
+            .as_object()
+            .unwrap()
+            .iter()
+            .map(|(key, value)| (key.clone(), StepValue::from_json(value.clone())))
+            .collect()
+    }
+
+    fn consensus(value: Value) -> Consensus {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn combine(
+        consensus: &Consensus,
+        answers: &[StepResult],
+    ) -> Result<(StepResult, BTreeSet<usize>), anyhow::Error> {
+        let answers = answers.iter().collect::<Vec<_>>();
+        consensus.combine(&answers, consensus.required_answers(answers.len()))
+    }
+
+    fn disagreeing(indexes: &[usize]) -> BTreeSet<usize> {
+        indexes.iter().cloned().collect()
+    }
+
+    #[test]
+    fn required_answers() {
+        assert_eq!(consensus(json!({})).required_answers(1), 1);
+        assert_eq!(consensus(json!({})).required_answers(3), 2);
+        assert_eq!(consensus(json!({})).required_answers(4), 3);
+        assert_eq!(consensus(json!({"quorum": 1})).required_answers(4), 1);
+    }
+
+    #[test]
+    fn majority_loosely_compares_values() {
+        let answers = [
+            answer(json!({"hash": "0xab", "number": "0x10"})),
+            answer(json!({"hash": "0xab", "number": 16})),
+            answer(json!({"hash": "0xcd", "number": "16"})),
+        ];
+        let (result, disagreeing_answers) = combine(&consensus(json!({})), &answers).unwrap();
+        assert_eq!(result["hash"], StepValue::from_json(json!("0xab")));
+        // The value of the first agreeing answer is kept
+        assert_eq!(result["number"], StepValue::from_json(json!("0x10")));
+        assert_eq!(disagreeing_answers, disagreeing(&[2]));
+    }
+
+    #[test]
+    fn first_group_wins_a_tie() {
+        let answers = [
+            answer(json!({"hash": "0xcd"})),
+            answer(json!({"hash": "0xab"})),
+            answer(json!({"hash": "0xab"})),
+            answer(json!({"hash": "0xcd"})),
+        ];
+        let (result, disagreeing_answers) =
+            combine(&consensus(json!({"quorum": 2})), &answers).unwrap();
+        assert_eq!(result["hash"], StepValue::from_json(json!("0xcd")));
+        assert_eq!(disagreeing_answers, disagreeing(&[1, 2]));
+    }
+
+    #[test]
+    fn missing_fields_count_as_null() {
+        let answers = [
+            answer(json!({"hash": "0xab", "extra": 1})),
+            answer(json!({"hash": "0xab"})),
+            answer(json!({"hash": "0xab"})),
+        ];
+        let (result, disagreeing_answers) = combine(&consensus(json!({})), &answers).unwrap();
+        assert_eq!(result["extra"], StepValue::Null);
+        assert_eq!(disagreeing_answers, disagreeing(&[0]));
+
+        let err = combine(&consensus(json!({"quorum": 3})), &answers).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no quorum for field extra: 2 of 3 required answers agree"
+        );
+    }
+
+    #[test]
+    fn median_within_tolerance() {
+        let answers = [
+            answer(json!({"number": "0x64"})),
+            answer(json!({"number": 102})),
+            answer(json!({"number": 98})),
+            answer(json!({"number": 97})),
+        ];
+        // Lower median of 97, 98, 100, 102 is 98
+        let median = consensus(json!({"median_fields": ["number"], "tolerance": 2.0}));
+        let (result, disagreeing_answers) = combine(&median, &answers).unwrap();
+        assert_eq!(result["number"], StepValue::from_json(json!(98)));
+        // 100 and 97 are at the tolerance boundary, 102 is past it
+        assert_eq!(disagreeing_answers, disagreeing(&[1]));
+
+        let strict = consensus(json!({"median_fields": ["number"], "quorum": 1}));
+        let (_, disagreeing_answers) = combine(&strict, &answers).unwrap();
+        assert_eq!(disagreeing_answers, disagreeing(&[0, 1, 3]));
+        let err = combine(
+            &consensus(json!({"median_fields": ["number"], "tolerance": 1.0})),
+            &answers,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no quorum for field number: 2 of 3 required answers agree"
+        );
+    }
+
+    #[test]
+    fn median_keeps_hex_format() {
+        let answers = [
+            answer(json!({"number": "0x10"})),
+            answer(json!({"number": 15})),
+            answer(json!({"number": "0x11"})),
+        ];
+        let median = consensus(json!({"median_fields": ["number"], "tolerance": 1.0}));
+        let (result, disagreeing_answers) = combine(&median, &answers).unwrap();
+        assert_eq!(result["number"], StepValue::from_json(json!("0x10")));
+        assert!(disagreeing_answers.is_empty());
+    }
+
+    #[test]
+    fn median_of_missing_or_non_numeric_field_is_an_error() {
+        let median = consensus(json!({"median_fields": ["number"]}));
+        let answers = [answer(json!({"number": 1})), answer(json!({}))];
+        let err = combine(&median, &answers).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("median field number is not a number"));
+        let answers = [
+            answer(json!({"number": 1})),
+            answer(json!({"number": "latest"})),
+        ];
+        assert!(combine(&median, &answers).is_err());
+    }
+
+    #[test]
+    fn response_time_is_not_voted_on() {
+        let key = CONFIG.response_time_key.clone();
+        let answers = [
+            answer(json!({ key.as_str(): 30, "hash": "0xab" })),
+            answer(json!({ key.as_str(): 10, "hash": "0xab" })),
+            answer(json!({ key.as_str(): 20, "hash": "0xab" })),
+        ];
+        let (result, disagreeing_answers) = combine(&consensus(json!({})), &answers).unwrap();
+        assert_eq!(result[&key], StepValue::from(20));
+        assert!(disagreeing_answers.is_empty());
+    }
+
+    #[test]
+    fn connection_timings_and_status_are_not_voted_on() {
+        let answers = [
+            answer(json!({"connect_time_ms": 31, "handshake_time_ms": 70, "status_code": 200})),
+            answer(json!({"connect_time_ms": 12, "handshake_time_ms": 45, "status_code": 200})),
+            answer(json!({"connect_time_ms": 25, "handshake_time_ms": 52, "status_code": 503})),
+        ];
+        let (result, disagreeing_answers) = combine(&consensus(json!({})), &answers).unwrap();
+        assert_eq!(result[CONNECT_TIME_KEY], StepValue::from(25));
+        assert_eq!(result[HANDSHAKE_TIME_KEY], StepValue::from(52));
+        assert_eq!(result[STATUS_CODE_KEY], StepValue::from(200));
+        assert!(disagreeing_answers.is_empty());
+        let (result, _) = combine(&consensus(json!({})), &answers[..1]).unwrap();
+        assert_eq!(result.len(), 3);
+        // Answers without connection timings have none
+        let (result, _) =
+            combine(&consensus(json!({})), &[answer(json!({"hash": "0xab"}))]).unwrap();
+        assert!(!result.contains_key(CONNECT_TIME_KEY));
+    }
+}
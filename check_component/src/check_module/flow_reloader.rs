@@ -0,0 +1,228 @@
+use crate::check_module::check_module::{
+    BlockChainType, CheckComponent, CheckFlows, EndpointInfo, GeneratorBuilder,
+};
+use crate::check_module::flow_source::FlowSource;
+use crate::check_module::flow_validator::{parse_check_flows, parse_json, validate_base_endpoints};
+use crate::CONFIG;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{watch, Mutex};
+
+/// Check flows and base endpoints which can be reloaded while running.
+#[derive(Clone, Debug, Default)]
+pub struct FlowConfig {
+    // Increased on every reload
+    pub version: u64,
+    pub check_flows: CheckFlows,
+    pub base_nodes: HashMap<BlockChainType, Vec<EndpointInfo>>,
+}
+
+/// Parse and validate check-flow json of the source at location.
+pub fn parse_check_flow_document(
+    location: &String,
+    json: &str,
+) -> Result<CheckFlows, anyhow::Error> {
+    parse_check_flows(json).map_err(|errors| {
+        anyhow::Error::msg(format!(
+            "Invalid check flow `{}`:\n{}",
+            location,
+            GeneratorBuilder::format_flow_errors(location, &errors)
+        ))
+    })
+}
+
+/// Parse base-endpoint json of the source at location.
+pub fn parse_base_endpoint_document(
+    location: &String,
+    json: &str,
+) -> Result<HashMap<BlockChainType, Vec<EndpointInfo>>, anyhow::Error> {
+    parse_json(json).map_err(|err| {
+        anyhow::Error::msg(format!(
+            "Invalid base endpoint `{}`:\n{}",
+            location,
+            GeneratorBuilder::format_flow_errors(location, &vec![err])
+        ))
+    })
+}
+
+/// Fetch and parse a source. A valid document of an url is cached, an url which cannot be
+/// fetched or is invalid falls back to its last cached document.
+pub async fn load_source<T>(
+    source: &FlowSource,
+    parse: fn(&String, &str) -> Result<T, anyhow::Error>,
+) -> Result<(T, String), anyhow::Error> {
+    let loaded = match source.fetch().await {
+        Ok(fetched) => {
+            parse(source.location(), &fetched.content).map(|value| (value, fetched.content))
+        }
+        Err(err) => Err(err),
+    };
+    match loaded {
+        Ok((value, content)) => {
+            source.save_known_good(&content).await;
+            Ok((value, content))
+        }
+        Err(err) if source.is_url() => {
+            warn!("{}, use cached `{}`", err, source.location());
+            let content = source
+                .load_known_good()
+                .map_err(|cache_err| anyhow::Error::msg(format!("{}. {}", err, cache_err)))?;
+            let value = parse(source.location(), &content)?;
+            Ok((value, content))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+impl FlowConfig {
+    pub fn from_documents(
+        check_flow_source: &FlowSource,
+        check_flow_json: &str,
+        base_endpoint_source: &FlowSource,
+        base_endpoint_json: &str,
+        version: u64,
+    ) -> Result<Self, anyhow::Error> {
+        let check_flows = parse_check_flow_document(check_flow_source.location(), check_flow_json)?;
+        let base_nodes =
+            parse_base_endpoint_document(base_endpoint_source.location(), base_endpoint_json)?;
+        let errors = validate_base_endpoints(check_flow_json, &check_flows, &base_nodes);
+        if !errors.is_empty() {
+            warn!(
+                "{}",
+                GeneratorBuilder::format_flow_errors(check_flow_source.location(), &errors)
+            );
+        }
+        Ok(FlowConfig {
+            version,
+            check_flows,
+            base_nodes,
+        })
+    }
+}
+
+impl CheckComponent {
+    /// Use the latest reloaded flow config. Call it between checks, so that a running check
+    /// keeps the version it started with. Returns true if the config changed.
+    pub fn refresh_flow_config(&mut self) -> bool {
+        let config = match &self.flow_config {
+            Some(receiver) => receiver.borrow().clone(),
+            None => return false,
+        };
+        if config.version == self.flow_config_version {
+            return false;
+        }
+        info!("Use check flow config version {}", config.version);
+        self.check_flows = config.check_flows.clone();
+        self.base_nodes = config.base_nodes.clone();
+        self.flow_config_version = config.version;
+        true
+    }
+}
+
+/// Reload check-flow and base-endpoint sources when they are modified, on SIGHUP or on request.
+#[derive(Debug)]
+pub struct FlowReloader {
+    check_flow_source: Arc<FlowSource>,
+    base_endpoint_source: Arc<FlowSource>,
+    sender: watch::Sender<Arc<FlowConfig>>,
+    reload_lock: Mutex<()>,
+}
+
+impl FlowReloader {
+    /// Create reloader for the sources of the check component and subscribe it to reloads.
+    pub fn new(check_component: &mut CheckComponent) -> Arc<Self> {
+        let config = FlowConfig {
+            version: check_component.flow_config_version,
+            check_flows: check_component.check_flows.clone(),
+            base_nodes: check_component.base_nodes.clone(),
+        };
+        let (sender, receiver) = watch::channel(Arc::new(config));
+        check_component.flow_config = Some(receiver);
+        Arc::new(FlowReloader {
+            check_flow_source: check_component.check_flow_source.clone(),
+            base_endpoint_source: check_component.base_endpoint_source.clone(),
+            sender,
+            reload_lock: Mutex::new(()),
+        })
+    }
+
+    /// Receiver of reloaded configs, e.g. for another copy of the check component.
+    pub fn subscribe(&self) -> watch::Receiver<Arc<FlowConfig>> {
+        self.sender.subscribe()
+    }
+
+    /// Load and validate the sources, then publish them. On error the current config is kept
+    /// until the sources are modified again. Returns the new version.
+    pub async fn reload(&self) -> Result<u64, anyhow::Error> {
+        let version = self.reload_sources(true).await?;
+        Ok(version.unwrap_or(self.sender.borrow().version))
+    }
+
+    // Returns None if sources are not modified and reload is not forced
+    async fn reload_sources(&self, force: bool) -> Result<Option<u64>, anyhow::Error> {
+        let _reload_lock = self.reload_lock.lock().await;
+        let check_flow = self.check_flow_source.fetch().await?;
+        let base_endpoint = self.base_endpoint_source.fetch().await?;
+        if !force && !check_flow.changed && !base_endpoint.changed {
+            return Ok(None);
+        }
+        let version = self.sender.borrow().version + 1;
+        let config = FlowConfig::from_documents(
+            &self.check_flow_source,
+            &check_flow.content,
+            &self.base_endpoint_source,
+            &base_endpoint.content,
+            version,
+        )?;
+        self.check_flow_source
+            .save_known_good(&check_flow.content)
+            .await;
+        self.base_endpoint_source
+            .save_known_good(&base_endpoint.content)
+            .await;
+        let _ = self.sender.send(Arc::new(config));
+        info!(
+            "Reloaded check flow `{}` and base endpoint `{}`, version {}",
+            self.check_flow_source.location(),
+            self.base_endpoint_source.location(),
+            version
+        );
+        Ok(Some(version))
+    }
+
+    async fn reload_and_log(&self, force: bool) {
+        if let Err(err) = self.reload_sources(force).await {
+            warn!("Reload check flow failed, keep current version: {}", err);
+        }
+    }
+
+    /// Poll the sources every `flow_reload_interval_ms` (0 disables polling) and reload on SIGHUP.
+    /// Urls are polled with conditional requests.
+    pub async fn watch(self: Arc<Self>) {
+        let is_polling = CONFIG.flow_reload_interval_ms > 0;
+        let mut interval =
+            tokio::time::interval(Duration::from_millis(CONFIG.flow_reload_interval_ms.max(1)));
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(err) => {
+                warn!("Cannot listen to SIGHUP: {}", err);
+                None
+            }
+        };
+        loop {
+            tokio::select! {
+                _ = interval.tick(), if is_polling => {
+                    self.reload_and_log(false).await;
+                }
+                Some(_) = async { hangup.as_mut()?.recv().await }, if hangup.is_some() => {
+                    info!("Received SIGHUP, reload check flow");
+                    self.reload_and_log(true).await;
+                }
+                else => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use crate::check_module::util::write_atomic;
+use crate::{BASE_ENDPOINT_JSON, CONFIG};
+use log::{debug, warn};
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Content of a source and whether it changed since the previous fetch.
+#[derive(Clone, Debug)]
+pub struct Fetched {
+    pub content: String,
+    pub changed: bool,
+}
+
+/// Check-flow or base-endpoint document.
+///
+/// The location is a local path, an http(s) url or empty for env var `BASE_ENDPOINT_JSON`.
+/// Urls are fetched over verified TLS with `If-None-Match`/`If-Modified-Since` and checked
+/// against a pinned sha256 checksum, given as url fragment (`#sha256=<hex>`) or in
+/// `flow_checksums` of the config. The last valid document of an url is cached in
+/// `flow_cache_dir` and used when the url cannot be fetched.
+#[derive(Debug, Default)]
+pub struct FlowSource {
+    location: String,
+    state: Mutex<SourceState>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct SourceState {
+    #[serde(default)]
+    etag: Option<String>,
+    #[serde(default)]
+    last_modified: Option<String>,
+    // Modified time of a local file
+    #[serde(skip)]
+    modified: Option<SystemTime>,
+    #[serde(skip)]
+    content: Option<String>,
+}
+
+impl FlowSource {
+    pub fn new(location: &str) -> Self {
+        let mut source = FlowSource {
+            location: location.to_string(),
+            state: Mutex::new(SourceState::default()),
+        };
+        // Continue conditional requests from the cached document
+        if source.is_url() {
+            if let (Ok(meta), Ok(content)) = (
+                std::fs::read_to_string(source.cache_path("meta.json")),
+                source.load_known_good(),
+            ) {
+                let mut state: SourceState = serde_json::from_str(&meta).unwrap_or_default();
+                state.content = Some(content);
+                source.state = Mutex::new(state);
+            }
+        }
+        source
+    }
+
+    pub fn location(&self) -> &String {
+        &self.location
+    }
+
+    pub fn is_url(&self) -> bool {
+        self.location.starts_with("http://") || self.location.starts_with("https://")
+    }
+
+    pub async fn fetch(&self) -> Result<Fetched, anyhow::Error> {
+        let mut state = self.state.lock().await;
+        if self.location.is_empty() {
+            let changed = state.content.is_none();
+            let content = state.content.get_or_insert(BASE_ENDPOINT_JSON.clone());
+            return Ok(Fetched {
+                content: content.clone(),
+                changed,
+            });
+        }
+        if !self.is_url() {
+            return self.fetch_file(&mut state);
+        }
+        self.fetch_url(&mut state).await
+    }
+
+    fn fetch_file(&self, state: &mut SourceState) -> Result<Fetched, anyhow::Error> {
+        let modified = std::fs::metadata(&self.location)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if let (Some(content), true) = (&state.content, modified == state.modified) {
+            return Ok(Fetched {
+                content: content.clone(),
+                changed: false,
+            });
+        }
+        let content = std::fs::read_to_string(&self.location).map_err(|err| {
+            anyhow::Error::msg(format!("Unable to read `{}`: {}", self.location, err))
+        })?;
+        state.modified = modified;
+        state.content = Some(content.clone());
+        Ok(Fetched {
+            content,
+            changed: true,
+        })
+    }
+
+    async fn fetch_url(&self, state: &mut SourceState) -> Result<Fetched, anyhow::Error> {
+        let client = reqwest::Client::builder().timeout(FETCH_TIMEOUT).build()?;
+        let mut request = client.get(&self.location);
+        if state.content.is_some() {
+            if let Some(etag) = &state.etag {
+                request = request.header(IF_NONE_MATCH, etag.as_str());
+            }
+            if let Some(last_modified) = &state.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+            }
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            debug!("{} is not modified", self.location);
+            if let Some(content) = &state.content {
+                return Ok(Fetched {
+                    content: content.clone(),
+                    changed: false,
+                });
+            }
+        }
+        let response = response.error_for_status()?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        let content = response.text().await?;
+        self.verify_checksum(&content)?;
+
+        let changed = state.content.as_ref() != Some(&content);
+        state.etag = etag;
+        state.last_modified = last_modified;
+        state.content = Some(content.clone());
+        Ok(Fetched { content, changed })
+    }
+
+    // Pinned checksum from url fragment, otherwise from config. A checksum is never fetched,
+    // it would come from the same server as the document.
+    fn expected_checksum(&self) -> Result<Option<String>, anyhow::Error> {
+        let url = Url::parse(&self.location)?;
+        let checksum = url
+            .fragment()
+            .and_then(|fragment| fragment.strip_prefix("sha256="))
+            .or_else(|| {
+                CONFIG
+                    .flow_checksums
+                    .get(&self.location)
+                    .map(String::as_str)
+            });
+        Ok(checksum.map(|checksum| checksum.trim().to_lowercase()))
+    }
+
+    fn verify_checksum(&self, content: &str) -> Result<(), anyhow::Error> {
+        match self.expected_checksum()? {
+            Some(expected) => {
+                let actual = hex::encode(Sha256::digest(content.as_bytes()));
+                if actual != expected {
+                    return Err(anyhow::Error::msg(format!(
+                        "Checksum of `{}` is {}, expected {}",
+                        self.location, actual, expected
+                    )));
+                }
+                Ok(())
+            }
+            None if CONFIG.flow_checksum_required => Err(anyhow::Error::msg(format!(
+                "There is no pinned checksum for `{}`",
+                self.location
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn cache_path(&self, extension: &str) -> PathBuf {
+        let name = self
+            .location
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        PathBuf::from(&CONFIG.flow_cache_dir).join(format!("{}.{}", name, extension))
+    }
+
+    /// Cache the document of an url after it is validated.
+    pub async fn save_known_good(&self, content: &str) {
+        if !self.is_url() {
+            return;
+        }
+        let meta = {
+            let state = self.state.lock().await;
+            serde_json::to_string(&*state).unwrap_or_default()
+        };
+        let result = std::fs::create_dir_all(&CONFIG.flow_cache_dir)
+            .and_then(|_| write_atomic(&self.cache_path("json"), content))
+            .and_then(|_| write_atomic(&self.cache_path("meta.json"), &meta));
+        if let Err(err) = result {
+            warn!("Cannot cache `{}`: {}", self.location, err);
+        }
+    }
+
+    pub fn load_known_good(&self) -> Result<String, anyhow::Error> {
+        let path = self.cache_path("json");
+        std::fs::read_to_string(&path).map_err(|err| {
+            anyhow::Error::msg(format!(
+                "There is no cached `{}` in {:?}: {}",
+                self.location, path, err
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &str = "{}";
+    // sha256 of CONTENT
+    const CHECKSUM: &str = "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a";
+
+    #[test]
+    fn checksum_pinned_in_fragment() {
+        let url = format!("https://config.example.com/flow.json#sha256={}", CHECKSUM);
+        assert!(FlowSource::new(&url).verify_checksum(CONTENT).is_ok());
+        let url = format!(
+            "https://config.example.com/flow.json#sha256={}",
+            CHECKSUM.to_uppercase()
+        );
+        assert!(FlowSource::new(&url).verify_checksum(CONTENT).is_ok());
+        let err = FlowSource::new(&url).verify_checksum("[]").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Checksum of `https://config.example.com/"));
+    }
+
+    #[test]
+    fn checksum_is_not_fetched() {
+        let source = FlowSource::new("https://config.example.com/flow.json");
+        assert_eq!(source.expected_checksum().unwrap(), None);
+        assert_eq!(
+            source.verify_checksum(CONTENT).is_ok(),
+            !CONFIG.flow_checksum_required
+        );
+    }
+}
@@ -0,0 +1,607 @@
+use crate::check_module::check_module::{CheckFlow, CheckFlows, CheckStep};
+use regex::{Captures, Regex};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+// Top-level keys of check-flow which are not tasks
+pub const TEMPLATES_KEY: &str = "templates";
+pub const BASE_FLOWS_KEY: &str = "base_flows";
+
+// Deeper nesting of `extends` and `include` is reported as a cycle
+const MAX_DEPTH: usize = 16;
+
+/// Check flows with templates and base flows expanded.
+#[derive(Debug, Default)]
+pub struct ExpandedFlows {
+    pub check_flows: CheckFlows,
+    // Path of an expanded step like `task/0/check_steps/1` -> path of the step in the document
+    origins: HashMap<String, String>,
+}
+
+impl ExpandedFlows {
+    /// Path in the document of a path in the expanded flows, e.g. a step of a template.
+    pub fn source_path(&self, path: &str) -> String {
+        let segments = path.splitn(5, '/').collect::<Vec<&str>>();
+        if segments.len() < 4 {
+            return path.to_string();
+        }
+        match self.origins.get(&segments[..4].join("/")) {
+            Some(origin) => match segments.get(4) {
+                Some(rest) => format!("{}/{}", origin, rest),
+                None => origin.clone(),
+            },
+            None => path.to_string(),
+        }
+    }
+}
+
+/// Flow collected from its base flows, steps are not expanded yet.
+#[derive(Default)]
+struct RawFlow {
+    fields: Map<String, Value>,
+    params: Map<String, Value>,
+    // Step and its path in the document
+    steps: Vec<(Value, String)>,
+}
+
+struct Expander<'a> {
+    templates: Map<String, Value>,
+    base_flows: Map<String, Value>,
+    errors: &'a mut Vec<(String, String)>,
+}
+
+/// Expand check-flow document:
+/// - steps `{"include": "<template>", "params": {...}}` are replaced by the steps of the template
+/// - flows `{"extends": "<base flow>", ...}` get fields, params and steps of the base flow,
+///   a step with the return name of a base step replaces it
+/// - `${param}` in strings is replaced by the param of the flow, the include or the template default
+///
+/// Returns list of (json path, message) on error.
+pub fn expand_check_flows(
+    mut document: Map<String, Value>,
+) -> Result<ExpandedFlows, Vec<(String, String)>> {
+    let mut errors = Vec::new();
+    let mut expander = Expander {
+        templates: take_object(&mut document, TEMPLATES_KEY, &mut errors),
+        base_flows: take_object(&mut document, BASE_FLOWS_KEY, &mut errors),
+        errors: &mut errors,
+    };
+    let mut expanded = ExpandedFlows::default();
+    for (task, flows) in document {
+        let flows = match flows {
+            Value::Array(flows) => flows,
+            _ => {
+                expander
+                    .errors
+                    .push((task, "task must be a list of check flows".to_string()));
+                continue;
+            }
+        };
+        let mut check_flows = Vec::new();
+        for (flow_index, flow) in flows.iter().enumerate() {
+            let flow_path = format!("{}/{}", task, flow_index);
+            let steps_path = format!("{}/check_steps", flow_path);
+            if let Some((check_flow, origins)) = expander.expand_flow(flow, &flow_path) {
+                for (step_index, origin) in origins.into_iter().enumerate() {
+                    expanded
+                        .origins
+                        .insert(format!("{}/{}", steps_path, step_index), origin);
+                }
+                check_flows.push(check_flow);
+            }
+        }
+        expanded.check_flows.insert(task, check_flows);
+    }
+    match errors.is_empty() {
+        true => Ok(expanded),
+        false => Err(errors),
+    }
+}
+
+fn take_object(
+    document: &mut Map<String, Value>,
+    key: &str,
+    errors: &mut Vec<(String, String)>,
+) -> Map<String, Value> {
+    match document.remove(key) {
+        None => Map::new(),
+        Some(Value::Object(object)) => object,
+        Some(_) => {
+            errors.push((key.to_string(), format!("{} must be an object", key)));
+            Map::new()
+        }
+    }
+}
+
+impl<'a> Expander<'a> {
+    // Returns the flow and the document path of each step
+    fn expand_flow(&mut self, flow: &Value, flow_path: &str) -> Option<(CheckFlow, Vec<String>)> {
+        let raw = self.collect_flow(flow, flow_path, 0)?;
+        let mut fields = Map::new();
+        for (key, value) in raw.fields.iter() {
+            let value = self.substitute(value, &raw.params, &format!("{}/{}", flow_path, key))?;
+            fields.insert(key.clone(), value);
+        }
+        fields.insert("check_steps".to_string(), Value::Array(vec![]));
+        let mut check_flow = match serde_json::from_value::<CheckFlow>(Value::Object(fields)) {
+            Ok(check_flow) => check_flow,
+            Err(err) => {
+                self.errors.push((flow_path.to_string(), err.to_string()));
+                return None;
+            }
+        };
+        let mut origins = Vec::new();
+        let mut is_valid = true;
+        for (step, origin) in self.expand_steps(raw.steps, &raw.params, 0) {
+            match serde_json::from_value::<CheckStep>(step) {
+                Ok(step) => {
+                    check_flow.check_steps.push(step);
+                    origins.push(origin);
+                }
+                Err(err) => {
+                    self.errors.push((origin, err.to_string()));
+                    is_valid = false;
+                }
+            }
+        }
+        match is_valid {
+            true => Some((check_flow, origins)),
+            false => None,
+        }
+    }
+
+    fn collect_flow(&mut self, flow: &Value, flow_path: &str, depth: usize) -> Option<RawFlow> {
+        let flow = match flow.as_object() {
+            Some(flow) => flow,
+            None => {
+                self.errors.push((
+                    flow_path.to_string(),
+                    "check flow must be an object".to_string(),
+                ));
+                return None;
+            }
+        };
+        let mut raw = match flow.get("extends") {
+            None => RawFlow::default(),
+            Some(Value::String(name)) => {
+                let extends_path = format!("{}/extends", flow_path);
+                let base_flow = match self.base_flows.get(name) {
+                    Some(base_flow) => base_flow.clone(),
+                    None => {
+                        self.errors
+                            .push((extends_path, format!("unknown base flow {:?}", name)));
+                        return None;
+                    }
+                };
+                if depth >= MAX_DEPTH {
+                    self.errors
+                        .push((extends_path, format!("base flow {:?} extends itself", name)));
+                    return None;
+                }
+                self.collect_flow(
+                    &base_flow,
+                    &format!("{}/{}", BASE_FLOWS_KEY, name),
+                    depth + 1,
+                )?
+            }
+            Some(_) => {
+                self.errors.push((
+                    format!("{}/extends", flow_path),
+                    "extends must be a base flow name".to_string(),
+                ));
+                return None;
+            }
+        };
+        for (key, value) in flow {
+            match (key.as_str(), value) {
+                ("extends", _) => {}
+                ("params", Value::Object(params)) => raw.params.extend(params.clone()),
+                ("check_steps", Value::Array(steps)) => {
+                    for (step_index, step) in steps.iter().enumerate() {
+                        let step_path = format!("{}/check_steps/{}", flow_path, step_index);
+                        let overridden = step.get("return_name").and_then(|return_name| {
+                            raw.steps.iter().position(|(base_step, _)| {
+                                base_step.get("return_name") == Some(return_name)
+                            })
+                        });
+                        match overridden {
+                            Some(index) => raw.steps[index] = (step.clone(), step_path),
+                            None => raw.steps.push((step.clone(), step_path)),
+                        }
+                    }
+                }
+                ("params", _) | ("check_steps", _) => self.errors.push((
+                    format!("{}/{}", flow_path, key),
+                    format!("{} has wrong type", key),
+                )),
+                _ => {
+                    raw.fields.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        Some(raw)
+    }
+
+    // Replace included templates by their steps and substitute params
+    fn expand_steps(
+        &mut self,
+        steps: Vec<(Value, String)>,
+        params: &Map<String, Value>,
+        depth: usize,
+    ) -> Vec<(Value, String)> {
+        let mut expanded = Vec::new();
+        for (step, step_path) in steps {
+            let name = match step.get("include") {
+                None => {
+                    if let Some(step) = self.substitute(&step, params, &step_path) {
+                        expanded.push((step, step_path));
+                    }
+                    continue;
+                }
+                Some(Value::String(name)) => name,
+                Some(_) => {
+                    self.errors.push((
+                        format!("{}/include", step_path),
+                        "include must be a template name".to_string(),
+                    ));
+                    continue;
+                }
+            };
+            let include_path = format!("{}/include", step_path);
+            let template = match self.templates.get(name) {
+                Some(template) => template.clone(),
+                None => {
+                    self.errors
+                        .push((include_path, format!("unknown template {:?}", name)));
+                    continue;
+                }
+            };
+            if depth >= MAX_DEPTH {
+                self.errors
+                    .push((include_path, format!("template {:?} includes itself", name)));
+                continue;
+            }
+            // Default params of the template, overridden by params of the flow and of the include
+            let mut template_params = match template.get("params") {
+                Some(Value::Object(defaults)) => defaults.clone(),
+                _ => Map::new(),
+            };
+            template_params.extend(params.clone());
+            if let Some(include_params) = step.get("params") {
+                match self.substitute(include_params, params, &format!("{}/params", step_path)) {
+                    Some(Value::Object(include_params)) => template_params.extend(include_params),
+                    Some(_) => self.errors.push((
+                        format!("{}/params", step_path),
+                        "params must be an object".to_string(),
+                    )),
+                    None => continue,
+                }
+            }
+            let template_path = format!("{}/{}/steps", TEMPLATES_KEY, name);
+            let template_steps = match template.get("steps") {
+                Some(Value::Array(steps)) => steps
+                    .iter()
+                    .enumerate()
+                    .map(|(index, step)| (step.clone(), format!("{}/{}", template_path, index)))
+                    .collect(),
+                _ => {
+                    self.errors.push((
+                        format!("{}/{}", TEMPLATES_KEY, name),
+                        format!("template {:?} has no steps", name),
+                    ));
+                    continue;
+                }
+            };
+            expanded.extend(self.expand_steps(template_steps, &template_params, depth + 1));
+        }
+        expanded
+    }
+
+    // Replace `${param}` in strings and keys. A string which is only `${param}` is replaced
+    // by the param value, so that numbers, booleans and objects can be passed.
+    fn substitute(
+        &mut self,
+        value: &Value,
+        params: &Map<String, Value>,
+        path: &str,
+    ) -> Option<Value> {
+        lazy_static::lazy_static! {
+            static ref PARAM: Regex = Regex::new(r"\$\{([A-Za-z0-9_]+)\}").unwrap();
+        }
+        let mut missing = Vec::new();
+        let mut substitute_string = |text: &str| -> Option<Value> {
+            if let Some(capture) = PARAM.captures(text) {
+                if capture[0].len() == text.len() {
+                    return match params.get(&capture[1]) {
+                        Some(param) => Some(param.clone()),
+                        None => {
+                            missing.push(capture[1].to_string());
+                            None
+                        }
+                    };
+                }
+            }
+            let replaced =
+                PARAM.replace_all(text, |capture: &Captures| match params.get(&capture[1]) {
+                    Some(Value::String(param)) => param.clone(),
+                    Some(param) => param.to_string(),
+                    None => {
+                        missing.push(capture[1].to_string());
+                        String::new()
+                    }
+                });
+            Some(Value::String(replaced.to_string()))
+        };
+        let result = substitute_value(value, &mut substitute_string);
+        if !missing.is_empty() {
+            missing.sort();
+            missing.dedup();
+            self.errors.push((
+                path.to_string(),
+                format!("missing template params {:?}", missing),
+            ));
+            return None;
+        }
+        result
+    }
+}
+
+fn substitute_value(
+    value: &Value,
+    substitute_string: &mut impl FnMut(&str) -> Option<Value>,
+) -> Option<Value> {
+    match value {
+        Value::String(text) => substitute_string(text),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| substitute_value(value, substitute_string))
+            .collect::<Option<Vec<Value>>>()
+            .map(Value::Array),
+        Value::Object(object) => {
+            let mut substituted = Map::new();
+            for (key, value) in object {
+                let key = match substitute_string(key)? {
+                    Value::String(key) => key,
+                    key => key.to_string(),
+                };
+                substituted.insert(key, substitute_value(value, substitute_string)?);
+            }
+            Some(Value::Object(substituted))
+        }
+        _ => Some(value.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn expand(document: Value) -> Result<ExpandedFlows, Vec<(String, String)>> {
+        match document {
+            Value::Object(document) => expand_check_flows(document),
+            _ => panic!("document must be an object"),
+        }
+    }
+
+    fn steps(expanded: &ExpandedFlows, task: &str) -> Vec<Value> {
+        expanded.check_flows[task][0]
+            .check_steps
+            .iter()
+            .map(|step| serde_json::to_value(step).unwrap())
+            .collect()
+    }
+
+    fn return_names(expanded: &ExpandedFlows, task: &str) -> Vec<String> {
+        expanded.check_flows[task][0]
+            .check_steps
+            .iter()
+            .map(|step| step.return_name.clone())
+            .collect()
+    }
+
+    fn compare(return_name: &str) -> Value {
+        json!({
+            "action": {
+                "action_type": "compare",
+                "operator_items": {"operator_type": "eq", "params": ["#1", "#1"]}
+            },
+            "return_name": return_name
+        })
+    }
+
+    fn call(return_name: &str) -> Value {
+        json!({
+            "action": {
+                "action_type": "call",
+                "is_base_node": "${base}",
+                "time_out": "${timeout}",
+                "body": "{\"method\": \"${method}\", \"params\": ${call_params}}"
+            },
+            "return_name": return_name
+        })
+    }
+
+    #[test]
+    fn extends_chain_overrides_steps_by_return_name() {
+        let expanded = expand(json!({
+            "base_flows": {
+                "base": {
+                    "blockchain": "eth",
+                    "network": "${network}",
+                    "params": {"network": "mainnet"},
+                    "check_steps": [compare("first"), compare("second")]
+                },
+                "middle": {"extends": "base", "check_steps": [compare("third")]}
+            },
+            "task": [{
+                "extends": "middle",
+                "params": {"network": "goerli"},
+                "check_steps": [
+                    {"action": {"action_type": "set", "vars": {"a": "1"}}, "return_name": "second"},
+                    compare("fourth")
+                ]
+            }]
+        }))
+        .unwrap();
+        assert_eq!(
+            return_names(&expanded, "task"),
+            vec!["first", "second", "third", "fourth"]
+        );
+        assert_eq!(steps(&expanded, "task")[1]["action"]["action_type"], "set");
+        let flow = &expanded.check_flows["task"][0];
+        // Params of the flow override params of the base flow
+        assert_eq!(flow.network, "goerli");
+        assert_eq!(
+            expanded.source_path("task/0/check_steps/0"),
+            "base_flows/base/check_steps/0"
+        );
+        assert_eq!(
+            expanded.source_path("task/0/check_steps/1"),
+            "task/0/check_steps/0"
+        );
+        assert_eq!(
+            expanded.source_path("task/0/check_steps/2"),
+            "base_flows/middle/check_steps/0"
+        );
+        assert_eq!(
+            expanded.source_path("task/0/check_steps/3/action/operator_items"),
+            "task/0/check_steps/1/action/operator_items"
+        );
+        // Paths outside of steps are not mapped
+        assert_eq!(expanded.source_path("task/0/network"), "task/0/network");
+        assert_eq!(
+            expanded.source_path("task/0/check_steps/9"),
+            "task/0/check_steps/9"
+        );
+    }
+
+    #[test]
+    fn includes_are_expanded_with_param_precedence() {
+        let expanded = expand(json!({
+            "templates": {
+                "outer": {
+                    "params": {"method": "eth_chainId", "timeout": 1000},
+                    "steps": [
+                        {"include": "inner", "params": {"name": "${name}_inner"}},
+                        call("${name}")
+                    ]
+                },
+                "inner": {
+                    "params": {"base": true},
+                    "steps": [call("${name}")]
+                }
+            },
+            "task": [{
+                "blockchain": "eth",
+                "params": {"base": false, "timeout": 2000, "call_params": [], "name": "flow"},
+                "check_steps": [
+                    {"include": "outer", "params": {"method": "eth_blockNumber", "name": "call"}},
+                    compare("last")
+                ]
+            }]
+        }))
+        .unwrap();
+        assert_eq!(
+            return_names(&expanded, "task"),
+            vec!["call_inner", "call", "last"]
+        );
+        let steps = steps(&expanded, "task");
+        for step in steps.iter().take(2) {
+            let action = &step["action"];
+            // Whole string params keep their type: template default < flow < include
+            assert_eq!(action["is_base_node"], json!(false));
+            assert_eq!(action["time_out"], json!(2000));
+            assert_eq!(
+                action["body"],
+                json!("{\"method\": \"eth_blockNumber\", \"params\": []}")
+            );
+        }
+        assert_eq!(
+            expanded.source_path("task/0/check_steps/0/action/body"),
+            "templates/inner/steps/0/action/body"
+        );
+        assert_eq!(
+            expanded.source_path("task/0/check_steps/1"),
+            "templates/outer/steps/1"
+        );
+        assert_eq!(
+            expanded.source_path("task/0/check_steps/2"),
+            "task/0/check_steps/1"
+        );
+    }
+
+    #[test]
+    fn missing_params_are_reported_once() {
+        let errors = expand(json!({
+            "task": [{
+                "blockchain": "eth",
+                "check_steps": [{
+                    "action": {"action_type": "set", "vars": {"a": "${b}${a}${b}", "c": "${a}"}},
+                    "return_name": "set"
+                }]
+            }]
+        }))
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![(
+                "task/0/check_steps/0".to_string(),
+                "missing template params [\"a\", \"b\"]".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let errors = expand(json!({
+            "base_flows": {
+                "first": {"extends": "second"},
+                "second": {"extends": "first"}
+            },
+            "templates": {
+                "loop": {"steps": [{"include": "loop"}]}
+            },
+            "extends_task": [{"extends": "first"}],
+            "include_task": [{"blockchain": "eth", "check_steps": [{"include": "loop"}]}]
+        }))
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "base_flows/second/extends".to_string(),
+                    "base flow \"first\" extends itself".to_string()
+                ),
+                (
+                    "templates/loop/steps/0/include".to_string(),
+                    "template \"loop\" includes itself".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_names_are_reported() {
+        let errors = expand(json!({
+            "templates": [],
+            "task": [
+                {"extends": "missing"},
+                {"blockchain": "eth", "check_steps": [{"include": "missing"}, {"include": 1}]}
+            ],
+            "other": {}
+        }))
+        .unwrap_err();
+        let paths: Vec<_> = errors.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "templates",
+                "other",
+                "task/0/extends",
+                "task/1/check_steps/0/include",
+                "task/1/check_steps/1/include"
+            ]
+        );
+    }
+}
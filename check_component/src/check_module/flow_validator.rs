@@ -0,0 +1,745 @@
+use crate::check_module::check_module::{
+    network_base_endpoints, ActionCall, BlockChainType, CheckAction, CheckFlow, CheckFlows,
+    EndpointInfo, OperatorCompare, STATUS_CODE_KEY,
+};
+use crate::check_module::consensus::{
+    Consensus, ANSWERED_ENDPOINTS_KEY, DISAGREEING_ENDPOINTS_KEY, FAILED_ENDPOINTS_KEY,
+};
+use crate::check_module::flow_template::expand_check_flows;
+use crate::check_module::http_client::HANDSHAKE_TIME_KEY;
+use crate::check_module::return_field::ReturnField;
+use crate::check_module::template_helper::is_helper;
+use crate::check_module::ws_action::CONNECT_TIME_KEY;
+use crate::CONFIG;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// An error found in check-flow file, located by line and column (1-based).
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlowError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for FlowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// Parse a json document as is, so a syntax error is located in the original text.
+/// Check-flow and base-endpoint documents are both read this way.
+pub fn parse_json<T: DeserializeOwned>(json: &str) -> Result<T, FlowError> {
+    serde_json::from_str(json).map_err(|err| FlowError {
+        line: err.line(),
+        column: err.column(),
+        message: err.to_string(),
+    })
+}
+
+/// Parse, expand templates and validate check-flow json. All found errors are returned at once.
+pub fn parse_check_flows(json: &str) -> Result<CheckFlows, Vec<FlowError>> {
+    let document: Map<String, Value> = parse_json(json).map_err(|err| vec![err])?;
+    let positions = JsonPositions::index(json);
+    let to_errors = |errors: Vec<(String, String)>| {
+        let mut flow_errors: Vec<FlowError> = Vec::new();
+        for (path, message) in errors {
+            let error = positions.to_error(&path, message);
+            // Errors of a template are found in every flow including it
+            if !flow_errors.contains(&error) {
+                flow_errors.push(error);
+            }
+        }
+        flow_errors
+    };
+    let expanded = expand_check_flows(document).map_err(to_errors)?;
+    let errors = to_errors(
+        validate_check_flows(&expanded.check_flows)
+            .into_iter()
+            .map(|(path, message)| (expanded.source_path(&path), message))
+            .collect(),
+    );
+    match errors.is_empty() {
+        true => Ok(expanded.check_flows),
+        false => Err(errors),
+    }
+}
+
+// Number of base endpoints a flow can use. A flow for any network without chain key uses
+// the network keys of the chain, the smallest one is counted.
+fn flow_endpoint_count(
+    flow: &CheckFlow,
+    base_nodes: &HashMap<BlockChainType, Vec<EndpointInfo>>,
+) -> usize {
+    if !flow.is_any_network() {
+        return network_base_endpoints(base_nodes, &flow.blockchain, &flow.network)
+            .map(|endpoints| endpoints.len())
+            .unwrap_or_default();
+    }
+    if let Some(endpoints) = base_nodes.get(&flow.blockchain) {
+        return endpoints.len();
+    }
+    let network_prefix = format!("{}.", flow.blockchain);
+    base_nodes
+        .iter()
+        .filter(|(chain, _)| chain.starts_with(&network_prefix))
+        .map(|(_, endpoints)| endpoints.len())
+        .min()
+        .unwrap_or_default()
+}
+
+/// Check that every chain calling a base node has base endpoints.
+pub fn validate_base_endpoints(
+    json: &str,
+    check_flows: &CheckFlows,
+    base_nodes: &HashMap<BlockChainType, Vec<EndpointInfo>>,
+) -> Vec<FlowError> {
+    let positions = JsonPositions::index(json);
+    let mut errors = Vec::new();
+    for (task, flows) in sorted(check_flows) {
+        for (flow_index, flow) in flows.iter().enumerate() {
+            let uses_base_node = flow
+                .check_steps
+                .iter()
+                .any(|step| step.action.is_base_node());
+            let endpoint_count = flow_endpoint_count(flow, base_nodes);
+            let has_endpoints = endpoint_count > 0;
+            if uses_base_node && !has_endpoints {
+                errors.push(positions.to_error(
+                    &json_path(&[task, &flow_index.to_string(), "blockchain"]),
+                    match flow.is_any_network() {
+                        true => format!(
+                            "missing base endpoints for chain {:?} used by task {}",
+                            flow.blockchain, task
+                        ),
+                        false => format!(
+                            "missing base endpoints for chain {:?} network {:?} used by task {}",
+                            flow.blockchain, flow.network, task
+                        ),
+                    },
+                ));
+            }
+            for (step_index, step) in flow.check_steps.iter().enumerate() {
+                let consensus = match &step.action {
+                    CheckAction::Call(action) => action.consensus.as_ref(),
+                    _ => None,
+                };
+                if let Some(consensus) = consensus {
+                    let queried = consensus
+                        .endpoints
+                        .unwrap_or(endpoint_count)
+                        .min(endpoint_count);
+                    if has_endpoints && consensus.required_answers(queried) > queried {
+                        errors.push(positions.to_error(
+                            &json_path(&[
+                                task,
+                                &flow_index.to_string(),
+                                "check_steps",
+                                &step_index.to_string(),
+                                "action",
+                                "consensus",
+                            ]),
+                            format!(
+                                "quorum cannot be reached with {} base endpoints of chain {:?}",
+                                queried, flow.blockchain
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    errors
+}
+
+fn sorted(check_flows: &CheckFlows) -> Vec<(&String, &Vec<CheckFlow>)> {
+    let mut check_flows = check_flows.iter().collect::<Vec<_>>();
+    check_flows.sort_by(|(first, _), (second, _)| first.cmp(second));
+    check_flows
+}
+
+// Returns list of (json path, message)
+fn validate_check_flows(check_flows: &CheckFlows) -> Vec<(String, String)> {
+    let mut errors = Vec::new();
+    for (task, flows) in sorted(check_flows) {
+        for (flow_index, flow) in flows.iter().enumerate() {
+            let flow_path = [task.as_str(), &flow_index.to_string(), "check_steps"].join("/");
+            errors.extend(validate_check_flow(flow, &flow_path));
+        }
+    }
+    errors
+}
+
+fn validate_check_flow(flow: &CheckFlow, flow_path: &str) -> Vec<(String, String)> {
+    let mut errors = Vec::new();
+    // Keys available in step result when the step runs
+    let mut produced_keys: HashSet<String> = HashSet::new();
+    let mut return_names: HashSet<&String> = HashSet::new();
+    for (step_index, step) in flow.check_steps.iter().enumerate() {
+        let step_path = format!("{}/{}", flow_path, step_index);
+        if step.return_name.is_empty() {
+            errors.push((step_path.clone(), "return_name is empty".to_string()));
+        } else if !return_names.insert(&step.return_name) {
+            errors.push((
+                format!("{}/return_name", step_path),
+                format!("return_name {} is duplicated", step.return_name),
+            ));
+        }
+        for (dependency_index, name) in step.depends_on.iter().flatten().enumerate() {
+            if !return_names.contains(name) || name == &step.return_name {
+                errors.push((
+                    format!("{}/depends_on/{}", step_path, dependency_index),
+                    format!("depends_on references unknown previous step {:?}", name),
+                ));
+            }
+        }
+        if let Some(retry) = &step.retry {
+            if retry.attempts == 0 {
+                errors.push((
+                    format!("{}/retry/attempts", step_path),
+                    "retry attempts must be at least 1".to_string(),
+                ));
+            }
+            if let CheckAction::Compare(_) = step.action {
+                errors.push((
+                    format!("{}/retry", step_path),
+                    "compare step cannot be retried".to_string(),
+                ));
+            }
+        }
+        let produced_fields = match &step.action {
+            CheckAction::Call(action) => {
+                errors.extend(validate_templates(
+                    &action.templates(),
+                    &step_path,
+                    &produced_keys,
+                ));
+                errors.extend(validate_return_fields(&action.return_fields, &step_path));
+                let mut fields = action.return_fields.keys().cloned().collect::<Vec<_>>();
+                fields.push(CONFIG.response_time_key.clone());
+                fields.push(STATUS_CODE_KEY.to_string());
+                if action.measure_connection {
+                    fields.push(CONNECT_TIME_KEY.to_string());
+                    fields.push(HANDSHAKE_TIME_KEY.to_string());
+                }
+                if let Some(consensus) = &action.consensus {
+                    errors.extend(validate_consensus(action, consensus, &step_path));
+                    fields.push(ANSWERED_ENDPOINTS_KEY.to_string());
+                    fields.push(DISAGREEING_ENDPOINTS_KEY.to_string());
+                    fields.push(FAILED_ENDPOINTS_KEY.to_string());
+                }
+                fields
+            }
+            CheckAction::WsCall(action) | CheckAction::WsSubscribe(action) => {
+                errors.extend(validate_templates(
+                    &action.templates(),
+                    &step_path,
+                    &produced_keys,
+                ));
+                errors.extend(validate_return_fields(&action.return_fields, &step_path));
+                action.result_keys(matches!(step.action, CheckAction::WsSubscribe(_)))
+            }
+            CheckAction::Set(action) => {
+                errors.extend(validate_templates(
+                    &action.templates(),
+                    &step_path,
+                    &produced_keys,
+                ));
+                action.vars.keys().cloned().collect()
+            }
+            CheckAction::Compare(action) => {
+                let operator_path = format!("{}/action/operator_items", step_path);
+                errors.extend(validate_operator(
+                    &action.operator_items,
+                    &operator_path,
+                    &produced_keys,
+                ));
+                vec![step.return_name.clone()]
+            }
+        };
+        for field in produced_fields {
+            produced_keys.insert(format!("{}_{}", step.return_name, field));
+        }
+    }
+    errors
+}
+
+fn validate_templates(
+    templates: &[&String],
+    step_path: &str,
+    produced_keys: &HashSet<String>,
+) -> Vec<(String, String)> {
+    let mut errors = Vec::new();
+    for template in templates {
+        for name in template_references(template) {
+            if !produced_keys.contains(&name) {
+                errors.push((
+                    format!("{}/action", step_path),
+                    format!("template references unknown step result {:?}", name),
+                ));
+            }
+        }
+    }
+    errors
+}
+
+fn validate_return_fields(
+    return_fields: &HashMap<String, ReturnField>,
+    step_path: &str,
+) -> Vec<(String, String)> {
+    let mut errors = Vec::new();
+    for (name, field) in return_fields.iter() {
+        if let Err(err) = field.compile() {
+            errors.push((
+                format!("{}/action/return_fields/{}", step_path, name),
+                err.to_string(),
+            ));
+        }
+    }
+    errors
+}
+
+fn validate_consensus(
+    action: &ActionCall,
+    consensus: &Consensus,
+    step_path: &str,
+) -> Vec<(String, String)> {
+    let path = format!("{}/action/consensus", step_path);
+    let mut errors = Vec::new();
+    if !action.is_base_node {
+        errors.push((path.clone(), "consensus needs is_base_node".to_string()));
+    }
+    if consensus.quorum == Some(0) || consensus.endpoints == Some(0) {
+        errors.push((
+            path.clone(),
+            "quorum and endpoints must be at least 1".to_string(),
+        ));
+    }
+    for field in consensus.median_fields.iter() {
+        if !action.return_fields.contains_key(field) {
+            errors.push((
+                format!("{}/median_fields", path),
+                format!("median field {:?} is not a return field", field),
+            ));
+        }
+    }
+    errors
+}
+
+fn validate_operator(
+    operator: &OperatorCompare,
+    path: &str,
+    produced_keys: &HashSet<String>,
+) -> Vec<(String, String)> {
+    let mut errors = Vec::new();
+    let mut unknown_items = HashSet::new();
+    for item in operator.items() {
+        if !item.starts_with('#') && !produced_keys.contains(item) && unknown_items.insert(item) {
+            errors.push((
+                path.to_string(),
+                format!("operator references unknown step result {:?}", item),
+            ));
+        }
+    }
+    for err in invalid_regexes(operator) {
+        errors.push((path.to_string(), err));
+    }
+    errors
+}
+
+fn invalid_regexes(operator: &OperatorCompare) -> Vec<String> {
+    match operator {
+        OperatorCompare::And(operators) | OperatorCompare::Or(operators) => {
+            operators.iter().flat_map(invalid_regexes).collect()
+        }
+        OperatorCompare::Not(operand) => invalid_regexes(operand.operator()),
+        OperatorCompare::Regex([_, pattern]) => pattern
+            .strip_prefix('#')
+            .and_then(|pattern| Regex::new(pattern).err())
+            .map(|err| format!("invalid regex: {}", err))
+            .into_iter()
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Names of step results used by handlebars expressions in a template.
+pub fn template_references(template: &str) -> Vec<String> {
+    lazy_static::lazy_static! {
+        static ref EXPRESSION: Regex = Regex::new(r"\{\{\{?~?([^{}]*?)~?\}?\}\}").unwrap();
+        static ref LITERAL: Regex = Regex::new(r#"^(-?[0-9.]+|".*"|'.*'|true|false|null)$"#).unwrap();
+    }
+    let mut names = Vec::new();
+    for capture in EXPRESSION.captures_iter(template) {
+        let tokens = capture[1].split_whitespace().collect::<Vec<&str>>();
+        let references = match tokens.first() {
+            None => continue,
+            // Comments, block helpers and `else`
+            Some(token) if token.starts_with(&['!', '#', '/', '>'][..]) || *token == "else" => {
+                continue
+            }
+            // Helper without params, e.g. `{{timestamp}}`
+            Some(token) if tokens.len() == 1 && is_helper(token) => continue,
+            Some(_) if tokens.len() == 1 => &tokens[..],
+            // Helper call: first token is helper name
+            Some(_) => &tokens[1..],
+        };
+        for token in references {
+            // Helper of a subexpression, e.g. `(sub a 1)`
+            if token.starts_with('(') || token.contains('=') {
+                continue;
+            }
+            let token = token.trim_end_matches(')');
+            if !token.is_empty() && !LITERAL.is_match(token) {
+                names.push(token.to_string());
+            }
+        }
+    }
+    names
+}
+
+fn json_path(segments: &[&str]) -> String {
+    segments.join("/")
+}
+
+// Line and column of every value in a json document, keyed by path like `task/0/check_steps/1`
+struct JsonPositions {
+    positions: HashMap<String, (usize, usize)>,
+}
+
+impl JsonPositions {
+    fn index(json: &str) -> Self {
+        let mut scanner = JsonScanner {
+            bytes: json.as_bytes(),
+            offset: 0,
+            line: 1,
+            column: 1,
+            positions: HashMap::new(),
+        };
+        scanner.scan_value(String::new());
+        JsonPositions {
+            positions: scanner.positions,
+        }
+    }
+
+    // Use the nearest indexed parent if the path itself is not found
+    fn to_error(&self, path: &str, message: String) -> FlowError {
+        let mut path = path;
+        loop {
+            if let Some((line, column)) = self.positions.get(path) {
+                return FlowError {
+                    line: *line,
+                    column: *column,
+                    message,
+                };
+            }
+            match path.rfind('/') {
+                Some(index) => path = &path[..index],
+                None => {
+                    let (line, column) = self.positions.get(path).cloned().unwrap_or((1, 1));
+                    return FlowError {
+                        line,
+                        column,
+                        message,
+                    };
+                }
+            }
+        }
+    }
+}
+
+struct JsonScanner<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    line: usize,
+    column: usize,
+    positions: HashMap<String, (usize, usize)>,
+}
+
+impl<'a> JsonScanner<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.offset).cloned()
+    }
+
+    fn bump(&mut self) {
+        if let Some(byte) = self.peek() {
+            self.offset += 1;
+            if byte == b'\n' {
+                self.line += 1;
+                self.column = 1;
+            } else if byte & 0xC0 != 0x80 {
+                // Do not count utf-8 continuation bytes
+                self.column += 1;
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(byte) = self.peek() {
+            if !byte.is_ascii_whitespace() {
+                break;
+            }
+            self.bump();
+        }
+    }
+
+    fn scan_value(&mut self, path: String) {
+        self.skip_whitespace();
+        self.positions
+            .insert(path.clone(), (self.line, self.column));
+        match self.peek() {
+            Some(b'{') => self.scan_object(path),
+            Some(b'[') => self.scan_array(path),
+            Some(b'"') => {
+                self.scan_string();
+            }
+            Some(_) => {
+                while let Some(byte) = self.peek() {
+                    if byte == b',' || byte == b'}' || byte == b']' || byte.is_ascii_whitespace() {
+                        break;
+                    }
+                    self.bump();
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn scan_string(&mut self) -> String {
+        let start = self.offset + 1;
+        self.bump();
+        while let Some(byte) = self.peek() {
+            match byte {
+                b'\\' => {
+                    self.bump();
+                    self.bump();
+                }
+                b'"' => {
+                    let raw = &self.bytes[start..self.offset];
+                    self.bump();
+                    let raw = String::from_utf8_lossy(raw).to_string();
+                    return serde_json::from_str::<String>(&format!("\"{}\"", raw)).unwrap_or(raw);
+                }
+                _ => self.bump(),
+            }
+        }
+        String::new()
+    }
+
+    fn child_path(path: &str, key: &str) -> String {
+        match path.is_empty() {
+            true => key.to_string(),
+            false => format!("{}/{}", path, key),
+        }
+    }
+
+    fn scan_object(&mut self, path: String) {
+        self.bump();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b'"') => {
+                    let key = self.scan_string();
+                    self.skip_whitespace();
+                    if self.peek() == Some(b':') {
+                        self.bump();
+                    }
+                    self.scan_value(Self::child_path(&path, &key));
+                }
+                Some(b',') => self.bump(),
+                Some(b'}') => {
+                    self.bump();
+                    return;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn scan_array(&mut self, path: String) {
+        self.bump();
+        let mut index = 0;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.bump(),
+                Some(b']') => {
+                    self.bump();
+                    return;
+                }
+                None => return,
+                Some(_) => {
+                    let offset = self.offset;
+                    self.scan_value(Self::child_path(&path, &index.to_string()));
+                    index += 1;
+                    if self.offset == offset {
+                        // Malformed document, serde reports the error
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare_step(return_name: &str, operator: &str) -> String {
+        format!(
+            r#"{{
+          "action": {{
+            "action_type": "compare",
+            "operator_items": {}
+          }},
+          "return_name": "{}"
+        }}"#,
+            operator, return_name
+        )
+    }
+
+    fn check_flows(steps: &[String]) -> String {
+        format!(
+            r#"{{
+  "node": [
+    {{
+      "blockchain": "eth",
+      "check_steps": [
+        {}
+      ]
+    }}
+  ]
+}}"#,
+            steps.join(",\n        ")
+        )
+    }
+
+    fn located(errors: &[FlowError]) -> Vec<(usize, usize)> {
+        errors.iter().map(|err| (err.line, err.column)).collect()
+    }
+
+    #[test]
+    fn positions_of_nested_values() {
+        let json = "{\n  \"a\": [1, {\"b\": \"é\\\"x\", \"c\": null}],\n  \"d\": {}\n}";
+        let positions = JsonPositions::index(json).positions;
+        assert_eq!(positions[""], (1, 1));
+        assert_eq!(positions["a"], (2, 8));
+        assert_eq!(positions["a/0"], (2, 9));
+        assert_eq!(positions["a/1"], (2, 12));
+        assert_eq!(positions["a/1/b"], (2, 18));
+        // Multi-byte and escaped characters are one column each
+        assert_eq!(positions["a/1/c"], (2, 31));
+        assert_eq!(positions["d"], (3, 8));
+    }
+
+    #[test]
+    fn error_uses_nearest_indexed_parent() {
+        let positions = JsonPositions::index("{\n  \"a\": {\n    \"b\": 1\n  }\n}");
+        let error = positions.to_error("a/b/missing/0", "message".to_string());
+        assert_eq!((error.line, error.column), (3, 10));
+        let error = positions.to_error("unknown", "message".to_string());
+        assert_eq!((error.line, error.column), (1, 1));
+    }
+
+    #[test]
+    fn scanner_stops_on_malformed_documents() {
+        for json in &[
+            "",
+            "{",
+            "[1, ",
+            "{\"a\": \"unterminated",
+            "{\"a\" 1 ]",
+            "[}",
+        ] {
+            JsonPositions::index(json);
+        }
+        let positions = JsonPositions::index("{\"a\": [1, }").positions;
+        assert_eq!(positions["a/0"], (1, 8));
+    }
+
+    #[test]
+    fn syntax_errors_are_located() {
+        let errors =
+            parse_check_flows("{\n  \"node\": [\n    {\"check_steps\": [],}\n  ]\n}").unwrap_err();
+        assert_eq!(located(&errors), vec![(3, 24)]);
+        assert!(errors[0].message.contains("trailing comma"));
+
+        let errors =
+            parse_check_flows("{\n  \"node\": [\n    {\"check_steps\": \"x}\n  ]\n}").unwrap_err();
+        // The string ends at the newline
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 4);
+        assert!(errors[0].message.contains("control character"));
+    }
+
+    #[test]
+    fn documents_share_the_same_parser() {
+        // Comments are rejected in check flows and base endpoints alike
+        let json = "{\n  // comment\n  \"eth\": []\n}";
+        let flow_error = parse_check_flows(json).unwrap_err().remove(0);
+        let base_error =
+            parse_json::<HashMap<BlockChainType, Vec<EndpointInfo>>>(json).unwrap_err();
+        assert_eq!(flow_error, base_error);
+        assert_eq!((base_error.line, base_error.column), (2, 3));
+    }
+
+    #[test]
+    fn validation_errors_are_located() {
+        let json = check_flows(&[
+            compare_step("first", r##"{"operator_type": "eq", "params": ["#1", "#1"]}"##),
+            compare_step(
+                "second",
+                r##"{"operator_type": "regex", "params": ["missing", "#("]}"##,
+            ),
+            r##"{"action": {"action_type": "compare", "operator_items": {"operator_type": "eq", "params": ["first_first", "#true"]}},
+         "return_name": "third", "depends_on": ["first", "unknown"]}"##
+                .to_string(),
+        ]);
+        let errors = parse_check_flows(&json).unwrap_err();
+        assert_eq!(located(&errors), vec![(16, 31), (16, 31), (21, 58)]);
+        assert_eq!(
+            errors[0].message,
+            "operator references unknown step result \"missing\""
+        );
+        assert!(errors[1].message.starts_with("invalid regex"));
+        assert_eq!(
+            errors[2].message,
+            "depends_on references unknown previous step \"unknown\""
+        );
+    }
+
+    #[test]
+    fn depends_on_cycles_are_rejected() {
+        let step = |return_name: &str, depends_on: &str| {
+            format!(
+                r##"{{"action": {{"action_type": "compare", "operator_items": {{"operator_type": "eq", "params": ["#1", "#1"]}}}},
+         "return_name": "{}", "depends_on": ["{}"]}}"##,
+                return_name, depends_on
+            )
+        };
+        let json = check_flows(&[step("first", "second"), step("second", "first")]);
+        let errors = parse_check_flows(&json).unwrap_err();
+        let messages: Vec<_> = errors.iter().map(|err| err.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec!["depends_on references unknown previous step \"second\""]
+        );
+        let json = check_flows(&[step("first", "first")]);
+        assert_eq!(parse_check_flows(&json).unwrap_err().len(), 1);
+    }
+
+    #[test]
+    fn valid_flows_are_parsed() {
+        let json = check_flows(&[compare_step(
+            "compare",
+            r##"{"operator_type": "eq", "params": ["#1", "#1"]}"##,
+        )]);
+        let check_flows = parse_check_flows(&json).unwrap();
+        assert_eq!(check_flows["node"][0].check_steps.len(), 1);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::CONFIG;
+use anyhow::Error;
+use reqwest::{Client, Url};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+// Key added to step result of call actions with `measure_connection`, next to `connect_time_ms`
+pub const HANDSHAKE_TIME_KEY: &str = "handshake_time_ms";
+
+/// Http clients shared by all checks. Clones share the connection pool, so that
+/// connections and tls sessions to components and base endpoints are reused.
+#[derive(Clone, Debug)]
+pub struct HttpClient {
+    pooled: Client,
+    // Opens a new connection for every request, to measure cold-connection latency
+    cold: Client,
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        HttpClient::new().expect("Cannot build http client")
+    }
+}
+
+impl HttpClient {
+    pub fn new() -> Result<Self, Error> {
+        // Components are called by ip, their certificates do not match
+        let pooled = Client::builder()
+            .danger_accept_invalid_certs(true)
+            .pool_max_idle_per_host(CONFIG.http_pool_max_idle_per_host)
+            .pool_idle_timeout(Duration::from_millis(CONFIG.http_pool_idle_timeout_ms))
+            .tcp_nodelay(true)
+            .build()?;
+        let cold = Client::builder()
+            .danger_accept_invalid_certs(true)
+            .pool_max_idle_per_host(0)
+            .tcp_nodelay(true)
+            .build()?;
+        Ok(HttpClient { pooled, cold })
+    }
+
+    pub fn pooled(&self) -> &Client {
+        &self.pooled
+    }
+
+    pub fn get(&self, cold_connection: bool) -> &Client {
+        match cold_connection {
+            true => &self.cold,
+            false => &self.pooled,
+        }
+    }
+}
+
+/// Time to open a tcp connection and, for https, to complete the tls handshake.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionTiming {
+    pub connect_time_ms: u64,
+    pub handshake_time_ms: u64,
+}
+
+impl ConnectionTiming {
+    /// Open a separate connection to the host of the url and time each phase.
+    pub async fn probe(url: &str, time_out: Duration) -> Result<Self, Error> {
+        tokio::time::timeout(time_out, Self::probe_url(url))
+            .await
+            .map_err(|_| Error::msg(format!("connect to {} timed out", url)))?
+    }
+
+    async fn probe_url(url: &str) -> Result<Self, Error> {
+        let url = Url::parse(url)?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| Error::msg(format!("url {} has no host", url)))?;
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| Error::msg(format!("url {} has no port", url)))?;
+
+        let now = Instant::now();
+        let stream = TcpStream::connect((host, port)).await?;
+        stream.set_nodelay(true)?;
+        let connect_time_ms = now.elapsed().as_millis() as u64;
+
+        let mut handshake_time_ms = 0;
+        if url.scheme() == "https" {
+            let connector = native_tls::TlsConnector::builder()
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true)
+                .build()?;
+            let connector = tokio_native_tls::TlsConnector::from(connector);
+            let now = Instant::now();
+            connector.connect(host, stream).await?;
+            handshake_time_ms = now.elapsed().as_millis() as u64;
+        }
+        Ok(ConnectionTiming {
+            connect_time_ms,
+            handshake_time_ms,
+        })
+    }
+}
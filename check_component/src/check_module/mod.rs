@@ -1,4 +1,26 @@
+pub mod base_endpoint_health;
+pub mod base_node_cache;
+pub mod check_history;
+pub mod check_limits;
 pub mod check_module;
+pub mod checkmk_output;
+pub mod consensus;
+pub mod flow_reloader;
+pub mod flow_source;
+pub mod flow_template;
+pub mod flow_validator;
+pub mod http_client;
+pub mod prometheus_metrics;
+pub mod report_outbox;
+pub mod report_signature;
+pub mod report_sink;
+pub mod retry;
+pub mod return_field;
+pub mod step_graph;
+pub mod step_value;
 pub mod store_report;
+pub mod template_helper;
+pub mod util;
+pub mod ws_action;
 
 use wrap_wrk;
@@ -0,0 +1,396 @@
+use crate::check_module::check_module::{CheckMkReport, ComponentInfo};
+use crate::check_module::util::lock;
+use crate::CONFIG;
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use warp::Filter;
+use wrap_wrk::WrkReport;
+
+// Suffix of the step response times gathered by `run_check_steps`
+const RESPONSE_TIME_SUFFIX: &str = "_response_time_ms";
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// Name, type and help of the exported metrics
+const FAMILIES: &[(&str, &str, &str)] = &[
+    (
+        "mbr_check_status",
+        "gauge",
+        "CheckMk status of the latest check: 0 ok, 1 warning, 2 critical, 3 unknown",
+    ),
+    (
+        "mbr_checks_total",
+        "counter",
+        "Checks of the component by CheckMk status",
+    ),
+    (
+        "mbr_check_step_response_time_ms",
+        "gauge",
+        "Response time of a check step of the latest check",
+    ),
+    (
+        "mbr_benchmark_latency_ms",
+        "gauge",
+        "Latency percentile of the latest benchmark",
+    ),
+    (
+        "mbr_benchmark_success_ratio",
+        "gauge",
+        "Ratio of 2xx and 3xx responses of the latest benchmark",
+    ),
+    (
+        "mbr_benchmark_requests_per_second",
+        "gauge",
+        "Requests per second of the latest benchmark",
+    ),
+    (
+        "mbr_ping_success_ratio",
+        "gauge",
+        "Ratio of successful pings of the latest ping check",
+    ),
+    (
+        "mbr_check_queue_depth",
+        "gauge",
+        "Components waiting to be checked",
+    ),
+    (
+        "mbr_report_outbox_backlog",
+        "gauge",
+        "Reports of the outbox waiting to be sent to the portal or rejected by it",
+    ),
+];
+
+lazy_static! {
+    /// Metrics of checks, benchmarks and pings of this process, scraped at `GET /metrics`.
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    series: Mutex<BTreeMap<(&'static str, Labels), Sample>>,
+}
+
+#[derive(Debug)]
+struct Sample {
+    value: f64,
+    updated: Instant,
+}
+
+// Every component series has this label, other series are not dropped by `metrics_series_ttl_ms`
+const COMPONENT_TYPE_LABEL: &str = "component_type";
+
+fn component_labels(component: &ComponentInfo, with_id: bool) -> Labels {
+    let mut labels = vec![
+        ("chain", component.blockchain.clone()),
+        ("network", component.network.clone()),
+        ("zone", format!("{:?}", component.zone)),
+        (COMPONENT_TYPE_LABEL, component.component_type.to_string()),
+    ];
+    if with_id {
+        labels.push(("component_id", component.id.clone()));
+    }
+    labels
+}
+
+fn with_label(mut labels: Labels, name: &'static str, value: &str) -> Labels {
+    labels.push((name, value.to_string()));
+    labels
+}
+
+fn status_name(status: u8) -> &'static str {
+    match status {
+        0 => "ok",
+        1 => "warning",
+        2 => "critical",
+        _ => "unknown",
+    }
+}
+
+impl Metrics {
+    fn update(&self, name: &'static str, labels: Labels, update: impl FnOnce(&mut f64)) {
+        let mut series = lock(&self.series);
+        let sample = series.entry((name, labels)).or_insert_with(|| Sample {
+            value: 0.0,
+            updated: Instant::now(),
+        });
+        update(&mut sample.value);
+        sample.updated = Instant::now();
+    }
+
+    fn set(&self, name: &'static str, labels: Labels, value: f64) {
+        self.update(name, labels, |current| *current = value);
+    }
+
+    fn add(&self, name: &'static str, labels: Labels, value: f64) {
+        self.update(name, labels, |current| *current += value);
+    }
+
+    pub fn record_check(&self, component: &ComponentInfo, report: &CheckMkReport) {
+        let labels = component_labels(component, CONFIG.metrics_component_id_label);
+        self.set("mbr_check_status", labels.clone(), report.status as f64);
+        self.add(
+            "mbr_checks_total",
+            with_label(labels.clone(), "status", status_name(report.status)),
+            1.0,
+        );
+        for (key, value) in report.metric.metric.iter() {
+            if let (Some(step), Some(value)) =
+                (key.strip_suffix(RESPONSE_TIME_SUFFIX), value.as_f64())
+            {
+                self.set(
+                    "mbr_check_step_response_time_ms",
+                    with_label(labels.clone(), "step", step),
+                    value,
+                );
+            }
+        }
+    }
+
+    pub fn record_benchmark(&self, component: &ComponentInfo, report: &WrkReport) {
+        let labels = component_labels(component, CONFIG.metrics_component_id_label);
+        for (quantile, latency) in [
+            ("0.9", report.histogram_90),
+            ("0.95", report.histogram_95),
+            ("0.99", report.histogram_99),
+        ] {
+            self.set(
+                "mbr_benchmark_latency_ms",
+                with_label(labels.clone(), "quantile", quantile),
+                latency as f64,
+            );
+        }
+        if let Some(success_percent) = report.get_success_percent() {
+            self.set(
+                "mbr_benchmark_success_ratio",
+                labels.clone(),
+                success_percent as f64 / 100.0,
+            );
+        }
+        self.set(
+            "mbr_benchmark_requests_per_second",
+            labels,
+            report.req_per_sec as f64,
+        );
+    }
+
+    pub fn record_ping(&self, component: &ComponentInfo, success_ratio: f32) {
+        self.set(
+            "mbr_ping_success_ratio",
+            component_labels(component, CONFIG.metrics_component_id_label),
+            success_ratio as f64,
+        );
+    }
+
+    /// Add components to or remove them from a queue, e.g. `verify` of the check component server.
+    pub fn add_queue_depth(&self, queue: &str, delta: i64) {
+        self.add(
+            "mbr_check_queue_depth",
+            vec![("queue", queue.to_string())],
+            delta as f64,
+        );
+    }
+
+    pub fn set_outbox_backlog(&self, pending: usize, failed: usize) {
+        for (state, count) in [("pending", pending), ("failed", failed)] {
+            self.set(
+                "mbr_report_outbox_backlog",
+                vec![("state", state.to_string())],
+                count as f64,
+            );
+        }
+    }
+
+    /// Metrics in Prometheus text format. Series of components not checked for
+    /// `metrics_series_ttl_ms` are dropped, e.g. components removed from the lists.
+    pub fn render(&self) -> String {
+        self.render_with_ttl(Duration::from_millis(CONFIG.metrics_series_ttl_ms))
+    }
+
+    fn render_with_ttl(&self, ttl: Duration) -> String {
+        let mut series = lock(&self.series);
+        if !ttl.is_zero() {
+            series.retain(|(_, labels), sample| {
+                !labels.iter().any(|(name, _)| *name == COMPONENT_TYPE_LABEL)
+                    || sample.updated.elapsed() < ttl
+            });
+        }
+        let mut output = String::new();
+        for (family, metric_type, help) in FAMILIES {
+            let samples = series
+                .iter()
+                .filter(|((name, _), _)| name == family)
+                .collect::<Vec<_>>();
+            if samples.is_empty() {
+                continue;
+            }
+            let _ = writeln!(output, "# HELP {} {}", family, help);
+            let _ = writeln!(output, "# TYPE {} {}", family, metric_type);
+            for ((name, labels), sample) in samples {
+                let labels = labels
+                    .iter()
+                    .map(|(label, value)| format!("{}=\"{}\"", label, escape_label_value(value)))
+                    .collect::<Vec<String>>()
+                    .join(",");
+                let _ = writeln!(
+                    output,
+                    "{}{{{}}} {}",
+                    name,
+                    labels,
+                    format_value(sample.value)
+                );
+            }
+        }
+        output
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// `GET /metrics` for Prometheus, served by the check component server and the fisherman.
+pub fn create_metrics() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+{
+    warp::path!("metrics")
+        .and(warp::get())
+        .map(|| warp::reply::with_header(METRICS.render(), "content-type", CONTENT_TYPE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check_module::check_module::{ComponentType, Zone};
+    use serde_json::json;
+
+    fn component(id: &str, network: &str) -> ComponentInfo {
+        ComponentInfo {
+            blockchain: "eth".to_string(),
+            network: network.to_string(),
+            id: id.to_string(),
+            zone: Zone::AS,
+            component_type: ComponentType::Node,
+            ..Default::default()
+        }
+    }
+
+    fn set_age(metrics: &Metrics, name: &str, age: Duration) {
+        for ((series_name, _), sample) in lock(&metrics.series).iter_mut() {
+            if *series_name == name {
+                sample.updated = Instant::now() - age;
+            }
+        }
+    }
+
+    #[test]
+    fn component_id_label_is_optional() {
+        let labels = component_labels(&component("c1", "mainnet"), false);
+        assert!(!labels.iter().any(|(name, _)| *name == "component_id"));
+        let labels = component_labels(&component("c1", "mainnet"), true);
+        assert_eq!(labels.last(), Some(&("component_id", "c1".to_string())));
+    }
+
+    #[test]
+    fn render_help_type_and_samples() {
+        let metrics = Metrics::default();
+        let mut report = CheckMkReport {
+            status: 2,
+            ..Default::default()
+        };
+        report.metric.metric = vec![
+            ("checkCall_response_time_ms".to_string(), json!(120)),
+            ("checkCall_status_code".to_string(), json!(200)),
+        ]
+        .into_iter()
+        .collect();
+        let labels = component_labels(&component("c1", "mainnet"), false);
+        metrics.set("mbr_check_status", labels.clone(), report.status as f64);
+        metrics.add(
+            "mbr_checks_total",
+            with_label(labels.clone(), "status", status_name(report.status)),
+            1.0,
+        );
+        metrics.add(
+            "mbr_checks_total",
+            with_label(labels, "status", status_name(report.status)),
+            1.0,
+        );
+        metrics.set_outbox_backlog(3, 0);
+        let output = metrics.render_with_ttl(Duration::ZERO);
+        let component = r#"chain="eth",network="mainnet",zone="AS",component_type="node""#;
+        let expected = [
+            "# HELP mbr_check_status CheckMk status of the latest check: 0 ok, 1 warning, 2 critical, 3 unknown".to_string(),
+            "# TYPE mbr_check_status gauge".to_string(),
+            format!("mbr_check_status{{{}}} 2", component),
+            "# HELP mbr_checks_total Checks of the component by CheckMk status".to_string(),
+            "# TYPE mbr_checks_total counter".to_string(),
+            format!("mbr_checks_total{{{},status=\"critical\"}} 2", component),
+            "# HELP mbr_report_outbox_backlog Reports of the outbox waiting to be sent to the portal or rejected by it".to_string(),
+            "# TYPE mbr_report_outbox_backlog gauge".to_string(),
+            "mbr_report_outbox_backlog{state=\"failed\"} 0".to_string(),
+            "mbr_report_outbox_backlog{state=\"pending\"} 3".to_string(),
+        ];
+        assert_eq!(output.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        let metrics = Metrics::default();
+        metrics.set(
+            "mbr_ping_success_ratio",
+            component_labels(&component("c1", "a\"b\\c\nd"), false),
+            0.5,
+        );
+        let output = metrics.render_with_ttl(Duration::ZERO);
+        assert!(output.contains(r#"network="a\"b\\c\nd""#), "{}", output);
+        assert_eq!(format_value(f64::NAN), "NaN");
+        assert_eq!(format_value(f64::NEG_INFINITY), "-Inf");
+        assert_eq!(format_value(1.5), "1.5");
+    }
+
+    #[test]
+    fn stale_component_series_are_dropped() {
+        let metrics = Metrics::default();
+        let ttl = Duration::from_secs(60);
+        metrics.set(
+            "mbr_check_status",
+            component_labels(&component("old", "mainnet"), true),
+            0.0,
+        );
+        metrics.set(
+            "mbr_ping_success_ratio",
+            component_labels(&component("new", "mainnet"), true),
+            1.0,
+        );
+        metrics.add_queue_depth("check", 2);
+        set_age(&metrics, "mbr_check_status", ttl * 2);
+        set_age(&metrics, "mbr_check_queue_depth", ttl * 2);
+
+        // A ttl of 0 keeps every series
+        assert!(metrics
+            .render_with_ttl(Duration::ZERO)
+            .contains("mbr_check_status{"));
+        let output = metrics.render_with_ttl(ttl);
+        assert!(!output.contains("mbr_check_status"), "{}", output);
+        assert!(output.contains("component_id=\"new\""), "{}", output);
+        // Series without component labels are kept
+        assert!(output.contains("mbr_check_queue_depth{queue=\"check\"} 2"));
+        assert_eq!(lock(&metrics.series).len(), 2);
+    }
+}
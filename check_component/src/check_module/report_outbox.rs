@@ -0,0 +1,555 @@
+use crate::check_module::prometheus_metrics::METRICS;
+use crate::check_module::report_signature::{sign_report, ReportSigner};
+use crate::check_module::retry::{Backoff, HttpStatusError};
+use crate::check_module::store_report::StoreReport;
+use crate::check_module::util::{now_ms, write_atomic};
+use crate::{CONFIG, PORTAL_AUTHORIZATION};
+use anyhow::{bail, Error};
+use log::{debug, info, warn};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+// Rejected reports are moved to this sub directory instead of being retried
+const FAILED_DIR: &str = "failed";
+// Wait for new reports at most this time when the outbox is empty
+const IDLE_WAIT: Duration = Duration::from_secs(60);
+// Reports due at the same time are sent one after another, not in a busy loop
+const MIN_WAIT: Duration = Duration::from_millis(100);
+
+/// Portal request kept on disk until the portal accepted it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct OutboxEntry {
+    id: String,
+    url: String,
+    body: Value,
+    created_ms: u64,
+    attempts: usize,
+    next_attempt_ms: u64,
+    last_error: Option<String>,
+}
+
+/// Name of this process, e.g. `mbr-fisherman`.
+pub(crate) fn process_name() -> String {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| {
+            exe.file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+        })
+        .unwrap_or_else(|| "reports".to_string())
+}
+
+/// Reports for the portal are written to `report_outbox_dir` before they are sent, and removed
+/// when the portal accepted them. Failed sends are retried with `report_outbox_backoff`, also
+/// after a restart. A report queued again while it is in the outbox is sent once. Reports older
+/// than `report_outbox_max_age_ms` and the oldest beyond `report_outbox_max_reports` are dropped.
+/// Clones share the outbox.
+#[derive(Clone, Debug)]
+pub struct ReportOutbox {
+    dir: PathBuf,
+    notify: Arc<Notify>,
+    started: Arc<AtomicBool>,
+    // Reports are signed at every send, so that a retry is not rejected as too old or replayed,
+    // the signature keeps the creation time of the report
+    signer: Option<Arc<dyn ReportSigner>>,
+    backoff: Backoff,
+    max_age_ms: u64,
+    max_reports: usize,
+    // A portal request hanging longer is retried, so that it does not stop the outbox
+    timeout: Duration,
+}
+
+impl Default for ReportOutbox {
+    // Processes on the same host have their own outbox, e.g. `outbox/mbr-fisherman`
+    fn default() -> Self {
+        ReportOutbox::new(Path::new(&CONFIG.report_outbox_dir).join(process_name()))
+    }
+}
+
+impl ReportOutbox {
+    pub fn new(dir: PathBuf) -> Self {
+        ReportOutbox {
+            dir,
+            notify: Default::default(),
+            started: Default::default(),
+            signer: None,
+            backoff: CONFIG.report_outbox_backoff.clone(),
+            max_age_ms: CONFIG.report_outbox_max_age_ms,
+            max_reports: CONFIG.report_outbox_max_reports,
+            timeout: Duration::from_millis(CONFIG.report_outbox_timeout_ms),
+        }
+    }
+
+    pub fn with_signer(mut self, signer: Option<Arc<dyn ReportSigner>>) -> Self {
+        self.signer = signer;
+        self
+    }
+
+    /// Keep the request of the report until it is sent, returns false if the report is already
+    /// in the outbox. The file is written on a blocking thread, the outbox is pruned by the send
+    /// task.
+    pub async fn push(&self, report_id: &str, url: String, body: Value) -> Result<bool, Error> {
+        // The id is the file name of the entry
+        if report_id.is_empty() || !report_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            bail!("Invalid report id `{}`", report_id);
+        }
+        let outbox = self.clone();
+        let report_id = report_id.to_string();
+        let pushed =
+            tokio::task::spawn_blocking(move || outbox.write_new_entry(report_id, url, body))
+                .await??;
+        if pushed {
+            self.notify.notify_one();
+        }
+        Ok(pushed)
+    }
+
+    fn write_new_entry(&self, report_id: String, url: String, body: Value) -> Result<bool, Error> {
+        let path = self.entry_path(&report_id);
+        if path.exists() {
+            debug!("Report {} is already in the outbox", report_id);
+            return Ok(false);
+        }
+        std::fs::create_dir_all(&self.dir)?;
+        let now = now_ms();
+        let entry = OutboxEntry {
+            id: report_id,
+            url,
+            body,
+            created_ms: now,
+            attempts: 0,
+            next_attempt_ms: now,
+            last_error: None,
+        };
+        write_atomic(&path, &serde_json::to_string(&entry)?)?;
+        Ok(true)
+    }
+
+    /// Reports waiting to be sent and reports rejected by the portal.
+    pub fn backlog(&self) -> (usize, usize) {
+        (
+            Self::count_entries(&self.dir),
+            Self::count_entries(&self.dir.join(FAILED_DIR)),
+        )
+    }
+
+    /// Send reports of the outbox in the background, once per outbox.
+    pub fn start(&self, client: &Client) {
+        if !self.started.swap(true, Ordering::SeqCst) {
+            tokio::spawn(self.clone().run(client.clone()));
+        }
+    }
+
+    async fn run(self, client: Client) {
+        info!("Send reports of outbox {}", self.dir.display());
+        loop {
+            let wait = self.send_due(&client).await;
+            let (pending, failed) = self.update_backlog();
+            if pending > 0 {
+                info!(
+                    "Outbox {} has {} reports to send, {} rejected",
+                    self.dir.display(),
+                    pending,
+                    failed
+                );
+            }
+            tokio::select! {
+                _ = self.notify.notified() => {}
+                _ = tokio::time::sleep(wait.unwrap_or(IDLE_WAIT).max(MIN_WAIT)) => {}
+            }
+        }
+    }
+
+    /// Send due reports, oldest first. Returns the time until the next report is due.
+    /// After a failure the other reports wait too, the portal is likely down.
+    async fn send_due(&self, client: &Client) -> Option<Duration> {
+        self.prune(&self.dir, 0);
+        let mut entries = Self::read_entries(&self.dir);
+        entries.sort_by_key(|entry| entry.created_ms);
+        let mut next_attempt_ms: Option<u64> = None;
+        for mut entry in entries {
+            let now = now_ms();
+            if entry.next_attempt_ms > now {
+                next_attempt_ms = Some(
+                    next_attempt_ms
+                        .unwrap_or(u64::MAX)
+                        .min(entry.next_attempt_ms),
+                );
+                continue;
+            }
+            match self.send(client, &entry).await {
+                Ok(_) => {
+                    if let Err(err) = std::fs::remove_file(self.entry_path(&entry.id)) {
+                        warn!("Cannot remove sent report {}: {}", entry.id, err);
+                    }
+                }
+                Err(err) if Self::is_rejected(&err) => {
+                    warn!(
+                        "Portal rejected report {} of {}: {}",
+                        entry.id, entry.url, err
+                    );
+                    entry.last_error = Some(err.to_string());
+                    self.move_to_failed(&entry);
+                }
+                Err(err) => {
+                    entry.attempts += 1;
+                    let delay = self.backoff.delay(entry.attempts);
+                    warn!(
+                        "Cannot send report {} to {} (attempt {}), retry in {:?}: {}",
+                        entry.id, entry.url, entry.attempts, delay, err
+                    );
+                    entry.next_attempt_ms = now + delay.as_millis() as u64;
+                    entry.last_error = Some(err.to_string());
+                    self.write_entry(&entry);
+                    return Some(delay);
+                }
+            }
+        }
+        next_attempt_ms
+            .map(|next_attempt_ms| Duration::from_millis(next_attempt_ms.saturating_sub(now_ms())))
+    }
+
+    async fn send(&self, client: &Client, entry: &OutboxEntry) -> Result<(), Error> {
+        let body = match &self.signer {
+            Some(signer) => sign_report(&entry.body, signer.as_ref(), entry.created_ms)?,
+            None => entry.body.clone(),
+        };
+        let response = StoreReport::send_portal_request(
+            client,
+            &entry.url,
+            &body,
+            PORTAL_AUTHORIZATION.as_str(),
+            self.timeout,
+        )
+        .await?;
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(Error::new(HttpStatusError(status.as_u16()))
+                .context(format!("portal answered {}: {}", status, text)));
+        }
+        info!("Portal response of report {}: {}", entry.id, text);
+        Ok(())
+    }
+
+    // Client errors are not retried, except time out and too many requests
+    fn is_rejected(err: &Error) -> bool {
+        err.downcast_ref::<HttpStatusError>()
+            .map(|status| (400..500).contains(&status.0) && status.0 != 408 && status.0 != 429)
+            .unwrap_or_default()
+    }
+
+    fn entry_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn read_entries(dir: &Path) -> Vec<OutboxEntry> {
+        let files = match std::fs::read_dir(dir) {
+            Ok(files) => files,
+            Err(_) => return Vec::new(),
+        };
+        files
+            .filter_map(|file| file.ok().map(|file| file.path()))
+            .filter(|path| Self::is_entry(path))
+            .filter_map(|path| {
+                let entry = std::fs::read_to_string(&path)
+                    .map_err(Error::from)
+                    .and_then(|json| Ok(serde_json::from_str::<OutboxEntry>(&json)?));
+                match entry {
+                    Ok(entry) => Some(entry),
+                    Err(err) => {
+                        warn!("Cannot read outbox report {}: {}", path.display(), err);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn write_entry(&self, entry: &OutboxEntry) {
+        let written = serde_json::to_string(entry)
+            .map_err(Error::from)
+            .and_then(|json| Ok(write_atomic(&self.entry_path(&entry.id), &json)?));
+        if let Err(err) = written {
+            warn!("Cannot update outbox report {}: {}", entry.id, err);
+        }
+    }
+
+    fn move_to_failed(&self, entry: &OutboxEntry) {
+        let failed_dir = self.dir.join(FAILED_DIR);
+        let moved = std::fs::create_dir_all(&failed_dir)
+            .map_err(Error::from)
+            .map(|_| self.prune(&failed_dir, 1))
+            .and_then(|_| Ok(serde_json::to_string(entry)?))
+            .and_then(|json| {
+                write_atomic(&failed_dir.join(format!("{}.json", entry.id)), &json)?;
+                Ok(std::fs::remove_file(self.entry_path(&entry.id))?)
+            });
+        if let Err(err) = moved {
+            warn!("Cannot move rejected report {}: {}", entry.id, err);
+        }
+    }
+
+    // Drop reports older than `max_age_ms` and the oldest reports beyond `max_reports`, keeping
+    // room for `room` new reports
+    fn prune(&self, dir: &Path, room: usize) {
+        let mut entries = Self::read_entries(dir);
+        entries.sort_by_key(|entry| entry.created_ms);
+        let now = now_ms();
+        let expired = entries
+            .iter()
+            .take_while(|entry| {
+                self.max_age_ms > 0 && entry.created_ms.saturating_add(self.max_age_ms) < now
+            })
+            .count();
+        let excess = match self.max_reports {
+            0 => 0,
+            max_reports => (entries.len() + room).saturating_sub(max_reports),
+        };
+        for entry in entries.iter().take(expired.max(excess)) {
+            warn!(
+                "Drop report {} of {} from {}, created at {} ms, {} attempts",
+                entry.id,
+                entry.url,
+                dir.display(),
+                entry.created_ms,
+                entry.attempts
+            );
+            if let Err(err) = std::fs::remove_file(dir.join(format!("{}.json", entry.id))) {
+                warn!("Cannot remove report {}: {}", entry.id, err);
+            }
+        }
+    }
+
+    fn update_backlog(&self) -> (usize, usize) {
+        let (pending, failed) = self.backlog();
+        METRICS.set_outbox_backlog(pending, failed);
+        (pending, failed)
+    }
+
+    // Entries are `<id>.json`, hidden files are being written
+    fn is_entry(path: &Path) -> bool {
+        path.is_file()
+            && path
+                .extension()
+                .map(|ext| ext == "json")
+                .unwrap_or_default()
+            && !path
+                .file_name()
+                .map(|name| name.to_string_lossy().starts_with('.'))
+                .unwrap_or_default()
+    }
+
+    fn count_entries(dir: &Path) -> usize {
+        std::fs::read_dir(dir)
+            .map(|files| {
+                files
+                    .filter_map(|file| file.ok())
+                    .filter(|file| Self::is_entry(&file.path()))
+                    .count()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check_module::util::lock;
+    use rand::Rng;
+    use serde_json::json;
+    use std::collections::VecDeque;
+    use std::convert::Infallible;
+    use std::sync::Mutex;
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    struct TestOutbox {
+        outbox: ReportOutbox,
+    }
+
+    impl Drop for TestOutbox {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.outbox.dir);
+        }
+    }
+
+    fn test_outbox() -> TestOutbox {
+        let id: [u8; 8] = rand::thread_rng().gen();
+        let mut outbox = ReportOutbox::new(
+            std::env::temp_dir().join(format!("mbr-outbox-test-{}", hex::encode(id))),
+        );
+        outbox.backoff = Backoff::Exponential {
+            delay_ms: 1000,
+            multiplier: 2.0,
+            max_delay_ms: None,
+        };
+        TestOutbox { outbox }
+    }
+
+    fn read_entry(dir: &Path, id: &str) -> OutboxEntry {
+        let json = std::fs::read_to_string(dir.join(format!("{}.json", id))).unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn make_due(outbox: &ReportOutbox, id: &str) {
+        let mut entry = read_entry(&outbox.dir, id);
+        entry.next_attempt_ms = 0;
+        outbox.write_entry(&entry);
+    }
+
+    // Portal answering the given statuses, then 200
+    fn portal(statuses: &[u16]) -> (String, Arc<Mutex<VecDeque<u16>>>) {
+        portal_with_delay(statuses, Duration::ZERO)
+    }
+
+    fn portal_with_delay(statuses: &[u16], delay: Duration) -> (String, Arc<Mutex<VecDeque<u16>>>) {
+        std::env::set_var("PORTAL_AUTHORIZATION", "test");
+        let statuses = Arc::new(Mutex::new(
+            statuses.iter().copied().collect::<VecDeque<_>>(),
+        ));
+        let answers = statuses.clone();
+        let route = warp::post().and_then(move || {
+            let status = lock(&answers).pop_front().unwrap_or(200);
+            async move {
+                tokio::time::sleep(delay).await;
+                Ok::<_, Infallible>(warp::reply::with_status(
+                    "answer",
+                    StatusCode::from_u16(status).unwrap(),
+                ))
+            }
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (format!("http://{}/mbr/verify/provider", addr), statuses)
+    }
+
+    #[tokio::test]
+    async fn push_keys_entries_on_report_id() {
+        let test = test_outbox();
+        let outbox = &test.outbox;
+        let body = json!({"provider_id": "provider"});
+        assert!(outbox
+            .push("a1", "url".to_string(), body.clone())
+            .await
+            .unwrap());
+        assert!(!outbox
+            .push("a1", "url".to_string(), body.clone())
+            .await
+            .unwrap());
+        // Same request of another report
+        assert!(outbox
+            .push("b2", "url".to_string(), body.clone())
+            .await
+            .unwrap());
+        assert_eq!(outbox.backlog(), (2, 0));
+        assert!(outbox
+            .push("", "url".to_string(), body.clone())
+            .await
+            .is_err());
+        assert!(outbox.push("../a", "url".to_string(), body).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn prune_drops_expired_and_oldest_reports() {
+        let mut test = test_outbox();
+        test.outbox.max_reports = 3;
+        test.outbox.max_age_ms = 60000;
+        let outbox = &test.outbox;
+        for (index, id) in ["a", "b", "c"].iter().enumerate() {
+            outbox.push(id, "url".to_string(), json!({})).await.unwrap();
+            let mut entry = read_entry(&outbox.dir, id);
+            entry.created_ms = now_ms() - 3000 + index as u64 * 1000;
+            outbox.write_entry(&entry);
+        }
+        outbox
+            .push("d", "url".to_string(), json!({}))
+            .await
+            .unwrap();
+        assert_eq!(outbox.backlog(), (4, 0));
+        // The oldest report is dropped
+        outbox.prune(&outbox.dir, 0);
+        let mut ids: Vec<_> = ReportOutbox::read_entries(&outbox.dir)
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["b", "c", "d"]);
+
+        let mut entry = read_entry(&outbox.dir, "c");
+        entry.created_ms = now_ms() - 61000;
+        outbox.write_entry(&entry);
+        outbox.prune(&outbox.dir, 0);
+        assert_eq!(outbox.backlog(), (2, 0));
+        assert!(!outbox.entry_path("c").exists());
+    }
+
+    #[tokio::test]
+    async fn failed_sends_are_retried_with_backoff() {
+        let test = test_outbox();
+        let outbox = &test.outbox;
+        let (url, statuses) = portal(&[500, 503, 429]);
+        let client = Client::new();
+        outbox.push("a", url, json!({})).await.unwrap();
+
+        let before = now_ms();
+        assert_eq!(outbox.send_due(&client).await, Some(Duration::from_secs(1)));
+        let entry = read_entry(&outbox.dir, "a");
+        assert_eq!(entry.attempts, 1);
+        assert!(entry.next_attempt_ms >= before + 1000);
+        assert!(entry.last_error.unwrap().contains("500"));
+
+        // Not due yet, the portal is not asked
+        let wait = outbox.send_due(&client).await.unwrap();
+        assert!(wait <= Duration::from_secs(1));
+        assert_eq!(lock(&statuses).len(), 2);
+
+        make_due(outbox, "a");
+        assert_eq!(outbox.send_due(&client).await, Some(Duration::from_secs(2)));
+        make_due(outbox, "a");
+        assert_eq!(outbox.send_due(&client).await, Some(Duration::from_secs(4)));
+        assert_eq!(read_entry(&outbox.dir, "a").attempts, 3);
+
+        make_due(outbox, "a");
+        assert_eq!(outbox.send_due(&client).await, None);
+        assert_eq!(outbox.backlog(), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn rejected_reports_are_moved_to_failed() {
+        let test = test_outbox();
+        let outbox = &test.outbox;
+        let (url, _) = portal(&[400]);
+        outbox.push("a", url, json!({})).await.unwrap();
+
+        assert_eq!(outbox.send_due(&Client::new()).await, None);
+        assert_eq!(outbox.backlog(), (0, 1));
+        let entry = read_entry(&outbox.dir.join(FAILED_DIR), "a");
+        assert_eq!(entry.attempts, 0);
+        assert!(entry.last_error.unwrap().contains("400"));
+    }
+
+    #[tokio::test]
+    async fn hanging_sends_time_out_and_are_retried() {
+        let mut test = test_outbox();
+        test.outbox.timeout = Duration::from_millis(100);
+        let outbox = &test.outbox;
+        let (url, _) = portal_with_delay(&[], Duration::from_secs(5));
+        outbox.push("a", url, json!({})).await.unwrap();
+
+        let client = Client::new();
+        // The request fails after the outbox timeout, not after the answer of the portal
+        let sent = tokio::time::timeout(Duration::from_secs(2), outbox.send_due(&client)).await;
+        assert_eq!(sent.unwrap(), Some(Duration::from_secs(1)));
+        let entry = read_entry(&outbox.dir, "a");
+        assert_eq!(entry.attempts, 1);
+        assert!(entry.last_error.is_some());
+        assert_eq!(outbox.backlog(), (1, 0));
+    }
+}
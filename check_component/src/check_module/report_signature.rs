@@ -0,0 +1,383 @@
+use crate::check_module::util::{lock, now_ms};
+use anyhow::Error;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Key of the signature in the report body
+pub const SIGNATURE_KEY: &str = "signature";
+// The shared portal authorization is not signed, it is added to the body when it is sent
+const AUTHORIZATION_KEY: &str = "authorization";
+const NONCE_BYTES: usize = 16;
+
+/// Key pair signing report bodies, e.g. the sr25519 key of the fisherman from `SIGNER_PHRASE`.
+pub trait ReportSigner: Debug + Send + Sync {
+    // Signature scheme, e.g. `sr25519`
+    fn scheme(&self) -> String;
+
+    fn public_key(&self) -> Vec<u8>;
+
+    fn sign(&self, message: &[u8]) -> Vec<u8>;
+}
+
+/// Signature of a report body, under `signature` of the body.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ReportSignature {
+    pub scheme: String,
+    // Hex with 0x prefix
+    pub public_key: String,
+    // Random hex, a nonce is accepted once per public key
+    pub nonce: String,
+    // Unix time in milliseconds of the signing, a report of the outbox is signed again at every send
+    pub timestamp: u64,
+    // Unix time in milliseconds the report was created, before the first send
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_ms: Option<u64>,
+    // Hex with 0x prefix, of the canonical body without `signature.signature` and `authorization`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn from_hex(value: &str) -> Result<Vec<u8>, SignatureError> {
+    hex::decode(value.trim_start_matches("0x"))
+        .map_err(|err| SignatureError::Malformed(format!("{}: {}", value, err)))
+}
+
+/// Json with sorted object keys and without spaces, the signed form of a body.
+pub fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let mut keys = map.keys().collect::<Vec<_>>();
+            keys.sort();
+            let fields = keys
+                .into_iter()
+                .map(|key| {
+                    format!(
+                        "{}:{}",
+                        Value::String(key.clone()),
+                        canonical_json(&map[key])
+                    )
+                })
+                .collect::<Vec<String>>();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(items) => format!(
+            "[{}]",
+            items
+                .iter()
+                .map(canonical_json)
+                .collect::<Vec<String>>()
+                .join(",")
+        ),
+        value => value.to_string(),
+    }
+}
+
+// Body as signed: signature without its value, without the portal authorization
+fn signed_message(body: &Value, signature: &ReportSignature) -> Result<Vec<u8>, Error> {
+    let mut body = body.clone();
+    let fields = body
+        .as_object_mut()
+        .ok_or_else(|| Error::msg("report body is not an object"))?;
+    fields.remove(AUTHORIZATION_KEY);
+    let unsigned = ReportSignature {
+        signature: None,
+        ..signature.clone()
+    };
+    fields.insert(SIGNATURE_KEY.to_string(), serde_json::to_value(unsigned)?);
+    Ok(canonical_json(&body).into_bytes())
+}
+
+/// Body with `signature`: public key of the signer, a new nonce, the current time, the creation
+/// time of the report and the signature of all of them with the body.
+pub fn sign_report(
+    body: &Value,
+    signer: &dyn ReportSigner,
+    created_ms: u64,
+) -> Result<Value, Error> {
+    let mut nonce = [0u8; NONCE_BYTES];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let mut signature = ReportSignature {
+        scheme: signer.scheme(),
+        public_key: to_hex(&signer.public_key()),
+        nonce: hex::encode(nonce),
+        timestamp: now_ms(),
+        created_ms: Some(created_ms),
+        signature: None,
+    };
+    let message = signed_message(body, &signature)?;
+    signature.signature = Some(to_hex(&signer.sign(&message)));
+    let mut body = body.clone();
+    if let Some(fields) = body.as_object_mut() {
+        fields.insert(SIGNATURE_KEY.to_string(), serde_json::to_value(signature)?);
+    }
+    Ok(body)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SignatureError {
+    Missing,
+    Malformed(String),
+    // Timestamp is further than the accepted age from now
+    Expired { timestamp: u64, now: u64 },
+    Invalid,
+    Replayed { nonce: String },
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "report is not signed"),
+            SignatureError::Malformed(err) => write!(f, "malformed report signature: {}", err),
+            SignatureError::Expired { timestamp, now } => write!(
+                f,
+                "report signed at {} is too old or too far in the future at {}",
+                timestamp, now
+            ),
+            SignatureError::Invalid => write!(f, "invalid report signature"),
+            SignatureError::Replayed { nonce } => write!(f, "report nonce {} is replayed", nonce),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Verifies signed reports and rejects replays: reports signed more than `max_age` from now
+/// and nonces already accepted for the public key within `max_age`.
+#[derive(Debug)]
+pub struct ReplayGuard {
+    max_age: Duration,
+    // Accepted nonces per public key with their timestamp
+    seen: Mutex<HashMap<(String, String), u64>>,
+}
+
+impl ReplayGuard {
+    pub fn new(max_age: Duration) -> Self {
+        ReplayGuard {
+            max_age,
+            seen: Default::default(),
+        }
+    }
+
+    /// Signature of the body if it is valid and not replayed. `verify` checks a signature of
+    /// the scheme: `verify(scheme, public_key, message, signature)`.
+    pub fn verify_report<F>(
+        &self,
+        body: &Value,
+        verify: F,
+    ) -> Result<ReportSignature, SignatureError>
+    where
+        F: Fn(&str, &[u8], &[u8], &[u8]) -> bool,
+    {
+        let signature = body.get(SIGNATURE_KEY).ok_or(SignatureError::Missing)?;
+        let signature: ReportSignature = serde_json::from_value(signature.clone())
+            .map_err(|err| SignatureError::Malformed(err.to_string()))?;
+        let signature_bytes = from_hex(
+            signature
+                .signature
+                .as_deref()
+                .ok_or(SignatureError::Missing)?,
+        )?;
+        let public_key = from_hex(&signature.public_key)?;
+
+        let now = now_ms();
+        let max_age_ms = self.max_age.as_millis() as u64;
+        if signature.timestamp.abs_diff(now) > max_age_ms {
+            return Err(SignatureError::Expired {
+                timestamp: signature.timestamp,
+                now,
+            });
+        }
+        let message = signed_message(body, &signature)
+            .map_err(|err| SignatureError::Malformed(err.to_string()))?;
+        if !verify(&signature.scheme, &public_key, &message, &signature_bytes) {
+            return Err(SignatureError::Invalid);
+        }
+        // Nonces are recorded after verification, so that forged reports cannot burn them
+        let mut seen = lock(&self.seen);
+        seen.retain(|_, timestamp| timestamp.saturating_add(max_age_ms) >= now);
+        let key = (signature.public_key.clone(), signature.nonce.clone());
+        if seen.contains_key(&key) {
+            return Err(SignatureError::Replayed {
+                nonce: signature.nonce,
+            });
+        }
+        seen.insert(key, signature.timestamp);
+        Ok(signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+
+    // Signature is the sha256 of the key and the message, the key is public
+    #[derive(Debug)]
+    struct TestSigner {
+        key: Vec<u8>,
+    }
+
+    impl ReportSigner for TestSigner {
+        fn scheme(&self) -> String {
+            "test".to_string()
+        }
+
+        fn public_key(&self) -> Vec<u8> {
+            self.key.clone()
+        }
+
+        fn sign(&self, message: &[u8]) -> Vec<u8> {
+            Sha256::new()
+                .chain_update(&self.key)
+                .chain_update(message)
+                .finalize()
+                .to_vec()
+        }
+    }
+
+    fn verify_test(scheme: &str, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        let signer = TestSigner {
+            key: public_key.to_vec(),
+        };
+        scheme == "test" && signer.sign(message) == signature
+    }
+
+    const CREATED_MS: u64 = 1660000000000;
+
+    fn signer() -> TestSigner {
+        TestSigner {
+            key: b"fisherman".to_vec(),
+        }
+    }
+
+    fn body() -> Value {
+        json!({"provider_id": "provider", "is_data_correct": true, "authorization": "portal"})
+    }
+
+    // Body signed at `timestamp` instead of now
+    fn sign_at(body: &Value, signer: &TestSigner, timestamp: u64) -> Value {
+        let mut signature = ReportSignature {
+            scheme: signer.scheme(),
+            public_key: to_hex(&signer.public_key()),
+            nonce: "00".to_string(),
+            timestamp,
+            created_ms: None,
+            signature: None,
+        };
+        let message = signed_message(body, &signature).unwrap();
+        signature.signature = Some(to_hex(&signer.sign(&message)));
+        let mut body = body.clone();
+        body[SIGNATURE_KEY] = serde_json::to_value(signature).unwrap();
+        body
+    }
+
+    fn guard() -> ReplayGuard {
+        ReplayGuard::new(Duration::from_secs(60))
+    }
+
+    #[test]
+    fn signed_report_round_trip() {
+        let signed = sign_report(&body(), &signer(), CREATED_MS).unwrap();
+        let signature = guard().verify_report(&signed, verify_test).unwrap();
+        assert_eq!(signature.public_key, to_hex(b"fisherman"));
+        assert_eq!(signature.nonce.len(), NONCE_BYTES * 2);
+        assert_eq!(signature.created_ms, Some(CREATED_MS));
+        // The portal authorization is added after signing
+        let mut sent = signed;
+        sent[AUTHORIZATION_KEY] = json!("other");
+        assert!(guard().verify_report(&sent, verify_test).is_ok());
+        assert_eq!(
+            guard().verify_report(&body(), verify_test),
+            Err(SignatureError::Missing)
+        );
+    }
+
+    #[test]
+    fn tampered_body_is_invalid() {
+        let mut signed = sign_report(&body(), &signer(), CREATED_MS).unwrap();
+        signed["is_data_correct"] = json!(false);
+        assert_eq!(
+            guard().verify_report(&signed, verify_test),
+            Err(SignatureError::Invalid)
+        );
+        let mut signed = sign_report(&body(), &signer(), CREATED_MS).unwrap();
+        signed[SIGNATURE_KEY]["nonce"] = json!("01");
+        assert_eq!(
+            guard().verify_report(&signed, verify_test),
+            Err(SignatureError::Invalid)
+        );
+        let mut signed = sign_report(&body(), &signer(), CREATED_MS).unwrap();
+        signed[SIGNATURE_KEY]["created_ms"] = json!(CREATED_MS + 1);
+        assert_eq!(
+            guard().verify_report(&signed, verify_test),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn wrong_key_is_invalid() {
+        let mut signed = sign_report(&body(), &signer(), CREATED_MS).unwrap();
+        signed[SIGNATURE_KEY]["public_key"] = json!(to_hex(b"other"));
+        assert_eq!(
+            guard().verify_report(&signed, verify_test),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn timestamps_beyond_max_age_are_expired() {
+        let now = now_ms();
+        for timestamp in [now - 61000, now + 61000, 0, u64::MAX] {
+            let signed = sign_at(&body(), &signer(), timestamp);
+            assert!(
+                matches!(
+                    guard().verify_report(&signed, verify_test),
+                    Err(SignatureError::Expired { .. })
+                ),
+                "{}",
+                timestamp
+            );
+        }
+        let signed = sign_at(&body(), &signer(), now - 30000);
+        assert!(guard().verify_report(&signed, verify_test).is_ok());
+        // No overflow with the longest max age
+        let signed = sign_at(&body(), &signer(), u64::MAX);
+        assert!(ReplayGuard::new(Duration::MAX)
+            .verify_report(&signed, verify_test)
+            .is_ok());
+    }
+
+    #[test]
+    fn replayed_nonce_is_rejected() {
+        let guard = guard();
+        let signed = sign_report(&body(), &signer(), CREATED_MS).unwrap();
+        assert!(guard.verify_report(&signed, verify_test).is_ok());
+        assert_eq!(
+            guard.verify_report(&signed, verify_test),
+            Err(SignatureError::Replayed {
+                nonce: signed[SIGNATURE_KEY]["nonce"].as_str().unwrap().to_string()
+            })
+        );
+        // Nonces are per public key
+        let signed = sign_at(&body(), &signer(), now_ms());
+        assert!(guard.verify_report(&signed, verify_test).is_ok());
+        let other = TestSigner {
+            key: b"other".to_vec(),
+        };
+        let signed = sign_at(&body(), &other, now_ms());
+        assert!(guard.verify_report(&signed, verify_test).is_ok());
+        let signed = sign_at(&body(), &other, now_ms());
+        assert!(guard.verify_report(&signed, verify_test).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+use crate::check_module::check_module::{CheckMkReport, ComponentInfo};
+use crate::check_module::report_outbox::ReportOutbox;
+use crate::check_module::report_signature::ReportSigner;
+use crate::check_module::store_report::{SendPurpose, StoreReport};
+use crate::CONFIG;
+use anyhow::Error;
+use futures::future::{join_all, BoxFuture};
+use log::{info, warn};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// Report sink of `report_sinks` in `config_check_component.json`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReportSinkConfig {
+    // `https://portal.{domain}/mbr/...` with the portal authorization
+    Portal,
+    // One JSON report per line appended to the file
+    JsonLines {
+        path: String,
+    },
+    Stdout,
+    // JSON report posted to the url
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        #[serde(default = "default_webhook_timeout_ms")]
+        timeout_ms: u64,
+    },
+}
+
+fn default_webhook_timeout_ms() -> u64 {
+    5000
+}
+
+/// Report of a component sent to every sink: the check report if the component was checked
+/// and the portal report with the benchmark data.
+#[derive(Debug, Serialize)]
+pub struct ReportEvent<'a> {
+    pub purpose: SendPurpose,
+    pub component: &'a ComponentInfo,
+    pub check_mk_report: Option<&'a CheckMkReport>,
+    pub report: &'a StoreReport,
+}
+
+impl<'a> ReportEvent<'a> {
+    /// Report for sinks other than the portal, without the portal authorization and api key.
+    pub fn to_json(&self) -> Result<Value, Error> {
+        let mut value = serde_json::to_value(self)?;
+        if let Some(report) = value.get_mut("report").and_then(Value::as_object_mut) {
+            report.remove("authorization");
+        }
+        if let Some(component) = value.get_mut("component").and_then(Value::as_object_mut) {
+            component.remove("appKey");
+        }
+        Ok(value)
+    }
+}
+
+pub trait ReportSink: Debug + Send + Sync {
+    fn name(&self) -> String;
+
+    /// Start background work of the sink, e.g. sending reports kept from a previous run.
+    fn start(&self, _client: &Client) {}
+
+    fn send<'a>(
+        &'a self,
+        client: &'a Client,
+        event: &'a ReportEvent<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+}
+
+// Reports are sent by the outbox, so that they are not lost while the portal is down
+#[derive(Debug, Default)]
+pub struct PortalSink {
+    outbox: ReportOutbox,
+}
+
+impl PortalSink {
+    pub fn new(signer: Option<Arc<dyn ReportSigner>>) -> Self {
+        PortalSink {
+            outbox: ReportOutbox::default().with_signer(signer),
+        }
+    }
+}
+
+impl ReportSink for PortalSink {
+    fn name(&self) -> String {
+        "portal".to_string()
+    }
+
+    fn start(&self, client: &Client) {
+        self.outbox.start(client);
+    }
+
+    fn send<'a>(
+        &'a self,
+        client: &'a Client,
+        event: &'a ReportEvent<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            self.outbox.start(client);
+            let (url, body) = event.report.portal_request(event.purpose)?;
+            if self.outbox.push(&event.report.report_id, url, body).await? {
+                info!("Queued report of {} for portal", event.component.id);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct JsonLinesSink {
+    path: String,
+    // Lines of concurrent reports are not interleaved
+    lock: Mutex<()>,
+}
+
+impl ReportSink for JsonLinesSink {
+    fn name(&self) -> String {
+        format!("json_lines {}", self.path)
+    }
+
+    fn send<'a>(
+        &'a self,
+        _client: &'a Client,
+        event: &'a ReportEvent<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let mut line = serde_json::to_string(&event.to_json()?)?;
+            line.push('\n');
+            let _lock = self.lock.lock().await;
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await?;
+            file.write_all(line.as_bytes()).await?;
+            file.flush().await?;
+            Ok(())
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct StdoutSink;
+
+impl ReportSink for StdoutSink {
+    fn name(&self) -> String {
+        "stdout".to_string()
+    }
+
+    fn send<'a>(
+        &'a self,
+        _client: &'a Client,
+        event: &'a ReportEvent<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            println!("{}", serde_json::to_string(&event.to_json()?)?);
+            Ok(())
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct WebhookSink {
+    url: String,
+    headers: HashMap<String, String>,
+    timeout: Duration,
+}
+
+impl ReportSink for WebhookSink {
+    fn name(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    fn send<'a>(
+        &'a self,
+        client: &'a Client,
+        event: &'a ReportEvent<'a>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let mut request_builder = client
+                .post(&self.url)
+                .timeout(self.timeout)
+                .header("content-type", "application/json")
+                .body(serde_json::to_string(&event.to_json()?)?);
+            for (key, value) in self.headers.iter() {
+                request_builder = request_builder.header(key, value);
+            }
+            request_builder.send().await?.error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+impl ReportSinkConfig {
+    // Portal reports are signed by the signer, if any
+    fn create_sink(&self, signer: &Option<Arc<dyn ReportSigner>>) -> Arc<dyn ReportSink> {
+        match self {
+            ReportSinkConfig::Portal => Arc::new(PortalSink::new(signer.clone())),
+            ReportSinkConfig::JsonLines { path } => Arc::new(JsonLinesSink {
+                path: path.clone(),
+                lock: Mutex::new(()),
+            }),
+            ReportSinkConfig::Stdout => Arc::new(StdoutSink),
+            ReportSinkConfig::Webhook {
+                url,
+                headers,
+                timeout_ms,
+            } => Arc::new(WebhookSink {
+                url: url.clone(),
+                headers: headers.clone(),
+                timeout: Duration::from_millis(*timeout_ms),
+            }),
+        }
+    }
+}
+
+/// Sinks of `report_sinks`, every report is sent to all of them. Clones share the sinks.
+#[derive(Clone, Debug)]
+pub struct ReportSinks {
+    sinks: Arc<Vec<Arc<dyn ReportSink>>>,
+}
+
+impl Default for ReportSinks {
+    fn default() -> Self {
+        ReportSinks::new(&CONFIG.report_sinks, None)
+    }
+}
+
+impl ReportSinks {
+    pub fn new(configs: &[ReportSinkConfig], signer: Option<Arc<dyn ReportSigner>>) -> Self {
+        ReportSinks {
+            sinks: Arc::new(
+                configs
+                    .iter()
+                    .map(|config| config.create_sink(&signer))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Start background work of the sinks, e.g. send reports kept in the outbox by a previous run.
+    pub fn start(&self, client: &Client) {
+        for sink in self.sinks.iter() {
+            sink.start(client);
+        }
+    }
+
+    /// Send the report to all sinks at once, a failing sink does not stop the others.
+    /// Returns false if a sink failed.
+    pub async fn send(&self, client: &Client, event: &ReportEvent<'_>) -> bool {
+        let results = join_all(self.sinks.iter().map(|sink| sink.send(client, event))).await;
+        let mut success = true;
+        for (sink, result) in self.sinks.iter().zip(results) {
+            if let Err(err) = result {
+                warn!(
+                    "Cannot send report of {} to {}: {}",
+                    event.component.id,
+                    sink.name(),
+                    err
+                );
+                success = false;
+            }
+        }
+        success
+    }
+}
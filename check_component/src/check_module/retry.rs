@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// Retry of a failed step, e.g.
+/// `{"attempts": 3, "backoff": {"type": "exponential", "delay_ms": 500}, "retry_on": ["timeout"]}`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicy {
+    // Number of attempts including the first one
+    #[serde(default = "default_attempts")]
+    pub(crate) attempts: usize,
+    #[serde(default)]
+    backoff: Backoff,
+    #[serde(default = "default_retry_on")]
+    retry_on: Vec<RetryOn>,
+}
+
+fn default_attempts() -> usize {
+    2
+}
+
+fn default_retry_on() -> Vec<RetryOn> {
+    vec![RetryOn::Timeout, RetryOn::Connection]
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Backoff {
+    Fixed {
+        delay_ms: u64,
+    },
+    // Delay is multiplied after each attempt, up to `max_delay_ms`
+    Exponential {
+        delay_ms: u64,
+        #[serde(default = "default_multiplier")]
+        multiplier: f64,
+        #[serde(default)]
+        max_delay_ms: Option<u64>,
+    },
+}
+
+fn default_multiplier() -> f64 {
+    2.0
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::Fixed { delay_ms: 500 }
+    }
+}
+
+/// Error classes a step can be retried on.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryOn {
+    // Request, connection or response time out
+    Timeout,
+    // Connection refused, reset, dns or tls failure
+    Connection,
+    // Response with http status 5xx
+    ServerError,
+    // Any error
+    Any,
+}
+
+/// Http status of a response which cannot be handled by the step.
+#[derive(Debug)]
+pub struct HttpStatusError(pub u16);
+
+impl fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http status {}", self.0)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+impl RetryOn {
+    fn matches(&self, err: &anyhow::Error) -> bool {
+        match self {
+            RetryOn::Any => true,
+            RetryOn::Timeout => {
+                err.downcast_ref::<tokio::time::error::Elapsed>().is_some()
+                    || err
+                        .downcast_ref::<reqwest::Error>()
+                        .map(|err| err.is_timeout())
+                        .unwrap_or_default()
+            }
+            RetryOn::Connection => {
+                err.downcast_ref::<reqwest::Error>()
+                    .map(|err| err.is_connect())
+                    .unwrap_or_default()
+                    || err.downcast_ref::<std::io::Error>().is_some()
+                    || matches!(
+                        err.downcast_ref::<tokio_tungstenite::tungstenite::Error>(),
+                        Some(tokio_tungstenite::tungstenite::Error::Io(_))
+                            | Some(tokio_tungstenite::tungstenite::Error::Tls(_))
+                            | Some(tokio_tungstenite::tungstenite::Error::ConnectionClosed)
+                            | Some(tokio_tungstenite::tungstenite::Error::AlreadyClosed)
+                    )
+            }
+            RetryOn::ServerError => err
+                .downcast_ref::<HttpStatusError>()
+                .map(|status| status.0 >= 500)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether the error of the attempt (1-based) should be retried.
+    pub fn should_retry(&self, attempt: usize, err: &anyhow::Error) -> bool {
+        attempt < self.attempts && self.retry_on.iter().any(|retry_on| retry_on.matches(err))
+    }
+
+    /// Delay before the next attempt after the attempt (1-based) failed.
+    pub fn delay(&self, attempt: usize) -> Duration {
+        self.backoff.delay(attempt)
+    }
+}
+
+impl Backoff {
+    /// Delay before the next attempt after the attempt (1-based) failed.
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let delay_ms = match self {
+            Backoff::Fixed { delay_ms } => *delay_ms,
+            Backoff::Exponential {
+                delay_ms,
+                multiplier,
+                max_delay_ms,
+            } => {
+                let delay_ms = *delay_ms as f64 * multiplier.powi(attempt as i32 - 1);
+                match max_delay_ms {
+                    Some(max_delay_ms) => delay_ms.min(*max_delay_ms as f64) as u64,
+                    None => delay_ms as u64,
+                }
+            }
+        };
+        Duration::from_millis(delay_ms)
+    }
+}
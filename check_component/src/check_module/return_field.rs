@@ -0,0 +1,245 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use serde_json_path::JsonPath;
+
+/// Field extracted from a call response into step result.
+///
+/// A path is either JSONPath (`$.result.transactions[0].hash`), dotted path without `$`
+/// (`result.transactions[0].hash`) or the legacy slash separated path (`result/hash`).
+/// An empty path returns the whole response.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ReturnField {
+    Path(String),
+    Detail(ReturnFieldDetail),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReturnFieldDetail {
+    path: String,
+    // Used when path is not found in the response, `null` is a valid default
+    #[serde(default, deserialize_with = "deserialize_some")]
+    default: Option<Value>,
+    // Return length of found array, object or string instead of the value
+    #[serde(default)]
+    length: bool,
+}
+
+fn deserialize_some<'de, D>(deserializer: D) -> Result<Option<Value>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Value::deserialize(deserializer).map(Some)
+}
+
+impl Default for ReturnField {
+    fn default() -> Self {
+        ReturnField::Path(String::new())
+    }
+}
+
+impl ReturnField {
+    pub fn path(&self) -> &String {
+        match self {
+            ReturnField::Path(path) => path,
+            ReturnField::Detail(detail) => &detail.path,
+        }
+    }
+
+    fn default_value(&self) -> Option<&Value> {
+        match self {
+            ReturnField::Path(_) => None,
+            ReturnField::Detail(detail) => detail.default.as_ref(),
+        }
+    }
+
+    fn is_length(&self) -> bool {
+        match self {
+            ReturnField::Path(_) => false,
+            ReturnField::Detail(detail) => detail.length,
+        }
+    }
+
+    /// Convert the path into JSONPath syntax.
+    pub fn json_path(&self) -> String {
+        let path = self.path().trim();
+        if path.starts_with('$') {
+            path.to_string()
+        } else if path.is_empty() {
+            "$".to_string()
+        } else if path.contains('/') {
+            let mut json_path = String::from("$");
+            for key in path.split('/').filter(|key| !key.is_empty()) {
+                match key.parse::<usize>() {
+                    Ok(index) => json_path.push_str(&format!("[{}]", index)),
+                    Err(_) => json_path.push_str(&format!("[{}]", Value::from(key))),
+                }
+            }
+            json_path
+        } else if path.starts_with('[') {
+            format!("${}", path)
+        } else {
+            format!("$.{}", path)
+        }
+    }
+
+    pub fn compile(&self) -> Result<JsonPath, anyhow::Error> {
+        let json_path = self.json_path();
+        JsonPath::parse(&json_path)
+            .map_err(|err| anyhow::Error::msg(format!("invalid path {:?}: {}", self.path(), err)))
+    }
+
+    // Wildcards, descendants, filters, slices and unions can select many values. Quoted names
+    // are skipped, so a key like `a:b` of the legacy path `result/a:b` selects one value.
+    fn is_multiple(&self) -> bool {
+        let json_path = self.json_path();
+        let mut quote = None;
+        let mut chars = json_path.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (quote, c) {
+                (Some(_), '\\') => {
+                    chars.next();
+                }
+                (Some(open), c) if c == open => quote = None,
+                (Some(_), _) => {}
+                (None, '\'') | (None, '"') => quote = Some(c),
+                (None, '*') | (None, '?') | (None, ':') | (None, ',') => return true,
+                (None, '.') if chars.peek() == Some(&'.') => return true,
+                (None, _) => {}
+            }
+        }
+        false
+    }
+
+    /// Extract field value from response.
+    pub fn extract(&self, resp: &Value) -> Result<Value, anyhow::Error> {
+        let nodes = self.compile()?.query(resp).all();
+        let value = match (nodes.len(), self.is_multiple()) {
+            (0, _) => {
+                return self
+                    .default_value()
+                    .cloned()
+                    .ok_or(anyhow::Error::msg(format!(
+                        "cannot find path {} in result: {:?} ",
+                        self.path(),
+                        resp
+                    )))
+            }
+            (_, true) => Value::Array(nodes.into_iter().cloned().collect()),
+            (_, false) => nodes[0].clone(),
+        };
+        if self.is_length() {
+            let length = match &value {
+                Value::Array(values) => values.len(),
+                Value::Object(values) => values.len(),
+                Value::String(value) => value.chars().count(),
+                _ => {
+                    return Err(anyhow::Error::msg(format!(
+                        "value of path {} has no length: {}",
+                        self.path(),
+                        value
+                    )))
+                }
+            };
+            return Ok(Value::from(length));
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn path(path: &str) -> ReturnField {
+        ReturnField::Path(path.to_string())
+    }
+
+    fn response() -> Value {
+        json!({
+            "result": {
+                "number": "0x10",
+                "a:b": 1,
+                "c,d": 2,
+                "e.f": 3,
+                "transactions": [{"hash": "0x1"}, {"hash": "0x2"}]
+            }
+        })
+    }
+
+    #[test]
+    fn legacy_paths() {
+        assert_eq!(
+            path("result/number").json_path(),
+            r#"$["result"]["number"]"#
+        );
+        assert_eq!(
+            path("/result/transactions/1/hash").json_path(),
+            r#"$["result"]["transactions"][1]["hash"]"#
+        );
+        for (legacy, value) in &[
+            ("result/number", json!("0x10")),
+            ("result/a:b", json!(1)),
+            ("result/c,d", json!(2)),
+            ("result/e.f", json!(3)),
+            ("result/transactions/1/hash", json!("0x2")),
+        ] {
+            let field = path(legacy);
+            assert!(!field.is_multiple(), "{}", legacy);
+            assert_eq!(&field.extract(&response()).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn dotted_paths() {
+        assert_eq!(path("").json_path(), "$");
+        assert_eq!(path("result.number").json_path(), "$.result.number");
+        assert_eq!(path("[0]").json_path(), "$[0]");
+        let field = path("result.transactions[0].hash");
+        assert!(!field.is_multiple());
+        assert_eq!(field.extract(&response()).unwrap(), json!("0x1"));
+        let field = path("result.transactions[*].hash");
+        assert!(field.is_multiple());
+        assert_eq!(field.extract(&response()).unwrap(), json!(["0x1", "0x2"]));
+    }
+
+    #[test]
+    fn json_paths() {
+        for single in &[
+            "$.result.number",
+            "$['result']['a:b']",
+            r#"$["result"]["c,d"]"#,
+            r#"$["result"]["e.f"]"#,
+            r#"$['result']["it's"]"#,
+            r#"$["result"]["\"*"]"#,
+        ] {
+            assert!(!path(single).is_multiple(), "{}", single);
+        }
+        for multiple in &[
+            "$.result.transactions[*]",
+            "$..hash",
+            "$.result.transactions[?@.hash]",
+            "$.result.transactions[0:1]",
+            "$.result['number','a:b']",
+        ] {
+            assert!(path(multiple).is_multiple(), "{}", multiple);
+        }
+        // A multi-select keeps array type even for one match
+        let field = path("$.result.transactions[0:1].hash");
+        assert_eq!(field.extract(&response()).unwrap(), json!(["0x1"]));
+    }
+
+    #[test]
+    fn default_and_length() {
+        let detail = |value: Value| serde_json::from_value::<ReturnField>(value).unwrap();
+        let field = detail(json!({"path": "result.missing", "default": null}));
+        assert_eq!(field.extract(&response()).unwrap(), Value::Null);
+        assert!(path("result.missing").extract(&response()).is_err());
+        let field = detail(json!({"path": "result.transactions", "length": true}));
+        assert_eq!(field.extract(&response()).unwrap(), json!(2));
+        let field = detail(json!({"path": "result.number", "length": true}));
+        assert_eq!(field.extract(&response()).unwrap(), json!(4));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::check_module::check_module::{CheckAction, CheckStep};
+use crate::check_module::flow_validator::template_references;
+
+impl CheckAction {
+    /// Step results used by templates or compare items of the action.
+    pub fn references(&self) -> Vec<String> {
+        let templates = match self {
+            CheckAction::Call(action) => action.templates(),
+            CheckAction::WsCall(action) | CheckAction::WsSubscribe(action) => action.templates(),
+            CheckAction::Set(action) => action.templates(),
+            CheckAction::Compare(action) => {
+                return action
+                    .operator_items
+                    .items()
+                    .into_iter()
+                    .filter(|item| !item.starts_with('#'))
+                    .cloned()
+                    .collect()
+            }
+        };
+        templates
+            .into_iter()
+            .flat_map(|template| template_references(template))
+            .collect()
+    }
+}
+
+// Latest previous step with the return name
+fn find_step(steps: &[CheckStep], before: usize, return_name: &str) -> Option<usize> {
+    (0..before)
+        .rev()
+        .find(|index| steps[*index].return_name == return_name)
+}
+
+// Previous step producing the step result key `{return_name}_{field}`, the longest return name wins
+fn find_producer(steps: &[CheckStep], before: usize, key: &str) -> Option<usize> {
+    (0..before)
+        .rev()
+        .filter(|index| key.starts_with(&format!("{}_", steps[*index].return_name)))
+        .max_by_key(|index| steps[*index].return_name.len())
+}
+
+/// Indexes of the steps each step waits for.
+///
+/// A step without `depends_on` waits for all previous steps, so existing flows keep running in order.
+/// Steps producing results used by templates or compare items of a step are always waited for.
+pub fn step_dependencies(steps: &[CheckStep]) -> Vec<Vec<usize>> {
+    steps
+        .iter()
+        .enumerate()
+        .map(|(index, step)| {
+            let mut dependencies = match &step.depends_on {
+                None => (0..index).collect::<Vec<usize>>(),
+                Some(names) => names
+                    .iter()
+                    .filter_map(|name| find_step(steps, index, name))
+                    .collect(),
+            };
+            for key in step.action.references() {
+                if let Some(producer) = find_producer(steps, index, &key) {
+                    dependencies.push(producer);
+                }
+            }
+            dependencies.sort_unstable();
+            dependencies.dedup();
+            dependencies
+        })
+        .collect()
+}
+
+/// Group steps into stages, steps of a stage only depend on steps of earlier stages
+/// and can run concurrently.
+pub fn step_stages(steps: &[CheckStep]) -> Vec<Vec<usize>> {
+    let mut stage_of_step: Vec<usize> = Vec::with_capacity(steps.len());
+    let mut stages: Vec<Vec<usize>> = Vec::new();
+    for (index, dependencies) in step_dependencies(steps).into_iter().enumerate() {
+        // Dependencies are always previous steps
+        let stage = dependencies
+            .iter()
+            .map(|dependency| stage_of_step[*dependency] + 1)
+            .max()
+            .unwrap_or_default();
+        stage_of_step.push(stage);
+        if stages.len() <= stage {
+            stages.resize(stage + 1, Vec::new());
+        }
+        stages[stage].push(index);
+    }
+    stages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Compare step of `item` with a constant
+    fn step(return_name: &str, depends_on: Option<&[&str]>, item: &str) -> CheckStep {
+        let mut step = json!({
+            "action": {
+                "action_type": "compare",
+                "operator_items": {"operator_type": "eq", "params": [item, "#1"]}
+            },
+            "return_name": return_name
+        });
+        if let Some(depends_on) = depends_on {
+            step["depends_on"] = json!(depends_on);
+        }
+        serde_json::from_value(step).unwrap()
+    }
+
+    #[test]
+    fn steps_without_depends_on_run_in_order() {
+        let steps = vec![
+            step("a", None, "#1"),
+            step("b", None, "#1"),
+            step("c", None, "#1"),
+        ];
+        assert_eq!(step_dependencies(&steps), vec![vec![], vec![0], vec![0, 1]]);
+        assert_eq!(step_stages(&steps), vec![vec![0], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn depends_on_builds_concurrent_stages() {
+        let steps = vec![
+            step("a", Some(&[]), "#1"),
+            step("b", Some(&[]), "#1"),
+            step("c", Some(&["a", "b"]), "#1"),
+            step("d", Some(&["a"]), "#1"),
+            step("e", None, "#1"),
+        ];
+        assert_eq!(
+            step_dependencies(&steps),
+            vec![vec![], vec![], vec![0, 1], vec![0], vec![0, 1, 2, 3]]
+        );
+        assert_eq!(step_stages(&steps), vec![vec![0, 1], vec![2, 3], vec![4]]);
+    }
+
+    #[test]
+    fn referenced_results_are_waited_for() {
+        let steps = vec![
+            step("a", Some(&[]), "#1"),
+            step("a_b", Some(&[]), "#1"),
+            // `a_b_value` is a result of `a_b`, the longest matching return name
+            step("c", Some(&[]), "a_b_value"),
+            step("d", Some(&[]), "a_value"),
+        ];
+        assert_eq!(
+            step_dependencies(&steps),
+            vec![vec![], vec![], vec![1], vec![0]]
+        );
+        assert_eq!(step_stages(&steps), vec![vec![0, 1], vec![2, 3]]);
+    }
+
+    #[test]
+    fn unknown_and_later_steps_are_not_waited_for() {
+        // A cycle is not possible: dependencies are only previous steps
+        let steps = vec![
+            step("a", Some(&["b"]), "#1"),
+            step("b", Some(&["a", "unknown"]), "#1"),
+            step("c", Some(&["c"]), "#1"),
+        ];
+        assert_eq!(step_dependencies(&steps), vec![vec![], vec![0], vec![]]);
+        assert_eq!(step_stages(&steps), vec![vec![0, 2], vec![1]]);
+    }
+
+    #[test]
+    fn repeated_return_name_waits_for_latest_step() {
+        let steps = vec![
+            step("a", Some(&[]), "#1"),
+            step("a", Some(&[]), "#1"),
+            step("b", Some(&["a"]), "#1"),
+        ];
+        assert_eq!(step_dependencies(&steps)[2], vec![1]);
+    }
+}
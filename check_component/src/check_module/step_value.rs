@@ -0,0 +1,295 @@
+use serde::de::Deserializer;
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+// Hex quantities longer than this (hashes, addresses, ...) are kept as string
+const MAX_HEX_QUANTITY_DIGITS: usize = 32;
+
+/// Typed value of a field returned by a check step.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StepValue {
+    Null,
+    Bool(bool),
+    Number(Number),
+    // Hex quantity like `0xde83cb`, the original text is kept for rendering templates
+    Hex(u128, String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Map<String, Value>),
+}
+
+impl Default for StepValue {
+    fn default() -> Self {
+        StepValue::Null
+    }
+}
+
+impl StepValue {
+    pub fn from_json(value: Value) -> Self {
+        match value {
+            Value::Null => StepValue::Null,
+            Value::Bool(value) => StepValue::Bool(value),
+            Value::Number(value) => StepValue::Number(value),
+            Value::String(value) => match Self::parse_hex_quantity(&value) {
+                Some(number) => StepValue::Hex(number, value),
+                None => StepValue::String(value),
+            },
+            Value::Array(value) => StepValue::Array(value),
+            Value::Object(value) => StepValue::Object(value),
+        }
+    }
+
+    /// Parse a literal written in check-flow (the part after `#`).
+    pub fn from_literal(literal: &str) -> Self {
+        match serde_json::from_str::<Value>(literal) {
+            Ok(value) if !value.is_array() && !value.is_object() => Self::from_json(value),
+            _ => Self::from_json(Value::String(literal.to_string())),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            StepValue::Null => Value::Null,
+            StepValue::Bool(value) => Value::Bool(*value),
+            StepValue::Number(value) => Value::Number(value.clone()),
+            StepValue::Hex(_, raw) => Value::String(raw.clone()),
+            StepValue::String(value) => Value::String(value.clone()),
+            StepValue::Array(value) => Value::Array(value.clone()),
+            StepValue::Object(value) => Value::Object(value.clone()),
+        }
+    }
+
+    // Ethereum quantity encoding: `0x` followed by hex digits without leading zeros
+    fn parse_hex_quantity(value: &str) -> Option<u128> {
+        let digits = value.strip_prefix("0x")?;
+        if digits.is_empty()
+            || digits.len() > MAX_HEX_QUANTITY_DIGITS
+            || (digits.len() > 1 && digits.starts_with('0'))
+            || !digits.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return None;
+        }
+        u128::from_str_radix(digits, 16).ok()
+    }
+
+    pub fn as_i128(&self) -> Option<i128> {
+        match self {
+            StepValue::Number(value) => value
+                .as_i64()
+                .map(|value| value as i128)
+                .or_else(|| value.as_u64().map(|value| value as i128)),
+            StepValue::Hex(value, _) => i128::try_from(*value).ok(),
+            StepValue::String(value) => value.trim().parse::<i128>().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            StepValue::Number(value) => value.as_f64(),
+            StepValue::Hex(value, _) => Some(*value as f64),
+            StepValue::String(value) => value.trim().parse::<f64>().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        self.as_f64().is_some()
+    }
+
+    /// Compare as numbers, integers are compared without losing precision.
+    pub fn compare_number(&self, other: &StepValue) -> Option<Ordering> {
+        match (self.as_i128(), other.as_i128()) {
+            (Some(first), Some(second)) => Some(first.cmp(&second)),
+            _ => self.as_f64()?.partial_cmp(&other.as_f64()?),
+        }
+    }
+
+    /// Numbers are equal by value (`0x10` == `16`), other values by their text.
+    pub fn loosely_eq(&self, other: &StepValue) -> bool {
+        match self.compare_number(other) {
+            Some(ordering) => ordering == Ordering::Equal,
+            None => self.to_string() == other.to_string(),
+        }
+    }
+
+    /// Array contains item, object contains key or string contains sub string.
+    pub fn contains(&self, item: &StepValue) -> bool {
+        match self {
+            StepValue::Array(values) => values
+                .iter()
+                .any(|value| StepValue::from_json(value.clone()).loosely_eq(item)),
+            StepValue::Object(values) => values.contains_key(&item.to_string()),
+            _ => self.to_string().contains(&item.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for StepValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StepValue::Null => write!(f, "null"),
+            StepValue::Bool(value) => write!(f, "{}", value),
+            StepValue::Number(value) => write!(f, "{}", value),
+            StepValue::Hex(_, raw) => write!(f, "{}", raw),
+            StepValue::String(value) => write!(f, "{}", value),
+            StepValue::Array(_) | StepValue::Object(_) => write!(f, "{}", self.to_json()),
+        }
+    }
+}
+
+impl From<bool> for StepValue {
+    fn from(value: bool) -> Self {
+        StepValue::Bool(value)
+    }
+}
+
+impl From<u64> for StepValue {
+    fn from(value: u64) -> Self {
+        StepValue::Number(Number::from(value))
+    }
+}
+
+impl From<Value> for StepValue {
+    fn from(value: Value) -> Self {
+        StepValue::from_json(value)
+    }
+}
+
+impl Serialize for StepValue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.to_json().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for StepValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Value::deserialize(deserializer).map(StepValue::from_json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn value(json: Value) -> StepValue {
+        StepValue::from_json(json)
+    }
+
+    #[test]
+    fn hex_quantities_are_parsed() {
+        assert_eq!(
+            value(json!("0xde83cb")),
+            StepValue::Hex(0xde83cb, "0xde83cb".to_string())
+        );
+        assert_eq!(value(json!("0x0")), StepValue::Hex(0, "0x0".to_string()));
+        assert_eq!(value(json!("0xABC")).as_i128(), Some(0xabc));
+        // Rendered with the original text
+        assert_eq!(value(json!("0x10")).to_string(), "0x10");
+        assert_eq!(value(json!("0x10")).to_json(), json!("0x10"));
+    }
+
+    #[test]
+    fn leading_zeros_and_non_quantities_are_strings() {
+        for text in ["0x", "0x01", "0x00", "0xzz", "10", "x10", "0X10"] {
+            assert_eq!(
+                value(json!(text)),
+                StepValue::String(text.to_string()),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn hex_quantity_digit_limit() {
+        let max = format!("0x{}", "f".repeat(MAX_HEX_QUANTITY_DIGITS));
+        assert_eq!(value(json!(max)), StepValue::Hex(u128::MAX, max.clone()));
+        // Hashes and addresses are longer
+        let hash = format!("0x{}", "1".repeat(MAX_HEX_QUANTITY_DIGITS + 1));
+        assert_eq!(value(json!(hash)), StepValue::String(hash.clone()));
+        // Beyond i128 there is only the float value
+        assert_eq!(value(json!(max)).as_i128(), None);
+        assert_eq!(value(json!(max)).as_f64(), Some(u128::MAX as f64));
+    }
+
+    #[test]
+    fn numbers_are_loosely_equal_by_value() {
+        assert!(value(json!("0x10")).loosely_eq(&StepValue::from_literal("16")));
+        assert!(value(json!("0x10")).loosely_eq(&value(json!(16))));
+        assert!(value(json!(16)).loosely_eq(&value(json!("16"))));
+        assert!(value(json!(1.5)).loosely_eq(&value(json!("1.50"))));
+        assert!(!value(json!("0x10")).loosely_eq(&value(json!(10))));
+        // Strings of hex digits with leading zeros are not quantities, they are compared as text
+        assert!(!value(json!("0x010")).loosely_eq(&value(json!("0x10"))));
+        assert!(value(json!("0x010")).loosely_eq(&value(json!("0x010"))));
+        // Other values by their text
+        assert!(value(json!(true)).loosely_eq(&StepValue::from_literal("true")));
+        assert!(value(json!("abc")).loosely_eq(&value(json!("abc"))));
+        assert!(!value(json!("abc")).loosely_eq(&value(json!("ABC"))));
+        assert!(value(json!(null)).loosely_eq(&value(json!(null))));
+    }
+
+    #[test]
+    fn compare_number() {
+        assert_eq!(
+            value(json!("0x11")).compare_number(&value(json!(16))),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            value(json!(-1)).compare_number(&value(json!(u64::MAX))),
+            Some(Ordering::Less)
+        );
+        // Large integers do not lose precision
+        assert_eq!(
+            value(json!(9007199254740993u64)).compare_number(&value(json!(9007199254740992u64))),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            value(json!(1.5)).compare_number(&value(json!("0x1"))),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(value(json!("abc")).compare_number(&value(json!(1))), None);
+        assert_eq!(value(json!(null)).compare_number(&value(json!(1))), None);
+        assert_eq!(value(json!([1])).compare_number(&value(json!(1))), None);
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(StepValue::from_literal("3"), value(json!(3)));
+        assert_eq!(StepValue::from_literal("true"), StepValue::Bool(true));
+        assert_eq!(StepValue::from_literal("null"), StepValue::Null);
+        assert_eq!(
+            StepValue::from_literal("0x10"),
+            StepValue::Hex(16, "0x10".to_string())
+        );
+        assert_eq!(
+            StepValue::from_literal("mainnet"),
+            StepValue::String("mainnet".to_string())
+        );
+        // Arrays and objects are not parsed
+        assert_eq!(
+            StepValue::from_literal("[1]"),
+            StepValue::String("[1]".to_string())
+        );
+    }
+
+    #[test]
+    fn contains() {
+        assert!(value(json!(["0x10", "a"])).contains(&value(json!(16))));
+        assert!(!value(json!(["0x10", "a"])).contains(&value(json!("b"))));
+        assert!(value(json!({"peers": 1})).contains(&value(json!("peers"))));
+        assert!(value(json!("mainnet")).contains(&value(json!("net"))));
+    }
+}
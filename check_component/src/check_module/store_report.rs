@@ -2,13 +2,18 @@ use crate::check_module::check_module::{CheckMkReport, ComponentInfo, ComponentT
 use crate::CONFIG;
 use anyhow::Error;
 use log::{debug, info};
+use rand::Rng;
 use reqwest::Response;
 use serde::{Deserialize, Serialize};
-use std::time::{SystemTime, UNIX_EPOCH};
+use serde_json::Value;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use wrap_wrk::{WrkBenchmark, WrkReport};
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct StoreReport {
+    // Random id, the outbox keeps one request per report
+    #[serde(skip)]
+    pub report_id: String,
     pub reporter: String,
     pub reporter_role: ReporterRole,
     #[serde(skip_deserializing)]
@@ -56,6 +61,7 @@ impl Default for ReportType {
     }
 }
 
+#[derive(Clone, Copy, Debug, Serialize)]
 pub enum SendPurpose {
     Verify,
     Store,
@@ -75,6 +81,7 @@ impl StoreReport {
         domain: &String,
     ) -> StoreReport {
         StoreReport {
+            report_id: hex::encode(rand::thread_rng().gen::<[u8; 16]>()),
             reporter: reporter.clone(),
             reporter_role,
             authorization: authorization.clone(),
@@ -130,6 +137,42 @@ impl StoreReport {
         Ok(serde_json::to_string(&self)?)
     }
 
+    /// Url and body of the portal request, without the portal authorization
+    /// so that it can be kept on disk until it is sent.
+    pub fn portal_request(&self, send_purpose: SendPurpose) -> Result<(String, Value), Error> {
+        let mut body = serde_json::to_value(&self)?;
+        if let Some(body) = body.as_object_mut() {
+            body.remove("authorization");
+        }
+        Ok((self.get_url(send_purpose), body))
+    }
+
+    /// Send a request of `portal_request` with the portal authorization.
+    pub async fn send_portal_request(
+        client: &reqwest::Client,
+        url: &str,
+        body: &Value,
+        authorization: &str,
+        timeout: Duration,
+    ) -> Result<Response, Error> {
+        let mut body = body.clone();
+        if let Some(body) = body.as_object_mut() {
+            body.insert(
+                "authorization".to_string(),
+                Value::String(authorization.to_string()),
+            );
+        }
+        let response = client
+            .post(url)
+            .timeout(timeout)
+            .header("content-type", "application/json")
+            .header("Authorization", authorization)
+            .body(serde_json::to_string(&body)?)
+            .send()
+            .await?;
+        Ok(response)
+    }
+
     fn get_url(&self, send_purpose: SendPurpose) -> String {
         match send_purpose {
             SendPurpose::Verify => {
@@ -147,9 +190,11 @@ impl StoreReport {
         }
     }
 
-    pub async fn send_data(&self, send_purpose: SendPurpose) -> Result<Response, Error> {
-        let client_builder = reqwest::ClientBuilder::new();
-        let client = client_builder.danger_accept_invalid_certs(true).build()?;
+    pub async fn send_data(
+        &self,
+        client: &reqwest::Client,
+        send_purpose: SendPurpose,
+    ) -> Result<Response, Error> {
         // create body
         let body = self.create_body()?;
         info!("body: {:?}", body);
@@ -0,0 +1,265 @@
+use handlebars::{Context, Handlebars, Helper, HelperDef, RenderContext, RenderError, ScopedJson};
+use rand::Rng;
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HelperFn = fn(&[Value]) -> Result<Value, String>;
+
+const HELPERS: [(&str, HelperFn); 11] = [
+    ("hex", hex),
+    ("dec", dec),
+    ("add", |params| arithmetic(params, i128::checked_add)),
+    ("sub", |params| arithmetic(params, i128::checked_sub)),
+    ("mul", |params| arithmetic(params, i128::checked_mul)),
+    ("div", |params| arithmetic(params, i128::checked_div)),
+    ("random_block", random_block),
+    ("random_int", random_int),
+    ("timestamp", timestamp),
+    ("uuid", |_| Ok(Value::from(uuid()))),
+    ("random_id", |_| {
+        Ok(Value::from(rand::thread_rng().gen::<u32>()))
+    }),
+];
+
+lazy_static::lazy_static! {
+    /// Renders templates of check steps, e.g. `{{sub baseCall_blockNumber 10}}`.
+    pub static ref HANDLEBARS: Handlebars<'static> = {
+        let mut handlebars = Handlebars::new();
+        for (name, helper) in HELPERS {
+            handlebars.register_helper(name, Box::new(ValueHelper(helper)));
+        }
+        handlebars
+    };
+}
+
+/// Whether the name is a helper of check step templates rather than a step result.
+pub fn is_helper(name: &str) -> bool {
+    HELPERS.iter().any(|(helper, _)| *helper == name)
+}
+
+// Helper computing a value from its params, so that helpers can be nested: `{{hex (sub (dec a) 1)}}`
+struct ValueHelper(HelperFn);
+
+impl HelperDef for ValueHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let params = h
+            .params()
+            .iter()
+            .map(|param| param.value().clone())
+            .collect::<Vec<Value>>();
+        (self.0)(&params)
+            .map(ScopedJson::Derived)
+            .map_err(|err| RenderError::new(format!("helper {}: {}", h.name(), err)))
+    }
+}
+
+// Number of a param and whether it is hex, e.g. block number `0x10d4f`
+fn number(params: &[Value], index: usize) -> Result<(i128, bool), String> {
+    match params.get(index) {
+        Some(Value::Number(number)) => number
+            .as_i64()
+            .map(|number| (number as i128, false))
+            .or_else(|| number.as_u64().map(|number| (number as i128, false)))
+            .ok_or(format!("{} is not an integer", number)),
+        Some(Value::String(text)) => match text.strip_prefix("0x") {
+            Some(hex) => i128::from_str_radix(hex, 16).map(|number| (number, true)),
+            None => text.parse::<i128>().map(|number| (number, false)),
+        }
+        .map_err(|err| format!("{:?} is not a number: {}", text, err)),
+        Some(value) => Err(format!("{} is not a number", value)),
+        None => Err(format!("missing param {}", index + 1)),
+    }
+}
+
+// Keep the format of the input, so that hex block numbers can be sent back to the node
+fn format_number(number: i128, is_hex: bool) -> Result<Value, String> {
+    match is_hex {
+        true if number < 0 => Err(format!("negative number {} cannot be hex", number)),
+        true => Ok(Value::from(format!("0x{:x}", number))),
+        false => i64::try_from(number)
+            .map(Value::from)
+            .map_err(|_| format!("{} is out of range", number)),
+    }
+}
+
+fn hex(params: &[Value]) -> Result<Value, String> {
+    let (number, _) = number(params, 0)?;
+    format_number(number, true)
+}
+
+fn dec(params: &[Value]) -> Result<Value, String> {
+    let (number, _) = number(params, 0)?;
+    format_number(number, false)
+}
+
+fn arithmetic(
+    params: &[Value],
+    operation: fn(i128, i128) -> Option<i128>,
+) -> Result<Value, String> {
+    let (first, is_hex) = number(params, 0)?;
+    let (second, _) = number(params, 1)?;
+    let result = operation(first, second).ok_or("overflow or division by zero")?;
+    format_number(result, is_hex)
+}
+
+// Random block of the latest `depth` blocks: `{{random_block baseCall_blockNumber 1000}}`
+fn random_block(params: &[Value]) -> Result<Value, String> {
+    let (latest, is_hex) = number(params, 0)?;
+    let (depth, _) = number(params, 1)?;
+    let oldest = (latest - depth.max(0)).max(0);
+    format_number(
+        rand::thread_rng().gen_range(oldest..=latest.max(oldest)),
+        is_hex,
+    )
+}
+
+fn random_int(params: &[Value]) -> Result<Value, String> {
+    let (min, _) = number(params, 0)?;
+    let (max, _) = number(params, 1)?;
+    if min > max {
+        return Err(format!("min {} is greater than max {}", min, max));
+    }
+    format_number(rand::thread_rng().gen_range(min..=max), false)
+}
+
+// Unix time in seconds, `{{timestamp "ms"}}` in milliseconds
+fn timestamp(params: &[Value]) -> Result<Value, String> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|err| err.to_string())?;
+    match params.first().and_then(|unit| unit.as_str()) {
+        Some("ms") => Ok(Value::from(elapsed.as_millis() as u64)),
+        Some("s") | None => Ok(Value::from(elapsed.as_secs())),
+        Some(unit) => Err(format!("unknown unit {:?}", unit)),
+    }
+}
+
+// Random uuid version 4
+fn uuid() -> String {
+    let mut bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(template: &str) -> Result<String, RenderError> {
+        HANDLEBARS.render_template(
+            template,
+            &json!({"hexBlock": "0x10d4f", "block": 68943, "text": "latest"}),
+        )
+    }
+
+    #[test]
+    fn hex_and_dec_round_trip() {
+        assert_eq!(hex(&[json!(68943)]), Ok(json!("0x10d4f")));
+        assert_eq!(hex(&[json!("68943")]), Ok(json!("0x10d4f")));
+        assert_eq!(dec(&[json!("0x10d4f")]), Ok(json!(68943)));
+        assert_eq!(
+            dec(&[json!(u64::MAX)]).unwrap_err(),
+            "18446744073709551615 is out of range"
+        );
+        assert!(hex(&[json!(-1)]).is_err());
+        assert!(hex(&[json!("0xzz")]).is_err());
+        assert!(hex(&[json!(1.5)]).is_err());
+        assert!(hex(&[json!(true)]).is_err());
+        assert_eq!(hex(&[]).unwrap_err(), "missing param 1");
+        assert_eq!(render("{{hex (dec hexBlock)}}").unwrap(), "0x10d4f");
+        assert_eq!(render("{{dec (hex block)}}").unwrap(), "68943");
+    }
+
+    #[test]
+    fn arithmetic_keeps_format_of_first_param() {
+        assert_eq!(render("{{sub hexBlock 15}}").unwrap(), "0x10d40");
+        assert_eq!(render("{{add block \"0x1\"}}").unwrap(), "68944");
+        assert_eq!(render("{{mul block 2}}").unwrap(), "137886");
+        assert_eq!(render("{{div hexBlock 16}}").unwrap(), "0x10d4");
+        assert_eq!(render("{{hex (sub (dec hexBlock) 1)}}").unwrap(), "0x10d4e");
+    }
+
+    #[test]
+    fn arithmetic_errors() {
+        // A hex number cannot be negative, a decimal one can
+        assert!(arithmetic(&[json!("0x1"), json!(2)], i128::checked_sub).is_err());
+        assert_eq!(
+            arithmetic(&[json!(1), json!(2)], i128::checked_sub),
+            Ok(json!(-1))
+        );
+        assert_eq!(
+            arithmetic(&[json!(1), json!(0)], i128::checked_div).unwrap_err(),
+            "overflow or division by zero"
+        );
+        assert!(arithmetic(&[json!(i128::MAX.to_string()), json!(1)], i128::checked_add).is_err());
+        // Results beyond i64 are only hex
+        assert!(arithmetic(&[json!(i64::MAX), json!(2)], i128::checked_mul).is_err());
+        assert_eq!(
+            arithmetic(&[json!("0x7fffffffffffffff"), json!(2)], i128::checked_mul),
+            Ok(json!("0xfffffffffffffffe"))
+        );
+        assert_eq!(
+            arithmetic(&[json!(1)], i128::checked_add).unwrap_err(),
+            "missing param 2"
+        );
+        assert!(render("{{div block 0}}").is_err());
+        assert!(render("{{sub text 1}}").is_err());
+    }
+
+    #[test]
+    fn random_block_is_within_depth() {
+        for _ in 0..100 {
+            let block = random_block(&[json!(100), json!(10)]).unwrap();
+            assert!((90..=100).contains(&block.as_i64().unwrap()), "{}", block);
+            let block = random_block(&[json!("0x64"), json!(10)]).unwrap();
+            let block = i64::from_str_radix(&block.as_str().unwrap()[2..], 16).unwrap();
+            assert!((90..=100).contains(&block));
+        }
+        // Not before the genesis block, a negative depth is the latest block
+        assert!((0..=5).contains(
+            &random_block(&[json!(5), json!(10)])
+                .unwrap()
+                .as_i64()
+                .unwrap()
+        ));
+        assert_eq!(random_block(&[json!(5), json!(-3)]), Ok(json!(5)));
+        assert_eq!(random_block(&[json!(0), json!(0)]), Ok(json!(0)));
+        assert_eq!(random_block(&[json!(-5), json!(0)]), Ok(json!(0)));
+    }
+
+    #[test]
+    fn random_int_and_uuid() {
+        for _ in 0..100 {
+            let value = random_int(&[json!(-2), json!(2)])
+                .unwrap()
+                .as_i64()
+                .unwrap();
+            assert!((-2..=2).contains(&value));
+        }
+        assert!(random_int(&[json!(2), json!(1)]).is_err());
+        let uuid = uuid();
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+        assert!(is_helper("random_block"));
+        assert!(!is_helper("checkCall_blockNumber"));
+        assert!(timestamp(&[json!("ms")]).unwrap().as_u64().unwrap() > 1_600_000_000_000);
+        assert!(timestamp(&[json!("h")]).is_err());
+    }
+}
@@ -0,0 +1,26 @@
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Lock a mutex, also after a thread panicked while holding it. Trackers, caches and metrics
+/// stay usable, a panic leaves at most one update half done.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Unix time in milliseconds.
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// Readers never see a partially written file. The temporary file is hidden, so readers
+// scanning a directory (e.g. the checkmk agent in its spool dir) skip it.
+pub(crate) fn write_atomic(path: &Path, content: &str) -> std::io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_path = path.with_file_name(format!(".{}.tmp", file_name));
+    std::fs::write(&tmp_path, content)?;
+    std::fs::rename(&tmp_path, path)
+}
@@ -0,0 +1,380 @@
+use crate::check_module::base_endpoint_health::ReferenceUnavailable;
+use crate::check_module::base_node_cache::BaseNodeCacheKey;
+use crate::check_module::check_module::{
+    ActionResponse, CheckComponent, CheckMkStatus, ComponentInfo, EndpointInfo, RenderedRequest,
+    StepResult,
+};
+use crate::check_module::return_field::ReturnField;
+use crate::check_module::step_value::StepValue;
+use crate::CONFIG;
+use anyhow::Error;
+use futures_util::{SinkExt, StreamExt};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::time::timeout_at;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{
+    connect_async_tls_with_config, Connector, MaybeTlsStream, WebSocketStream,
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Keys added to step result of websocket actions
+pub const CONNECT_TIME_KEY: &str = "connect_time_ms";
+pub const SUBSCRIPTION_ID_KEY: &str = "subscription_id";
+pub const NOTIFICATION_COUNT_KEY: &str = "notification_count";
+pub const NOTIFICATIONS_KEY: &str = "notifications";
+pub const FIRST_NOTIFICATION_TIME_KEY: &str = "first_notification_ms";
+
+/// Websocket action. `ws_call` sends `body` and waits for the response with the same id,
+/// `ws_subscribe` sends a subscription in `body` and captures notifications.
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ActionWebSocket {
+    pub(crate) is_base_node: bool,
+    // Path appended to websocket url of component or base endpoint, e.g. `/ws`
+    #[serde(default)]
+    pub(crate) path: String,
+    #[serde(default)]
+    pub(crate) header: HashMap<String, String>,
+    pub(crate) body: String,
+    // Seconds for the whole action, including connection and notifications
+    time_out: usize,
+    // Subscription only: close after this number of notifications
+    #[serde(default = "default_notification_number")]
+    pub(crate) notification_number: usize,
+    // Extracted from response of `ws_call` or `result` of the last notification of `ws_subscribe`
+    #[serde(default)]
+    pub(crate) return_fields: HashMap<String, ReturnField>,
+}
+
+fn default_notification_number() -> usize {
+    1
+}
+
+impl ActionWebSocket {
+    /// All templates of the request, they may reference results of previous steps.
+    pub fn templates(&self) -> Vec<&String> {
+        let mut templates = vec![&self.path, &self.body];
+        templates.extend(self.header.values());
+        templates
+    }
+
+    /// Keys added to step result, without the return name prefix.
+    pub fn result_keys(&self, is_subscribe: bool) -> Vec<String> {
+        let mut keys = self.return_fields.keys().cloned().collect::<Vec<_>>();
+        keys.push(CONFIG.response_time_key.clone());
+        keys.push(CONNECT_TIME_KEY.to_string());
+        if is_subscribe {
+            keys.push(SUBSCRIPTION_ID_KEY.to_string());
+            keys.push(NOTIFICATION_COUNT_KEY.to_string());
+            keys.push(NOTIFICATIONS_KEY.to_string());
+            keys.push(FIRST_NOTIFICATION_TIME_KEY.to_string());
+        }
+        keys
+    }
+
+    /// Path, header and body rendered with results of previous steps.
+    pub fn render(&self, step_result: &StepResult) -> Result<RenderedRequest, anyhow::Error> {
+        let mut header = Vec::new();
+        for (key, value) in self.header.iter() {
+            header.push((
+                key.clone(),
+                CheckComponent::replace_string(value.clone(), step_result)?,
+            ));
+        }
+        header.sort();
+        Ok(RenderedRequest {
+            path: CheckComponent::replace_string(self.path.clone(), step_result)?,
+            query: vec![],
+            header,
+            body: CheckComponent::replace_string(self.body.clone(), step_result)?,
+        })
+    }
+}
+
+// Websocket url of a http(s) url
+fn ws_url(url: &str, path: &str) -> String {
+    let url = if let Some(url) = url.strip_prefix("https://") {
+        format!("wss://{}", url)
+    } else if let Some(url) = url.strip_prefix("http://") {
+        format!("ws://{}", url)
+    } else {
+        url.to_string()
+    };
+    match path.is_empty() {
+        true => url,
+        false => format!(
+            "{}/{}",
+            url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        ),
+    }
+}
+
+// A JSON-RPC response to the request id, notifications have `method`
+fn is_response(message: &Value, request_id: Option<&Value>) -> bool {
+    message.get("method").is_none()
+        && match request_id {
+            Some(request_id) => message.get("id") == Some(request_id),
+            None => true,
+        }
+}
+
+impl CheckComponent {
+    pub async fn ws_action(
+        &self,
+        action: &ActionWebSocket,
+        is_subscribe: bool,
+        component: &ComponentInfo,
+        step_result: &StepResult,
+        return_name: &String,
+    ) -> Result<ActionResponse, anyhow::Error> {
+        debug!("websocket action: {:?}", action);
+        // Rendered once, so that all base endpoints get the same request
+        let request = action.render(step_result)?;
+        if action.is_base_node {
+            let base_endpoints = self.order_base_endpoints(self.get_base_endpoints(component)?);
+            // Components of the same chain compare against the same cached answer
+            let cache_key =
+                BaseNodeCacheKey::new_ws(component, action, is_subscribe, return_name, &request);
+            self.base_node_cache
+                .get_or_call(cache_key, || {
+                    self.ws_base_endpoints(
+                        action,
+                        is_subscribe,
+                        &base_endpoints,
+                        return_name,
+                        &request,
+                    )
+                })
+                .await
+                .map_err(|err| {
+                    // The component cannot be judged without the reference answer
+                    err.context(ReferenceUnavailable {
+                        blockchain: component.blockchain.clone(),
+                        network: component.network.clone(),
+                    })
+                })
+        } else {
+            Self::run_ws_action(
+                action,
+                is_subscribe,
+                component.get_url(&self.domain),
+                component.get_request_headers(&self.domain),
+                &request,
+                return_name,
+            )
+            .await
+        }
+    }
+
+    async fn ws_base_endpoints(
+        &self,
+        action: &ActionWebSocket,
+        is_subscribe: bool,
+        base_endpoints: &[&EndpointInfo],
+        return_name: &String,
+        request: &RenderedRequest,
+    ) -> Result<ActionResponse, anyhow::Error> {
+        let mut report = Err(anyhow::Error::msg("Cannot found working base node"));
+        for endpoint in base_endpoints {
+            debug!("try endpoint:{:?}", endpoint);
+            let mut headers = Vec::new();
+            if !endpoint.x_api_key.is_empty() {
+                headers.push(("x-api-key".to_string(), endpoint.x_api_key.clone()));
+            }
+            let now = Instant::now();
+            let res = Self::run_ws_action(
+                action,
+                is_subscribe,
+                endpoint.get_ws_url(),
+                headers,
+                request,
+                return_name,
+            )
+            .await;
+            self.record_base_endpoint_call(endpoint, now, &res);
+            // Keep the last error, it decides whether the step is retried
+            report = res;
+            if report.is_ok() {
+                debug!("endpoint {:?} success return: {:?}", endpoint, report);
+                break;
+            }
+        }
+        report
+    }
+
+    async fn run_ws_action(
+        action: &ActionWebSocket,
+        is_subscribe: bool,
+        url: String,
+        mut headers: Vec<(String, String)>,
+        request: &RenderedRequest,
+        return_name: &String,
+    ) -> Result<ActionResponse, anyhow::Error> {
+        headers.extend(request.header.iter().cloned());
+        let body = request.body.clone();
+        let request_id = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|request| request.get("id").cloned());
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(action.time_out as u64);
+        let now = Instant::now();
+        let mut stream = timeout_at(
+            deadline,
+            Self::connect_ws(&ws_url(&url, &request.path), headers),
+        )
+        .await
+        .map_err(|err| Error::new(err).context("websocket connection timed out"))??;
+        let connect_time_ms = now.elapsed().as_millis();
+
+        let now = Instant::now();
+        stream.send(Message::Text(body)).await?;
+        let response = loop {
+            let message = timeout_at(deadline, Self::next_json(&mut stream))
+                .await
+                .map_err(|err| Error::new(err).context("websocket response timed out"))??;
+            if is_response(&message, request_id.as_ref()) {
+                break message;
+            }
+        };
+        let response_time_ms = now.elapsed().as_millis();
+        debug!("websocket response: {:?}", response);
+
+        let mut result: StepResult = HashMap::new();
+        result.insert(
+            CONFIG.response_time_key.to_string(),
+            StepValue::from(response_time_ms as u64),
+        );
+        result.insert(
+            CONNECT_TIME_KEY.to_string(),
+            StepValue::from(connect_time_ms as u64),
+        );
+        let fields_source = match is_subscribe {
+            false => response,
+            true => {
+                if let Some(error) = response.get("error") {
+                    return Err(Error::msg(format!("subscription failed: {}", error)));
+                }
+                let subscription_id = response.get("result").cloned().unwrap_or_default();
+                let (notifications, first_notification_ms) = Self::capture_notifications(
+                    &mut stream,
+                    &subscription_id,
+                    action.notification_number,
+                    deadline,
+                    now,
+                )
+                .await?;
+                let last_notification = notifications
+                    .last()
+                    .cloned()
+                    .ok_or(Error::msg("no notification received before timeout"))?;
+                result.insert(
+                    SUBSCRIPTION_ID_KEY.to_string(),
+                    StepValue::from_json(subscription_id),
+                );
+                result.insert(
+                    NOTIFICATION_COUNT_KEY.to_string(),
+                    StepValue::from(notifications.len() as u64),
+                );
+                result.insert(
+                    FIRST_NOTIFICATION_TIME_KEY.to_string(),
+                    StepValue::from(first_notification_ms as u64),
+                );
+                result.insert(
+                    NOTIFICATIONS_KEY.to_string(),
+                    StepValue::Array(notifications),
+                );
+                last_notification
+            }
+        };
+        let _ = stream.close(None).await;
+
+        for (name, field) in action.return_fields.iter() {
+            let value = field.extract(&fields_source)?;
+            result.insert(name.clone(), StepValue::from_json(value));
+        }
+        Ok(ActionResponse {
+            success: true,
+            conclude: CheckMkStatus::Ok,
+            return_name: return_name.clone(),
+            result,
+            message: format!("websocket call {}: {}", return_name, true),
+        })
+    }
+
+    async fn connect_ws(url: &str, headers: Vec<(String, String)>) -> Result<WsStream, Error> {
+        let mut request = url.into_client_request()?;
+        for (key, value) in headers {
+            request.headers_mut().insert(
+                HeaderName::from_bytes(key.as_bytes())?,
+                HeaderValue::from_str(&value)?,
+            );
+        }
+        // Components are called by ip, their certificates do not match
+        let connector = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true)
+            .build()?;
+        let (stream, response) =
+            connect_async_tls_with_config(request, None, Some(Connector::NativeTls(connector)))
+                .await?;
+        debug!("websocket connected: {:?}", response.status());
+        Ok(stream)
+    }
+
+    // Next text message parsed as json, control frames are skipped
+    async fn next_json(stream: &mut WsStream) -> Result<Value, Error> {
+        loop {
+            match stream.next().await {
+                Some(Ok(Message::Text(text))) => return Ok(serde_json::from_str(&text)?),
+                Some(Ok(Message::Binary(data))) => return Ok(serde_json::from_slice(&data)?),
+                Some(Ok(Message::Close(frame))) => {
+                    return Err(Error::msg(format!("websocket closed: {:?}", frame)))
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err.into()),
+                None => return Err(Error::msg("websocket closed")),
+            }
+        }
+    }
+
+    // Collect `params.result` of notifications until the number is reached or the deadline.
+    // Returns notifications and the time to the first one.
+    async fn capture_notifications(
+        stream: &mut WsStream,
+        subscription_id: &Value,
+        notification_number: usize,
+        deadline: tokio::time::Instant,
+        subscribed_at: Instant,
+    ) -> Result<(Vec<Value>, u128), Error> {
+        let mut notifications = Vec::new();
+        let mut first_notification_ms = 0;
+        while notifications.len() < notification_number {
+            let message = match timeout_at(deadline, Self::next_json(stream)).await {
+                Ok(message) => message?,
+                Err(_) => break,
+            };
+            let params = match message.get("params") {
+                Some(params) if message.get("method").is_some() => params,
+                _ => continue,
+            };
+            if !subscription_id.is_null() && params.get("subscription") != Some(subscription_id) {
+                continue;
+            }
+            if notifications.is_empty() {
+                first_notification_ms = subscribed_at.elapsed().as_millis();
+            }
+            notifications.push(params.get("result").cloned().unwrap_or(message.clone()));
+        }
+        debug!("websocket notifications: {:?}", notifications);
+        Ok((notifications, first_notification_ms))
+    }
+}
@@ -5,8 +5,11 @@ use lazy_static::lazy_static;
 use local_ip_address::local_ip;
 use std::net::IpAddr;
 
+use crate::check_module::report_sink::ReportSinkConfig;
+use crate::check_module::retry::Backoff;
 use dotenv;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::env;
 
 pub const CONFIG_FILE: &str = "config_check_component.json";
@@ -25,12 +28,191 @@ pub(crate) struct Config {
     pub benchmark_duration: String,
     pub benchmark_rate: i32,
     pub benchmark_script: String,
+    // DApi takes the api key in the path instead of the x-api-key header
+    #[serde(default = "default_benchmark_dapi_script")]
+    pub benchmark_dapi_script: String,
     pub benchmark_wrk_path: String,
     pub success_percent_threshold: u32,
     pub node_response_time_threshold_ms: f32,
     pub gateway_response_time_threshold_ms: f32,
+    #[serde(default = "default_dapi_response_time_threshold_ms")]
+    pub dapi_response_time_threshold_ms: f32,
     pub accepted_low_latency_percent: f32,
     pub skip_benchmark: bool,
+    // Components checked at once by `check_components`, in total and per chain
+    #[serde(default = "default_check_concurrency")]
+    pub check_concurrency: usize,
+    #[serde(default = "default_check_concurrency_per_chain")]
+    pub check_concurrency_per_chain: usize,
+    // Wrk benchmarks running at once
+    #[serde(default = "default_benchmark_concurrency")]
+    pub benchmark_concurrency: usize,
+    // Base node answers are shared by checks of the same chain for this time, 0 disables the cache
+    #[serde(default = "default_base_node_cache_ttl_ms")]
+    pub base_node_cache_ttl_ms: u64,
+    // A base endpoint failing this number of calls in a row is skipped for `base_endpoint_disable_ms`
+    #[serde(default = "default_base_endpoint_max_failures")]
+    pub base_endpoint_max_failures: u64,
+    #[serde(default = "default_base_endpoint_disable_ms")]
+    pub base_endpoint_disable_ms: u64,
+    // Local checks of `check-kind --checkmk` put every component on its own piggyback host
+    #[serde(default = "default_checkmk_piggyback")]
+    pub checkmk_piggyback: bool,
+    // Series of components not checked for this time are dropped from `GET /metrics`, 0 keeps them
+    #[serde(default = "default_metrics_series_ttl_ms")]
+    pub metrics_series_ttl_ms: u64,
+    // Label component metrics with `component_id`, one series per component instead of per chain,
+    // network, zone and type
+    #[serde(default)]
+    pub metrics_component_id_label: bool,
+    // Every report is sent to all sinks: portal, json_lines, stdout or webhook
+    #[serde(default = "default_report_sinks")]
+    pub report_sinks: Vec<ReportSinkConfig>,
+    // Reports for the portal are kept in this dir until they are sent, in a sub dir per process
+    #[serde(default = "default_report_outbox_dir")]
+    pub report_outbox_dir: String,
+    // Delay of the next send of a report after a failed send
+    #[serde(default = "default_report_outbox_backoff")]
+    pub report_outbox_backoff: Backoff,
+    // Portal requests of the outbox taking longer fail and are retried
+    #[serde(default = "default_report_outbox_timeout_ms")]
+    pub report_outbox_timeout_ms: u64,
+    // Reports of the outbox older than this are dropped instead of being sent, 0 keeps them
+    #[serde(default = "default_report_outbox_max_age_ms")]
+    pub report_outbox_max_age_ms: u64,
+    // Oldest reports beyond this number are dropped, for pending and for rejected reports, 0 keeps them
+    #[serde(default = "default_report_outbox_max_reports")]
+    pub report_outbox_max_reports: usize,
+    // Check runs are kept in this SQLite database, shared by the processes on the host, empty disables it
+    #[serde(default = "default_check_history_path")]
+    pub check_history_path: String,
+    // Check runs older than this are pruned, 0 keeps them
+    #[serde(default = "default_check_history_max_age_ms")]
+    pub check_history_max_age_ms: u64,
+    // Oldest check runs of a component beyond this number are pruned, 0 keeps them
+    #[serde(default = "default_check_history_max_runs_per_component")]
+    pub check_history_max_runs_per_component: usize,
+    #[serde(default = "default_check_history_prune_interval_ms")]
+    pub check_history_prune_interval_ms: u64,
+    // Idle connections kept per host by the shared http client
+    #[serde(default = "default_http_pool_max_idle_per_host")]
+    pub http_pool_max_idle_per_host: usize,
+    #[serde(default = "default_http_pool_idle_timeout_ms")]
+    pub http_pool_idle_timeout_ms: u64,
+    // Check-flow and base-endpoint files are reloaded when modified, 0 disables polling
+    #[serde(default = "default_flow_reload_interval_ms")]
+    pub flow_reload_interval_ms: u64,
+    // Last valid check-flow and base-endpoint documents fetched from urls
+    #[serde(default = "default_flow_cache_dir")]
+    pub flow_cache_dir: String,
+    // Pinned sha256 checksums of documents by url, used when the url has no `#sha256=` fragment
+    #[serde(default)]
+    pub flow_checksums: HashMap<String, String>,
+    // Reject documents from urls without pinned sha256 checksum
+    #[serde(default)]
+    pub flow_checksum_required: bool,
+}
+
+fn default_flow_reload_interval_ms() -> u64 {
+    10000
+}
+
+fn default_dapi_response_time_threshold_ms() -> f32 {
+    2000.0
+}
+
+fn default_benchmark_dapi_script() -> String {
+    "./dapi.lua".to_string()
+}
+
+fn default_check_concurrency() -> usize {
+    16
+}
+
+fn default_check_concurrency_per_chain() -> usize {
+    8
+}
+
+fn default_benchmark_concurrency() -> usize {
+    2
+}
+
+fn default_base_node_cache_ttl_ms() -> u64 {
+    2000
+}
+
+fn default_base_endpoint_max_failures() -> u64 {
+    3
+}
+
+fn default_base_endpoint_disable_ms() -> u64 {
+    60000
+}
+
+fn default_checkmk_piggyback() -> bool {
+    true
+}
+
+fn default_metrics_series_ttl_ms() -> u64 {
+    3600000
+}
+
+fn default_report_sinks() -> Vec<ReportSinkConfig> {
+    vec![ReportSinkConfig::Portal]
+}
+
+fn default_report_outbox_dir() -> String {
+    "outbox".to_string()
+}
+
+fn default_report_outbox_backoff() -> Backoff {
+    Backoff::Exponential {
+        delay_ms: 1000,
+        multiplier: 2.0,
+        max_delay_ms: Some(300000),
+    }
+}
+
+fn default_report_outbox_timeout_ms() -> u64 {
+    10000
+}
+
+fn default_report_outbox_max_age_ms() -> u64 {
+    // 7 days
+    604800000
+}
+
+fn default_report_outbox_max_reports() -> usize {
+    10000
+}
+
+fn default_check_history_path() -> String {
+    "check_history.sqlite".to_string()
+}
+
+fn default_check_history_max_age_ms() -> u64 {
+    // 30 days
+    2592000000
+}
+
+fn default_check_history_max_runs_per_component() -> usize {
+    10000
+}
+
+fn default_check_history_prune_interval_ms() -> u64 {
+    3600000
+}
+
+fn default_http_pool_max_idle_per_host() -> usize {
+    8
+}
+
+fn default_http_pool_idle_timeout_ms() -> u64 {
+    90000
+}
+
+fn default_flow_cache_dir() -> String {
+    "flow_cache".to_string()
 }
 
 lazy_static! {
@@ -1,12 +1,22 @@
 use anyhow::Error;
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use futures_util::future::join;
 use logger::core::init_logger;
+use mbr_check_component::check_module::check_history::{CheckHistory, HistoryQuery};
 use mbr_check_component::check_module::check_module::{
-    CheckComponent, CheckMkReport, ComponentInfo,
+    CheckComponent, CheckMkReport, ComponentInfo, GeneratorBuilder,
 };
+use mbr_check_component::check_module::checkmk_output::{CheckMkMode, CheckMkOutput};
+use mbr_check_component::check_module::flow_reloader::FlowReloader;
+use mbr_check_component::check_module::flow_validator::{
+    parse_check_flows, parse_json, validate_base_endpoints,
+};
+use mbr_check_component::check_module::prometheus_metrics::METRICS;
+use mbr_check_component::check_module::report_sink::ReportEvent;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
 
 use log::{debug, info, warn};
 use logger;
@@ -32,7 +42,19 @@ async fn main() {
         .version("0.1")
         .about("mbr-check-component")
         .subcommand(create_check_component())
+        .subcommand(create_validate_flow())
+        .subcommand(create_history())
         .get_matches();
+    if let Some(ref matches) = matches.subcommand_matches("validate-flow") {
+        let check_flow_file = matches.value_of("check-flow").unwrap_or_default();
+        let base_endpoint_file = matches.value_of("base-endpoint");
+        let is_valid = validate_flow_file(check_flow_file, base_endpoint_file);
+        std::process::exit(if is_valid { 0 } else { 1 });
+    }
+    if let Some(ref matches) = matches.subcommand_matches("history") {
+        let is_ok = print_history(matches);
+        std::process::exit(if is_ok { 0 } else { 1 });
+    }
     if let Some(ref matches) = matches.subcommand_matches("check-kind") {
         let list_node_id_file = matches
             .value_of("list-node-id-file")
@@ -58,8 +80,16 @@ async fn main() {
         let output = matches
             .value_of("output")
             .unwrap_or("src/example/output.json");
+        let checkmk_mode = match matches.value_of("checkmk").map(str::parse::<CheckMkMode>) {
+            Some(Ok(mode)) => Some(mode),
+            Some(Err(err)) => {
+                eprintln!("{}", err);
+                std::process::exit(2);
+            }
+            None => None,
+        };
 
-        let check_component = CheckComponent::builder()
+        let mut check_component = CheckComponent::builder()
             .with_list_node_id_file(list_node_id_file.to_string(), None)
             .await
             .with_list_gateway_id_file(list_gateway_id_file.to_string(), None)
@@ -69,22 +99,51 @@ async fn main() {
             .with_list_user_file(list_user_file.to_string())
             .await
             .with_check_flow_file(check_flow_file.to_string())
+            .await
             .with_base_endpoint_file(base_endpoint_file.to_string())
+            .await
             .with_domain(domain.to_string())
             .with_output_file(output.to_string())
+            // Checkmk runs report to checkmk only
+            .with_store_report(checkmk_mode.is_none())
+            .with_write_to_file(checkmk_mode == Some(CheckMkMode::Spool))
             .build();
         log::debug!("check_component: {:?}", check_component);
+        if checkmk_mode == Some(CheckMkMode::Stdout) {
+            match check_component.checkmk_local_checks().await {
+                Ok(local_checks) => print!("{}", local_checks),
+                Err(err) => {
+                    eprintln!("Cannot check components: {}", err);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        // Send reports kept in the outbox by a previous run
+        check_component
+            .report_sinks
+            .start(check_component.http_client.pooled());
+        // Reload check flows and base endpoints when they change
+        let flow_reloader = FlowReloader::new(&mut check_component);
+        tokio::spawn(flow_reloader.clone().watch());
         // Create job queue
         let (sender, mut receiver): (Sender<ComponentInfo>, Receiver<ComponentInfo>) =
             channel(1024);
 
         let socket_addr = CHECK_COMPONENT_ENDPOINT.as_str();
 
+        let checkmk_output = CheckMkOutput::default();
         let server = ServerBuilder::default()
             .with_entry_point(socket_addr)
+            .with_flow_reloader(flow_reloader)
+            .with_checkmk_output(checkmk_output.clone())
             .build(check_component);
+        if checkmk_mode.is_some() {
+            let check_component = (*server.check_component_service).clone();
+            tokio::spawn(check_component.loop_checkmk(checkmk_output));
+        }
 
-        let check_component = server.check_component_service.clone();
+        let mut check_component = (*server.check_component_service).clone();
 
         // Run thread verify
         let task_job = tokio::spawn(async move {
@@ -94,7 +153,9 @@ async fn main() {
                 let component = receiver.recv().await;
 
                 if let Some(component) = component {
+                    METRICS.add_queue_depth("verify", -1);
                     info!("Verify component:{:?}", component);
+                    check_component.refresh_flow_config();
                     let res = check_component.get_report_component(&component).await;
                     match res {
                         Ok((check_mk_report, wrk_report)) => {
@@ -113,15 +174,16 @@ async fn main() {
                                 ReportType::Benchmark,
                             );
                             // Send report to verify
-                            let res = store_report.send_data(SendPurpose::Verify).await;
-                            match res {
-                                Ok(res) => {
-                                    info!("Send verify res: {:?}", res.text().await);
-                                }
-                                Err(err) => {
-                                    info!("Send verify error: {}", err);
-                                }
-                            }
+                            let event = ReportEvent {
+                                purpose: SendPurpose::Verify,
+                                component: &component,
+                                check_mk_report: Some(&check_mk_report),
+                                report: &store_report,
+                            };
+                            check_component
+                                .report_sinks
+                                .send(check_component.http_client.pooled(), &event)
+                                .await;
                         }
                         Err(err) => {}
                     }
@@ -145,6 +207,209 @@ async fn main() {
         join(task_job, task_serve).await;
     }
 }
+fn validate_flow_file(check_flow_file: &str, base_endpoint_file: Option<&str>) -> bool {
+    let path = check_flow_file.to_string();
+    let json = match std::fs::read_to_string(&path) {
+        Ok(json) => json,
+        Err(err) => {
+            eprintln!("Unable to read `{}`: {}", path, err);
+            return false;
+        }
+    };
+    let mut errors = Vec::new();
+    match parse_check_flows(&json) {
+        Ok(check_flows) => {
+            if let Some(base_endpoint_file) = base_endpoint_file {
+                let base_json = match std::fs::read_to_string(base_endpoint_file) {
+                    Ok(base_json) => base_json,
+                    Err(err) => {
+                        eprintln!("Unable to read `{}`: {}", base_endpoint_file, err);
+                        return false;
+                    }
+                };
+                match parse_json(&base_json) {
+                    Ok(base_nodes) => {
+                        errors.extend(validate_base_endpoints(&json, &check_flows, &base_nodes))
+                    }
+                    Err(err) => {
+                        eprintln!(
+                            "{}",
+                            GeneratorBuilder::format_flow_errors(
+                                &base_endpoint_file.to_string(),
+                                &vec![err]
+                            )
+                        );
+                        return false;
+                    }
+                }
+            }
+        }
+        Err(flow_errors) => errors.extend(flow_errors),
+    }
+    if errors.is_empty() {
+        println!("{}: ok", path);
+        true
+    } else {
+        eprintln!("{}", GeneratorBuilder::format_flow_errors(&path, &errors));
+        false
+    }
+}
+
+// Unix time in milliseconds or RFC 3339, e.g. `2022-06-01T00:00:00Z`
+fn parse_time_ms(value: &str) -> Result<u64, String> {
+    value.parse::<u64>().or_else(|_| {
+        chrono::DateTime::parse_from_rfc3339(value)
+            .map(|time| time.timestamp_millis() as u64)
+            .map_err(|err| format!("Invalid time `{}`: {}", value, err))
+    })
+}
+
+fn format_time_ms(time_ms: u64) -> String {
+    chrono::DateTime::<chrono::Utc>::from(UNIX_EPOCH + Duration::from_millis(time_ms)).to_rfc3339()
+}
+
+fn print_history(matches: &ArgMatches) -> bool {
+    let history = match matches.value_of("database") {
+        Some(path) => CheckHistory::new(PathBuf::from(path)),
+        None => CheckHistory::default(),
+    };
+    let component_id = matches.value_of("component-id");
+    if matches.is_present("failing") {
+        return match history.failing_components(component_id) {
+            Ok(components) => {
+                for component in components {
+                    println!(
+                        "{} {} {} {}: failing since {}, {} failed runs, last at {}",
+                        component.component_id,
+                        component.component_type,
+                        component.blockchain,
+                        component.network,
+                        format_time_ms(component.failing_since_ms),
+                        component.failed_runs,
+                        format_time_ms(component.last_failed_ms)
+                    );
+                }
+                true
+            }
+            Err(err) => {
+                eprintln!("Cannot read check history: {}", err);
+                false
+            }
+        };
+    }
+    let mut query = HistoryQuery {
+        component_id: component_id.map(str::to_string),
+        failed_only: matches.is_present("failed"),
+        limit: matches.value_of_t("limit").unwrap_or(100),
+        ..Default::default()
+    };
+    for (arg, time_ms) in [
+        ("since", &mut query.since_ms),
+        ("until", &mut query.until_ms),
+    ] {
+        if let Some(value) = matches.value_of(arg) {
+            match parse_time_ms(value) {
+                Ok(value) => *time_ms = Some(value),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return false;
+                }
+            }
+        }
+    }
+    match history.runs(&query) {
+        Ok(runs) => {
+            // One json run per line, newest first
+            for run in runs {
+                match serde_json::to_string(&run) {
+                    Ok(run) => println!("{}", run),
+                    Err(err) => eprintln!("Cannot print check run: {}", err),
+                }
+            }
+            true
+        }
+        Err(err) => {
+            eprintln!("Cannot read check history: {}", err);
+            false
+        }
+    }
+}
+
+fn create_history() -> App<'static> {
+    App::new("history")
+        .about("print check runs of the check history")
+        .arg(
+            Arg::new("database")
+                .short('d')
+                .long("database")
+                .value_name("database")
+                .help("SQLite database of the history, check_history_path of the config by default")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("component-id")
+                .short('c')
+                .long("component-id")
+                .value_name("component-id")
+                .help("Runs of the component only")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("since")
+                .long("since")
+                .value_name("since")
+                .help("Runs from this time: unix time in ms or RFC 3339")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("until")
+                .long("until")
+                .value_name("until")
+                .help("Runs before this time: unix time in ms or RFC 3339")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("failed")
+                .long("failed")
+                .help("Runs of components which were not ok only"),
+        )
+        .arg(
+            Arg::new("limit")
+                .short('l')
+                .long("limit")
+                .value_name("limit")
+                .help("Newest runs printed, 0 prints all, 100 by default")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("failing")
+                .long("failing")
+                .help("Components failing since their last ok run and when they started failing"),
+        )
+}
+
+fn create_validate_flow() -> App<'static> {
+    App::new("validate-flow")
+        .about("validate check-flow file")
+        .arg(
+            Arg::new("check-flow")
+                .short('c')
+                .long("check-flow")
+                .value_name("check-flow")
+                .help("Input check-flow file")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("base-endpoint")
+                .short('b')
+                .long("base-endpoint")
+                .value_name("base-endpoint")
+                .help("Input base-endpoint file, check that flows have base endpoints")
+                .takes_value(true),
+        )
+}
+
 fn create_check_component() -> App<'static> {
     App::new("check-kind")
         .about("check node kind is correct")
@@ -227,4 +492,12 @@ fn create_check_component() -> App<'static> {
                 .help("domain name")
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("checkmk")
+                .long("checkmk")
+                .value_name("checkmk")
+                .help("Report all components as checkmk local checks: stdout, spool (to the output file) or http")
+                .possible_values(&["stdout", "spool", "http"])
+                .takes_value(true),
+        )
 }
@@ -1,5 +1,9 @@
 use crate::check_module::check_module::{CheckComponent, ComponentInfo};
+use crate::check_module::checkmk_output::CheckMkOutput;
+use crate::check_module::flow_reloader::FlowReloader;
+use crate::check_module::prometheus_metrics::{create_metrics, METRICS};
 use crate::server_config::AccessControl;
+use crate::PORTAL_AUTHORIZATION;
 use std::collections::VecDeque;
 
 use log::{debug, info};
@@ -20,11 +24,15 @@ pub const MAX_JSON_BODY_SIZE: u64 = 1024 * 1024;
 #[derive(Default)]
 pub struct ServerBuilder {
     entry_point: String,
+    flow_reloader: Option<Arc<FlowReloader>>,
+    checkmk_output: Option<CheckMkOutput>,
 }
 
 pub struct CheckComponentServer {
     entry_point: String,
     pub check_component_service: Arc<CheckComponent>,
+    flow_reloader: Option<Arc<FlowReloader>>,
+    checkmk_output: Option<CheckMkOutput>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -37,6 +45,14 @@ pub struct SimpleResponse {
     success: bool,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ReloadResponse {
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u64>,
+    message: String,
+}
+
 impl CheckComponentServer {
     pub fn builder() -> ServerBuilder {
         ServerBuilder::default()
@@ -62,6 +78,12 @@ impl CheckComponentServer {
             .await
             .with(&cors)
             .or(self.create_ping().with(&cors))
+            .or(self.create_reload().with(&cors))
+            .or(self
+                .create_base_endpoints(self.check_component_service.clone())
+                .with(&cors))
+            .or(self.create_checkmk().with(&cors))
+            .or(create_metrics().with(&cors))
             .recover(handle_rejection);
         let socket_addr: SocketAddr = self.entry_point.parse().unwrap();
 
@@ -84,7 +106,12 @@ impl CheckComponentServer {
                 let component_info: ComponentInfo = serde_json::from_value(body).unwrap();
                 let sender_another_clone = sender_clone.clone();
                 async move {
+                    // Counted before sending, the job may take it at once
+                    METRICS.add_queue_depth("verify", 1);
                     let res = sender_another_clone.send(component_info).await;
+                    if res.is_err() {
+                        METRICS.add_queue_depth("verify", -1);
+                    }
                     Self::simple_response(true).await
                 }
             })
@@ -101,6 +128,123 @@ impl CheckComponentServer {
                 Self::simple_response(true).await
             })
     }
+    /// Reload check flows and base endpoints, needs the portal authorization
+    fn create_reload(
+        &self,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        let flow_reloader = self.flow_reloader.clone();
+        warp::path!("reload")
+            .and(warp::post())
+            .and(warp::header::optional::<String>("authorization"))
+            .and_then(move |authorization: Option<String>| {
+                let flow_reloader = flow_reloader.clone();
+                async move {
+                    info!("Receive reload request");
+                    let (status, res) =
+                        if authorization.as_deref() != Some(PORTAL_AUTHORIZATION.as_str()) {
+                            (
+                                StatusCode::UNAUTHORIZED,
+                                ReloadResponse {
+                                    success: false,
+                                    version: None,
+                                    message: "Unauthorized".to_string(),
+                                },
+                            )
+                        } else {
+                            let reloaded = match &flow_reloader {
+                                Some(reloader) => Some(reloader.reload().await),
+                                None => None,
+                            };
+                            match reloaded {
+                                Some(Ok(version)) => (
+                                    StatusCode::OK,
+                                    ReloadResponse {
+                                        success: true,
+                                        version: Some(version),
+                                        message: "Reloaded".to_string(),
+                                    },
+                                ),
+                                Some(Err(err)) => (
+                                    StatusCode::BAD_REQUEST,
+                                    ReloadResponse {
+                                        success: false,
+                                        version: None,
+                                        message: err.to_string(),
+                                    },
+                                ),
+                                None => (
+                                    StatusCode::NOT_FOUND,
+                                    ReloadResponse {
+                                        success: false,
+                                        version: None,
+                                        message: "Reload is not enabled".to_string(),
+                                    },
+                                ),
+                            }
+                        };
+                    Ok::<_, Rejection>(warp::reply::with_status(warp::reply::json(&res), status))
+                }
+            })
+    }
+
+    /// State of base endpoints: latency, failures and whether they are disabled,
+    /// needs the portal authorization because urls may contain api keys
+    fn create_base_endpoints(
+        &self,
+        service: Arc<CheckComponent>,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("base_endpoints")
+            .and(warp::get())
+            .and(warp::header::optional::<String>("authorization"))
+            .and_then(move |authorization: Option<String>| {
+                let service = service.clone();
+                async move {
+                    if authorization.as_deref() != Some(PORTAL_AUTHORIZATION.as_str()) {
+                        return Ok::<_, Rejection>(warp::reply::with_status(
+                            warp::reply::json(&SimpleResponse { success: false }),
+                            StatusCode::UNAUTHORIZED,
+                        ));
+                    }
+                    Ok(warp::reply::with_status(
+                        warp::reply::json(&service.base_endpoint_state()),
+                        StatusCode::OK,
+                    ))
+                }
+            })
+    }
+
+    /// Latest checkmk local checks of `check-kind --checkmk http`, needs the portal authorization
+    fn create_checkmk(
+        &self,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        let checkmk_output = self.checkmk_output.clone();
+        warp::path!("checkmk")
+            .and(warp::get())
+            .and(warp::header::optional::<String>("authorization"))
+            .and_then(move |authorization: Option<String>| {
+                let checkmk_output = checkmk_output.clone();
+                async move {
+                    let (status, res) =
+                        if authorization.as_deref() != Some(PORTAL_AUTHORIZATION.as_str()) {
+                            (StatusCode::UNAUTHORIZED, "Unauthorized".to_string())
+                        } else {
+                            match checkmk_output.as_ref().map(|output| output.get()) {
+                                Some(Some(local_checks)) => (StatusCode::OK, local_checks),
+                                Some(None) => (
+                                    StatusCode::SERVICE_UNAVAILABLE,
+                                    "First check is not finished".to_string(),
+                                ),
+                                None => (
+                                    StatusCode::NOT_FOUND,
+                                    "Checkmk output is not enabled".to_string(),
+                                ),
+                            }
+                        };
+                    Ok::<_, Rejection>(warp::reply::with_status(res, status))
+                }
+            })
+    }
+
     pub(crate) async fn simple_response(success: bool) -> Result<impl Reply, Rejection> {
         let res = SimpleResponse { success };
         Ok(warp::reply::json(&res))
@@ -128,10 +272,22 @@ impl ServerBuilder {
         self
     }
 
+    pub fn with_flow_reloader(mut self, flow_reloader: Arc<FlowReloader>) -> Self {
+        self.flow_reloader = Some(flow_reloader);
+        self
+    }
+
+    pub fn with_checkmk_output(mut self, checkmk_output: CheckMkOutput) -> Self {
+        self.checkmk_output = Some(checkmk_output);
+        self
+    }
+
     pub fn build(&self, check_component: CheckComponent) -> CheckComponentServer {
         CheckComponentServer {
             entry_point: self.entry_point.clone(),
             check_component_service: Arc::new(check_component),
+            flow_reloader: self.flow_reloader.clone(),
+            checkmk_output: self.checkmk_output.clone(),
         }
     }
 }
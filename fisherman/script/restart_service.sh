@@ -5,6 +5,7 @@ sudo mv ~/config_check_component.json /opt/fisherman/
 sudo mv ~/config_fisherman.json /opt/fisherman/
 sudo mv ~/run.sh /opt/fisherman/
 sudo mv ~/massbit.lua /opt/fisherman/
+sudo mv ~/dapi.lua /opt/fisherman/
 sudo mv ~/wrk /opt/fisherman/
 sudo mv ~/.env /opt/fisherman/
 
@@ -5,6 +5,7 @@ use futures::{stream, StreamExt};
 use futures_util::TryStreamExt;
 use log::{debug, info};
 use mbr_check_component::check_module::check_module::ComponentInfo;
+use mbr_check_component::check_module::prometheus_metrics::METRICS;
 use reqwest::Client;
 use std::collections::HashMap;
 use std::default::Default;
@@ -72,6 +73,15 @@ impl CheckPingPong for FishermanService {
                 })
                 .await;
         }
+        {
+            // Components without successful ping are not in the result
+            let result = result.read().await;
+            for component in list_providers_clone.iter() {
+                let sum_success = result.get(component).cloned().unwrap_or_default();
+                let success_rate = sum_success / (CONFIG.ping_sample_number as f32);
+                METRICS.record_ping(component, success_rate.min(1f32));
+            }
+        }
         let result = result
             .read()
             .await
@@ -1,9 +1,10 @@
 use crate::{CONFIG, ZONE};
 use anyhow::Error;
-use log::{debug, info};
+use log::{debug, info, warn};
 use mbr_check_component::check_module::check_module::{
     CheckComponent, CheckMkReport, ComponentInfo, ComponentType, WrkReport,
 };
+use mbr_check_component::check_module::report_sink::ReportEvent;
 use mbr_check_component::check_module::store_report::{
     ReportType, ReporterRole, SendPurpose, StoreReport,
 };
@@ -157,7 +158,7 @@ impl FishermanService {
         self.check_component_service
             .reload_components_list(Some(&CONFIG.checking_component_status), &ZONE)
             .await;
-        // List node and gateway
+        // List node and gateway, dapi has no ping endpoint
         let mut list_providers = self.check_component_service.list_nodes.clone();
         list_providers.extend(self.check_component_service.list_gateways.clone());
         list_providers
@@ -170,6 +171,10 @@ impl FishermanService {
         let mut count = 0;
         for reports in reports_history.iter() {
             if let Some(report) = reports.get(component) {
+                // A loop without reference answers neither breaks nor extends the fails
+                if !report.is_judged() {
+                    continue;
+                }
                 if !report.is_healthy(&component.component_type) {
                     count += 1;
                 } else {
@@ -183,7 +188,7 @@ impl FishermanService {
         match component.component_type {
             ComponentType::Node => count >= CONFIG.node_response_failed_number,
             ComponentType::Gateway => count >= CONFIG.gateway_response_failed_number,
-            ComponentType::DApi => false,
+            ComponentType::DApi => count >= CONFIG.dapi_response_failed_number,
         }
     }
     // pub async fn check_ping_pong(&mut self, list_providers: Arc<RwLock<Vec<ComponentInfo>>>) {
@@ -209,11 +214,16 @@ impl FishermanService {
         info!("number_of_sample:{}", number_of_sample);
         let sample_interval_ms = self.sample_interval_ms;
         info!("sample_interval_ms:{}", sample_interval_ms);
+        // Averaged reports of the last loops, only to count continuous fails. Every sample of a
+        // loop is already kept in the check history by `get_report_component`, an average is
+        // not a check run, so after a restart the fails are counted again from the first loop.
         let mut reports_history: VecDeque<HashMap<ComponentInfo, ComponentReport>> =
             VecDeque::new();
 
         info!("Run check component");
         loop {
+            // Checks of the previous loop are done, use reloaded check flows
+            self.check_component_service.refresh_flow_config();
             // Load new nodes/gateways list
             if let Err(e) = self
                 .check_component_service
@@ -232,6 +242,10 @@ impl FishermanService {
                 "Reload list gateway: {:?}",
                 self.check_component_service.list_gateways
             );
+            info!(
+                "Reload list dapi: {:?}",
+                self.check_component_service.list_dapis
+            );
 
             let mut average_reports: HashMap<ComponentInfo, ComponentReport> = HashMap::new();
             let mut collect_reports: HashMap<ComponentInfo, Vec<CheckMkReport>> = HashMap::new();
@@ -268,20 +282,45 @@ impl FishermanService {
 
             // Display report for debug
             for (component, report) in average_reports.iter() {
-                info!("id: {}, type: {:?}, chain {:?}, request_number: {}, success_number: {}, response_time_ms:{:?}ms, healthy: {}",
+                info!("id: {}, type: {:?}, chain {:?}, request_number: {}, success_number: {}, response_time_ms:{:?}ms, reference_failures: {}, healthy: {}",
                     component.id,
                     component.component_type,
                     component.blockchain,
                     report.request_number,
                     report.success_number,
                     report.response_time_ms,
+                    report.reference_failures,
                     report.is_healthy(&component.component_type)
                 );
             }
+            // Reference problems are reported once per base endpoint
+            for (chain, endpoints) in self.check_component_service.base_endpoint_state() {
+                for endpoint in endpoints
+                    .iter()
+                    .filter(|endpoint| endpoint.disabled || endpoint.broken)
+                {
+                    warn!(
+                        "Base endpoint {} of {}: disabled for {}ms, broken: {}, success {:?}%, last error: {:?}",
+                        endpoint.url,
+                        chain,
+                        endpoint.disabled_for_ms,
+                        endpoint.broken,
+                        endpoint.success_percent,
+                        endpoint.stats.last_error
+                    );
+                }
+            }
 
             if !self.is_no_report {
                 // Check and send report
                 for (component_info, report) in average_reports.iter() {
+                    if !report.is_judged() {
+                        info!(
+                            "Skip report of {}, reference endpoints of {} {} are unavailable",
+                            component_info.id, component_info.blockchain, component_info.network
+                        );
+                        continue;
+                    }
                     let reason = if !report.is_healthy(&component_info.component_type) {
                         Some(ProviderReportReason::BadPerformance(
                             report.request_number,
@@ -299,7 +338,7 @@ impl FishermanService {
                                 &component_info,
                             )
                         {
-                            // Store report to portal db
+                            // Send report to report sinks, e.g. portal db
                             let mut store_report = StoreReport::build(
                                 &*LOCAL_IP,
                                 ReporterRole::Fisherman,
@@ -308,9 +347,17 @@ impl FishermanService {
                             );
                             store_report
                                 .set_report_type(&component_info, ReportType::ReportProvider);
-                            let res = store_report.send_data(SendPurpose::Store).await;
-                            info!("Store report: {:?}", res.unwrap().text().await);
-                            // End Store report to portal db
+                            let event = ReportEvent {
+                                purpose: SendPurpose::Store,
+                                component: &component_info,
+                                check_mk_report: None,
+                                report: &store_report,
+                            };
+                            self.check_component_service
+                                .report_sinks
+                                .send(self.check_component_service.http_client.pooled(), &event)
+                                .await;
+                            // End send report
 
                             info!("Submit report: {:?}", component_info);
                             let provider_id: [u8; 36] =
@@ -334,7 +381,11 @@ impl FishermanService {
                                                 |component| *component.id != component_info.id,
                                             );
                                         }
-                                        _ => {}
+                                        ComponentType::DApi => {
+                                            self.check_component_service.list_dapis.retain(
+                                                |component| *component.id != component_info.id,
+                                            );
+                                        }
                                     }
                                     info!(
                                         "list_nodes:{:?}",
@@ -344,6 +395,10 @@ impl FishermanService {
                                         "list_gateways:{:?}",
                                         self.check_component_service.list_gateways
                                     );
+                                    info!(
+                                        "list_dapis:{:?}",
+                                        self.check_component_service.list_dapis
+                                    );
                                     Ok(())
                                 })
                             {
@@ -354,7 +409,7 @@ impl FishermanService {
                 }
             }
 
-            // Store to history
+            // Keep for the fails of the next loops
             reports_history.push_back(average_reports);
             while reports_history.len() > CONFIG.reports_history_queue_length_max {
                 reports_history.pop_front();
@@ -370,14 +425,21 @@ pub struct ComponentReport {
     request_number: u64,
     success_number: u64,
     response_time_ms: Option<u32>,
+    // Samples without answer of the base endpoints, they are not counted as requests
+    reference_failures: u64,
 }
 
 impl ComponentReport {
+    /// False if no sample could be compared against the base endpoints.
+    pub fn is_judged(&self) -> bool {
+        self.request_number > 0
+    }
+
     pub fn is_healthy(&self, component_type: &ComponentType) -> bool {
         let response_threshold = match component_type {
             ComponentType::Node => CONFIG.node_response_time_threshold,
             ComponentType::Gateway => CONFIG.gateway_response_time_threshold,
-            _ => u32::default(),
+            ComponentType::DApi => CONFIG.dapi_response_time_threshold,
         };
         // If there is not enough info return false
         if self.success_number == 0 || self.response_time_ms == None {
@@ -401,10 +463,15 @@ impl ComponentReport {
 
 impl From<&Vec<CheckMkReport>> for ComponentReport {
     fn from(reports: &Vec<CheckMkReport>) -> Self {
-        let check_number = CONFIG.number_of_samples;
+        let reference_failures = reports
+            .iter()
+            .filter(|report| report.reference_failure)
+            .count() as u64;
+        let check_number = CONFIG.number_of_samples.saturating_sub(reference_failures);
         let mut success_number = 0;
         let response_times = reports
             .iter()
+            .filter(|report| !report.reference_failure)
             .filter_map(|report| {
                 if report.is_component_status_ok() {
                     success_number += 1;
@@ -428,6 +495,7 @@ impl From<&Vec<CheckMkReport>> for ComponentReport {
             request_number: check_number,
             success_number,
             response_time_ms,
+            reference_failures,
         }
     }
 }
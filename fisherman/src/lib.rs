@@ -5,6 +5,7 @@ use std::env;
 use std::str::FromStr;
 pub mod check_ping_pong_service;
 pub mod fisherman_service;
+pub mod report_signer;
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
@@ -15,8 +16,10 @@ pub struct Config {
     pub success_percent_threshold: u32,
     pub node_response_time_threshold: u32,
     pub gateway_response_time_threshold: u32,
+    pub dapi_response_time_threshold: u32,
     pub node_response_failed_number: i32,
     pub gateway_response_failed_number: i32,
+    pub dapi_response_failed_number: i32,
     pub reports_history_queue_length_max: usize,
     pub check_task_list_fisherman: Vec<String>,
     pub checking_component_status: String,
@@ -37,6 +40,10 @@ pub struct Config {
     pub check_logic_interval: u64,
     pub check_benchmark_interval: u64,
     pub update_provider_list_interval: u64,
+    // Run the check cycles of `loop_check_component` next to the ping pong check. The cycles
+    // benchmark every provider and submit reports of failing providers to the portal and the chain
+    #[serde(default)]
+    pub check_component_loop: bool,
 }
 const CONFIG_FILE: &str = "config_fisherman.json";
 lazy_static! {
@@ -1,19 +1,23 @@
 use anyhow::Error;
 use clap::{Arg, Command};
 use dotenv;
-use log::{debug, info};
+use log::{debug, info, warn};
 use logger;
 use logger::core::init_logger;
 use mbr_check_component::check_module::check_module::{CheckComponent, ComponentInfo};
+use mbr_check_component::check_module::flow_reloader::FlowReloader;
+use mbr_check_component::check_module::prometheus_metrics::create_metrics;
 use mbr_check_component::SIGNER_PHRASE;
 use mbr_fisherman::check_ping_pong_service::CheckPingPong;
 use mbr_fisherman::fisherman_service::{
     FishermanService, ProviderReportReason, SubmitProviderReport,
 };
+use mbr_fisherman::report_signer::Sr25519Signer;
 use mbr_fisherman::FISHERMAN_ENDPOINT;
 use mbr_fisherman::{CONFIG, ZONE};
 use mbr_stats::chain_adapter::Projects;
 use std::convert::TryInto;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::RwLock;
@@ -68,10 +72,29 @@ async fn main() {
             .await
             .with_domain(domain.to_string())
             .with_check_flow_file(check_flow_file.to_string())
+            .await
             .with_base_endpoint_file(base_endpoint_file.to_string())
+            .await
+            .with_report_signer(Arc::new(
+                Sr25519Signer::from_phrase(SIGNER_PHRASE.as_str()).expect("Invalid SIGNER_PHRASE"),
+            ))
             .build();
         log::debug!("check_component: {:?}", check_component);
+        // Send reports kept in the outbox by a previous run
+        check_component
+            .report_sinks
+            .start(check_component.http_client.pooled());
+        // Reload check flows and base endpoints when they change
+        let flow_reloader = FlowReloader::new(&mut check_component);
+        task::spawn(flow_reloader.watch());
         let socket_addr = FISHERMAN_ENDPOINT.as_str();
+        // Prometheus metrics of checks and pings
+        match socket_addr.parse::<SocketAddr>() {
+            Ok(metrics_addr) => {
+                task::spawn(warp::serve(create_metrics()).run(metrics_addr));
+            }
+            Err(err) => warn!("Cannot serve metrics at {}: {}", socket_addr, err),
+        }
 
         let mut fisherman_service_org = FishermanService::builder()
             .with_number_of_sample(CONFIG.number_of_samples)
@@ -87,8 +110,11 @@ async fn main() {
         let list_providers_org = Arc::new(RwLock::new(
             fisherman_service_org.get_provider_list_from_portal().await,
         ));
-        // Check component
-        //fisherman_service.loop_check_component().await;
+        // Check component cycles feed the metrics and the check history, and report failing
+        // providers to the portal and the chain
+        if CONFIG.check_component_loop {
+            task::spawn(fisherman_service_org.clone().loop_check_component());
+        }
         let mut fisherman_service = fisherman_service_org.clone();
         let list_providers = list_providers_org.clone();
 
@@ -0,0 +1,163 @@
+use anyhow::Error;
+use mbr_check_component::check_module::report_signature::{
+    ReplayGuard, ReportSignature, ReportSigner, SignatureError,
+};
+use serde_json::Value;
+use sp_core::sr25519::{Pair, Public, Signature};
+use sp_core::Pair as PairTrait;
+use std::convert::TryInto;
+use std::fmt;
+
+pub const SR25519: &str = "sr25519";
+
+/// Signs reports of the fisherman with the sr25519 key of `SIGNER_PHRASE`.
+pub struct Sr25519Signer {
+    pair: Pair,
+}
+
+impl fmt::Debug for Sr25519Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Sr25519Signer({:?})", self.pair.public())
+    }
+}
+
+impl Sr25519Signer {
+    pub fn from_phrase(signer_phrase: &str) -> Result<Self, Error> {
+        let (pair, _) = Pair::from_string_with_seed(signer_phrase, None)
+            .map_err(|err| Error::msg(format!("Invalid signer phrase: {:?}", err)))?;
+        Ok(Sr25519Signer { pair })
+    }
+}
+
+impl ReportSigner for Sr25519Signer {
+    fn scheme(&self) -> String {
+        SR25519.to_string()
+    }
+
+    fn public_key(&self) -> Vec<u8> {
+        self.pair.public().as_ref().to_vec()
+    }
+
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.pair.sign(message).as_ref().to_vec()
+    }
+}
+
+/// Whether `signature` is the sr25519 signature of `message` by `public_key`.
+pub fn verify_sr25519(scheme: &str, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let public_key: [u8; 32] = match public_key.try_into() {
+        Ok(public_key) => public_key,
+        Err(_) => return false,
+    };
+    let signature: [u8; 64] = match signature.try_into() {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    scheme == SR25519
+        && Pair::verify(
+            &Signature::from_raw(signature),
+            message,
+            &Public::from_raw(public_key),
+        )
+}
+
+/// Signature of a report body signed by a fisherman, if it is valid and not replayed.
+/// The public key of the signature identifies the fisherman.
+pub fn verify_signed_report(
+    body: &Value,
+    replay_guard: &ReplayGuard,
+) -> Result<ReportSignature, SignatureError> {
+    replay_guard.verify_report(body, verify_sr25519)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mbr_check_component::check_module::report_signature::{sign_report, SIGNATURE_KEY};
+    use serde_json::json;
+    use std::time::Duration;
+
+    // Development phrase of substrate, not a key of a real account
+    const DEV_PHRASE: &str =
+        "bottom drive obey lake curtain smoke basket hold race lonely fit walk";
+
+    fn dev_signer(phrase: &str) -> Sr25519Signer {
+        Sr25519Signer::from_phrase(phrase).unwrap()
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        let digits: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("0x{}", digits)
+    }
+
+    fn guard() -> ReplayGuard {
+        ReplayGuard::new(Duration::from_secs(60))
+    }
+
+    #[test]
+    fn sr25519_signed_report_round_trip() {
+        let signer = dev_signer(DEV_PHRASE);
+        let body = json!({"provider_id": "provider", "is_data_correct": false});
+        let signed = sign_report(&body, &signer, 1660000000000).unwrap();
+        let signature = verify_signed_report(&signed, &guard()).unwrap();
+        assert_eq!(signature.scheme, SR25519);
+        assert_eq!(signature.public_key, hex(&signer.public_key()));
+        assert_eq!(signature.created_ms, Some(1660000000000));
+
+        let mut tampered = signed.clone();
+        tampered["is_data_correct"] = json!(true);
+        assert_eq!(
+            verify_signed_report(&tampered, &guard()),
+            Err(SignatureError::Invalid)
+        );
+        // Signature of another key
+        let other = dev_signer(&format!("{}//Bob", DEV_PHRASE));
+        let mut wrong_key = signed;
+        wrong_key[SIGNATURE_KEY]["public_key"] = json!(hex(&other.public_key()));
+        assert_eq!(
+            verify_signed_report(&wrong_key, &guard()),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn verify_sr25519_rejects_wrong_lengths_and_schemes() {
+        let signer = dev_signer(DEV_PHRASE);
+        let public_key = signer.public_key();
+        let signature = signer.sign(b"message");
+        assert_eq!(public_key.len(), 32);
+        assert_eq!(signature.len(), 64);
+        assert!(verify_sr25519(SR25519, &public_key, b"message", &signature));
+        assert!(!verify_sr25519(SR25519, &public_key, b"other", &signature));
+        assert!(!verify_sr25519(
+            "ed25519",
+            &public_key,
+            b"message",
+            &signature
+        ));
+        assert!(!verify_sr25519(
+            SR25519,
+            &public_key[..31],
+            b"message",
+            &signature
+        ));
+        assert!(!verify_sr25519(
+            SR25519,
+            &[public_key.clone(), vec![0]].concat(),
+            b"message",
+            &signature
+        ));
+        assert!(!verify_sr25519(
+            SR25519,
+            &public_key,
+            b"message",
+            &signature[..63]
+        ));
+        assert!(!verify_sr25519(SR25519, &public_key, b"message", &[]));
+    }
+
+    #[test]
+    fn invalid_phrase_is_an_error() {
+        assert!(Sr25519Signer::from_phrase("not a valid phrase").is_err());
+    }
+}